@@ -0,0 +1,129 @@
+//! `dfsadmin`, cluster management through the admin api of the naming server
+
+use tiny_dfs::{
+    client::{AdminClient, DfsError},
+    common::{addr, admin::ServerInfo},
+};
+
+const USAGE: &str = "Usage: cargo run dfsadmin [registration_addr] <command> [args...]
+
+Commands:
+    servers                         list the storage servers
+    file <path>                     show the replicas of every chunk of a file
+    replicate <path>                bring the files under a path up to their replication now
+    decommission <host:client_port> move every replica away from a storage server";
+
+/// `host:port`, with IPv6 hosts in brackets
+fn with_port(host: &str, port: u16) -> String {
+    match addr::Host::parse(host) {
+        Ok(host) => host.with_port(port),
+        Err(_) => format!("{}:{}", host, port),
+    }
+}
+
+fn print_servers(servers: &[ServerInfo]) {
+    println!(
+        "{:<24} {:>7} {:<15} {:>6} {:>7} {:>14} {:>14} {:>14} {:>8}",
+        "SERVER",
+        "COMMAND",
+        "STATE",
+        "FILES",
+        "CHUNKS",
+        "USED",
+        "CAPACITY",
+        "AVAILABLE",
+        "REQUESTS"
+    );
+    for srv in servers {
+        let state = match (srv.alive, srv.decommissioned) {
+            (false, _) => "dead".to_string(),
+            (true, false) => "alive".to_string(),
+            (true, true) if srv.chunks > 0 => "draining".to_string(),
+            (true, true) => "decommissioned".to_string(),
+        };
+        println!(
+            "{:<24} {:>7} {:<15} {:>6} {:>7} {:>14} {:>14} {:>14} {:>8}",
+            with_port(&srv.storage_ip, srv.client_port),
+            srv.command_port,
+            state,
+            srv.files,
+            srv.chunks,
+            srv.used_bytes,
+            srv.capacity_bytes,
+            srv.available_bytes,
+            srv.outstanding_requests
+        );
+    }
+}
+
+async fn run(client: &AdminClient, cmd: &str, args: &[String]) -> Result<(), DfsError> {
+    match (cmd, args) {
+        ("servers", []) => print_servers(&client.servers().await?),
+        ("file", [path]) => {
+            let file = client.file(path).await?;
+            println!(
+                "{}: {} chunks of {} bytes, replication {}",
+                path,
+                file.chunks.len(),
+                file.chunk_size,
+                file.replication
+            );
+            for chunk in file.chunks {
+                let replicas: Vec<String> = chunk
+                    .replicas
+                    .iter()
+                    .map(|srv| {
+                        let state = match (srv.alive, srv.decommissioned, srv.suspect) {
+                            (false, _, _) => " (dead)",
+                            (true, true, _) => " (decommissioned)",
+                            (true, false, true) => " (suspect)",
+                            (true, false, false) => "",
+                        };
+                        format!("{}{}", with_port(&srv.storage_ip, srv.client_port), state)
+                    })
+                    .collect();
+                println!(
+                    "chunk {} at {}: {}",
+                    chunk.chunk,
+                    chunk.offset,
+                    replicas.join(", ")
+                );
+            }
+        }
+        ("replicate", [path]) => {
+            let added = client.replicate(path).await?;
+            println!("{} replicas added", added);
+        }
+        ("decommission", [addr]) => {
+            let Ok(ip) = addr::Ip::parse(addr) else {
+                eprintln!("{}", USAGE);
+                std::process::exit(1);
+            };
+            let chunks = client.decommission(ip.host().as_str(), ip.port()).await?;
+            println!("{} decommissioned, {} replicas left to move", addr, chunks);
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// args[2] (optional): registration address of the naming server, localhost:22222 by default;
+/// then the command and its args
+pub async fn start_dfsadmin(args: &[String]) {
+    let (addr, rest) = match args.get(2) {
+        // Commands are plain words, addresses have a port
+        Some(addr) if addr.contains(':') => (addr.as_str(), &args[3..]),
+        _ => ("localhost:22222", &args[2.min(args.len())..]),
+    };
+    let Some((cmd, cmd_args)) = rest.split_first() else {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    };
+    if let Err(err) = run(&AdminClient::new(addr), cmd, cmd_args).await {
+        eprintln!("dfsadmin {}: {}", cmd, err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,417 @@
+//! Command-line client of tiny-dfs.
+//!
+//! Usage: dfs [-n naming_addr] <command> [args...]
+//!
+//! The naming server is `localhost:11111` unless given by `-n` or
+//! `TINY_DFS_NAMING`.
+
+use std::process::ExitCode;
+
+use rocket::{
+    time::OffsetDateTime,
+    tokio::{
+        self,
+        fs::File,
+        io::{self, AsyncWriteExt},
+    },
+};
+use tiny_dfs::{
+    client::{DfsClient, DfsError},
+    common::service::{FileType, FindArg},
+};
+
+const DEFAULT_NAMING: &str = "localhost:11111";
+
+const USAGE: &str = "Usage: dfs [-n naming_addr] <command> [args...]
+
+Commands:
+    ls [path]               list a dir, dirs end with /
+    tree [path]             list a dir recursively
+    stat <path>             show the type, size, times and owner of a file
+    mkdir [-p] <path>       create a dir, with its parents if -p
+    put <local> <remote>    upload a local file, - for stdin
+    get <remote> [local]    download a file, to stdout if local is -
+    cat <path>...           print files
+    rm [-r] <path>          delete a file, or a dir if -r
+    mv <src> <dst>          move a file or dir
+    du [path]               show bytes used under every dir
+    find [path] [-name <glob>] [-type f|d] [-min-size <n>] [-max-size <n>]
+                            list the files under a dir passing the filters";
+
+/// Errors of a command, printed as is
+enum CmdError {
+    Usage,
+    Dfs(DfsError),
+    Msg(String),
+}
+
+impl From<DfsError> for CmdError {
+    fn from(err: DfsError) -> Self {
+        CmdError::Dfs(err)
+    }
+}
+
+impl From<io::Error> for CmdError {
+    fn from(err: io::Error) -> Self {
+        CmdError::Msg(err.to_string())
+    }
+}
+
+type CmdResult = Result<(), CmdError>;
+
+/// `path` relative to `/` if not absolute, without trailing slashes
+fn remote_path(path: &str) -> String {
+    let path = path.trim_end_matches('/');
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+fn base_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Split `-x` style flags off the args
+fn split_flags<'a>(
+    args: &'a [String],
+    known: &[&str],
+) -> Result<(Vec<&'a str>, Vec<&'a str>), CmdError> {
+    let (flags, rest): (Vec<&str>, Vec<&str>) = args
+        .iter()
+        .map(String::as_str)
+        .partition(|arg| arg.starts_with('-') && arg.len() > 1);
+    if let Some(flag) = flags.iter().find(|flag| !known.contains(flag)) {
+        return Err(CmdError::Msg(format!("unknown flag {}", flag)));
+    }
+    Ok((flags, rest))
+}
+
+/// The one optional path of `ls`, `tree` and `du`
+fn opt_path(args: &[String]) -> Result<String, CmdError> {
+    match args {
+        [] => Ok("/".to_string()),
+        [path] => Ok(remote_path(path)),
+        _ => Err(CmdError::Usage),
+    }
+}
+
+async fn ls(client: &DfsClient, args: &[String]) -> CmdResult {
+    let path = opt_path(args)?;
+    if !client.stat(&path).await?.is_dir {
+        println!("{}", path);
+        return Ok(());
+    }
+    for entry in client.list_entries(&path).await? {
+        println!("{}{}", entry.name, if entry.is_dir { "/" } else { "" });
+    }
+    Ok(())
+}
+
+async fn tree(client: &DfsClient, args: &[String]) -> CmdResult {
+    let path = opt_path(args)?;
+    println!("{}", path);
+    // Depth first, (path, is_dir, depth) of the entries left to print
+    let mut stack = vec![(path, true, 0)];
+    while let Some((path, is_dir, depth)) = stack.pop() {
+        if depth > 0 {
+            let suffix = if is_dir { "/" } else { "" };
+            println!("{}{}{}", "    ".repeat(depth - 1), base_name(&path), suffix);
+        }
+        if is_dir {
+            for entry in client.list_entries(&path).await?.into_iter().rev() {
+                stack.push((join(&path, &entry.name), entry.is_dir, depth + 1));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn stat(client: &DfsClient, args: &[String]) -> CmdResult {
+    let [path] = args else {
+        return Err(CmdError::Usage);
+    };
+    let path = remote_path(path);
+    let stat = client.stat(&path).await?;
+    if stat.is_dir {
+        println!("{}: directory", path);
+    } else {
+        println!(
+            "{}: regular file, {} bytes, {} replicas",
+            path, stat.size, stat.replicas
+        );
+    }
+    println!("  owner:    {}", stat.owner.as_deref().unwrap_or("-"));
+    println!("  created:  {}", format_time(stat.created));
+    println!("  modified: {}", format_time(stat.modified));
+    println!("  accessed: {}", format_time(stat.accessed));
+    Ok(())
+}
+
+/// `millis` since the Unix epoch as a UTC date and time
+fn format_time(millis: u64) -> String {
+    let Ok(time) = OffsetDateTime::from_unix_timestamp_nanos(millis as i128 * 1_000_000) else {
+        return format!("{} ms", millis);
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+async fn mkdir(client: &DfsClient, args: &[String]) -> CmdResult {
+    let (flags, rest) = split_flags(args, &["-p"])?;
+    let [path] = rest[..] else {
+        return Err(CmdError::Usage);
+    };
+    let path = remote_path(path);
+    if flags.is_empty() {
+        client.mkdir(&path).await?;
+        return Ok(());
+    }
+    let mut prefix = String::new();
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        prefix = join(&prefix, name);
+        match client.stat(&prefix).await {
+            Ok(stat) if stat.is_dir => {}
+            Ok(_) => return Err(CmdError::Msg(format!("{} is a regular file", prefix))),
+            Err(DfsError::NotFound(_)) => client.mkdir(&prefix).await?,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+async fn put(client: &DfsClient, args: &[String]) -> CmdResult {
+    let [local, remote] = args else {
+        return Err(CmdError::Usage);
+    };
+    let mut remote = remote_path(remote);
+    // Into an existing dir, keep the local name
+    if let Ok(stat) = client.stat(&remote).await {
+        if !stat.is_dir || local == "-" {
+            return Err(CmdError::Msg(format!("{} already exists", remote)));
+        }
+        remote = join(&remote, base_name(local));
+    }
+    client.create(&remote).await?;
+    let mut file = client.open(&remote).await?;
+    if local == "-" {
+        io::copy(&mut io::stdin(), &mut file).await?;
+    } else {
+        io::copy(&mut File::open(local).await?, &mut file).await?;
+    }
+    file.shutdown().await?;
+    Ok(())
+}
+
+async fn get(client: &DfsClient, args: &[String]) -> CmdResult {
+    let (remote, local) = match args {
+        [remote] => (remote, None),
+        [remote, local] => (remote, Some(local.as_str())),
+        _ => return Err(CmdError::Usage),
+    };
+    let remote = remote_path(remote);
+    let mut file = client.open(&remote).await?;
+    match local {
+        Some("-") => {
+            let mut stdout = io::stdout();
+            io::copy(&mut file, &mut stdout).await?;
+            stdout.flush().await?;
+        }
+        local => {
+            let mut local = local.unwrap_or(".").to_string();
+            if tokio::fs::metadata(&local)
+                .await
+                .is_ok_and(|meta| meta.is_dir())
+            {
+                local = format!("{}/{}", local.trim_end_matches('/'), base_name(&remote));
+            }
+            let mut out = File::create(&local).await?;
+            io::copy(&mut file, &mut out).await?;
+            out.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+async fn cat(client: &DfsClient, args: &[String]) -> CmdResult {
+    if args.is_empty() {
+        return Err(CmdError::Usage);
+    }
+    let mut stdout = io::stdout();
+    for path in args {
+        let mut file = client.open(&remote_path(path)).await?;
+        io::copy(&mut file, &mut stdout).await?;
+    }
+    stdout.flush().await?;
+    Ok(())
+}
+
+async fn rm(client: &DfsClient, args: &[String]) -> CmdResult {
+    let (flags, rest) = split_flags(args, &["-r"])?;
+    let [path] = rest[..] else {
+        return Err(CmdError::Usage);
+    };
+    let path = remote_path(path);
+    if flags.is_empty() && client.stat(&path).await?.is_dir {
+        return Err(CmdError::Msg(format!("{} is a directory, use -r", path)));
+    }
+    client.delete(&path).await?;
+    Ok(())
+}
+
+async fn mv(client: &DfsClient, args: &[String]) -> CmdResult {
+    let [src, dst] = args else {
+        return Err(CmdError::Usage);
+    };
+    let src = remote_path(src);
+    let mut dst = remote_path(dst);
+    // Into an existing dir, keep the name
+    if client.stat(&dst).await.is_ok_and(|stat| stat.is_dir) {
+        dst = join(&dst, base_name(&src));
+    }
+    client.rename(&src, &dst).await?;
+    Ok(())
+}
+
+async fn du(client: &DfsClient, args: &[String]) -> CmdResult {
+    let path = opt_path(args)?;
+    let stat = client.stat(&path).await?;
+    if !stat.is_dir {
+        println!("{}\t{}", stat.size, path);
+        return Ok(());
+    }
+    // Post order, every dir is printed after its subdirs
+    let mut stack = vec![(path, false)];
+    let mut sizes: Vec<u64> = Vec::new();
+    while let Some((dir, visited)) = stack.pop() {
+        if visited {
+            let size = sizes.pop().unwrap();
+            println!("{}\t{}", size, dir);
+            if let Some(parent) = sizes.last_mut() {
+                *parent += size;
+            }
+            continue;
+        }
+        stack.push((dir.clone(), true));
+        let mut size = 0;
+        for entry in client.list_entries(&dir).await?.into_iter().rev() {
+            if entry.is_dir {
+                stack.push((join(&dir, &entry.name), false));
+            } else {
+                size += entry.size;
+            }
+        }
+        sizes.push(size);
+    }
+    Ok(())
+}
+
+async fn find(client: &DfsClient, args: &[String]) -> CmdResult {
+    let mut arg = FindArg::default();
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        if !flag.starts_with('-') {
+            if path.replace(remote_path(flag)).is_some() {
+                return Err(CmdError::Usage);
+            }
+            continue;
+        }
+        let value = args.next().ok_or(CmdError::Usage)?;
+        let size = || {
+            value
+                .parse()
+                .map_err(|_| CmdError::Msg(format!("invalid size {}", value)))
+        };
+        match flag.as_str() {
+            "-name" => arg.pattern = Some(value.clone()),
+            "-type" => {
+                arg.file_type = Some(match value.as_str() {
+                    "f" => FileType::File,
+                    "d" => FileType::Dir,
+                    _ => return Err(CmdError::Msg(format!("invalid type {}", value))),
+                })
+            }
+            "-min-size" => arg.min_size = Some(size()?),
+            "-max-size" => arg.max_size = Some(size()?),
+            _ => return Err(CmdError::Msg(format!("unknown flag {}", flag))),
+        }
+    }
+    arg.path = path.unwrap_or_else(|| "/".to_string());
+    let mut results = client.find(&arg).await?;
+    while let Some(entry) = results.next().await {
+        let entry = entry?;
+        let is_dir = entry.file_type == FileType::Dir;
+        let suffix = if is_dir && entry.path != "/" { "/" } else { "" };
+        println!("{}{}", entry.path, suffix);
+    }
+    Ok(())
+}
+
+#[rocket::main]
+async fn main() -> ExitCode {
+    env_logger::init();
+
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut naming =
+        std::env::var("TINY_DFS_NAMING").unwrap_or_else(|_| DEFAULT_NAMING.to_string());
+    if args.first().is_some_and(|arg| arg == "-n") {
+        if args.len() < 2 {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+        naming = args.remove(1);
+        args.remove(0);
+    }
+    let Some((cmd, args)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    let client = DfsClient::new(&naming);
+    let res = match cmd.as_str() {
+        "ls" => ls(&client, args).await,
+        "tree" => tree(&client, args).await,
+        "stat" => stat(&client, args).await,
+        "mkdir" => mkdir(&client, args).await,
+        "put" => put(&client, args).await,
+        "get" => get(&client, args).await,
+        "cat" => cat(&client, args).await,
+        "rm" => rm(&client, args).await,
+        "mv" => mv(&client, args).await,
+        "du" => du(&client, args).await,
+        "find" => find(&client, args).await,
+        "-h" | "--help" | "help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(CmdError::Usage),
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CmdError::Usage) => {
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+        Err(CmdError::Dfs(err)) => {
+            eprintln!("dfs {}: {}", cmd, err);
+            ExitCode::FAILURE
+        }
+        Err(CmdError::Msg(msg)) => {
+            eprintln!("dfs {}: {}", cmd, msg);
+            ExitCode::FAILURE
+        }
+    }
+}
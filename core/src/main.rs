@@ -1,38 +1,73 @@
-async fn start_naming_server(args: &Vec<String>) {
-    if args.len() != 4 {
-        panic!();
-    }
+mod admin;
 
-    tiny_dfs::start_naming_server(args).await;
-}
+use std::process::ExitCode;
 
-async fn start_storage_server(args: &Vec<String>) {
-    if args.len() != 6 {
-        panic!();
-    }
+use tiny_dfs::config::{NamingConfig, StorageConfig};
 
-    tiny_dfs::start_storage_server(args).await;
-}
+const USAGE: &str = "Usage: cargo run <naming|storage> [--config file.toml] [--flag value]...
+       cargo run dfsadmin [registration_addr] <command> [args...]
 
-#[rocket::main]
-async fn main() {
-    env_logger::init();
+Flags of naming:
+    --bind-address, --service-port, --registration-port, --meta-dir,
+    --replication-threshold, --replication-factor, --placement, --chunk-size,
+    --log-level
+Flags of storage:
+    --bind-address, --advertised-host, --client-port, --command-port,
+    --naming-address, --data-dir, --scrub-period, --log-level
 
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        log::error!("Invalid arg. Usage: cargo run [server_type(naming/storage)] ...");
-        panic!();
-    }
+Every flag can be set in the TOML file as well, with _ instead of -, or by an
+environment variable such as TINY_DFS_NAMING_SERVICE_PORT or
+TINY_DFS_STORAGE_DATA_DIR. Flags win over the environment, which wins over
+the file.";
 
-    let server_type: &str = &args[1];
-    if server_type == "naming" {
-        start_naming_server(&args).await;
-    } else if server_type == "storage" {
-        start_storage_server(&args).await;
-    } else {
-        log::error!("Unknown server type");
-        panic!();
+fn init_logger(log_level: &Option<String>) {
+    match log_level {
+        Some(filters) => env_logger::Builder::new().parse_filters(filters).init(),
+        None => env_logger::init(),
     }
 }
 
+#[rocket::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(server_type) = args.get(1) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+    let flags = &args[2..];
+    let wants_help = |flags: &[String]| flags.iter().any(|flag| flag == "-h" || flag == "--help");
 
+    match server_type.as_str() {
+        "naming" | "storage" if wants_help(flags) => println!("{}", USAGE),
+        "naming" => match NamingConfig::load(flags) {
+            Ok(config) => {
+                init_logger(&config.log_level);
+                tiny_dfs::start_naming_server(&config).await;
+            }
+            Err(err) => {
+                eprintln!("naming: invalid config, {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        "storage" => match StorageConfig::load(flags) {
+            Ok(config) => {
+                init_logger(&config.log_level);
+                tiny_dfs::start_storage_server(&config).await;
+            }
+            Err(err) => {
+                eprintln!("storage: invalid config, {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        "dfsadmin" => {
+            env_logger::init();
+            admin::start_dfsadmin(&args).await;
+        }
+        "-h" | "--help" => println!("{}", USAGE),
+        _ => {
+            eprintln!("unknown server type {:?}\n\n{}", server_type, USAGE);
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,124 @@
+use std::{
+    fs,
+    process::{Command, Output},
+    thread,
+    time::Duration,
+};
+
+use rocket::tokio::{self, net::TcpStream, time::sleep};
+use tiny_dfs::config::{NamingConfig, StorageConfig};
+
+const SERVICE_PORT: u16 = 11171;
+const REGISTRATION_PORT: u16 = 22282;
+const CLIENT_PORT: u16 = 33390;
+
+/// Start a naming server and a storage server with empty dirs, on a runtime
+/// of their own
+async fn start_cluster() {
+    let naming = NamingConfig {
+        service_port: SERVICE_PORT,
+        registration_port: REGISTRATION_PORT,
+        meta_dir: "/tmp/tiny-dfs-naming-cli".into(),
+        chunk_size: 16,
+        ..Default::default()
+    };
+    let storage = StorageConfig {
+        client_port: CLIENT_PORT,
+        command_port: CLIENT_PORT + 11111,
+        naming_address: format!("localhost:{}", REGISTRATION_PORT),
+        data_dir: "/tmp/tiny-dfs-cli".into(),
+        ..Default::default()
+    };
+    let _ = fs::remove_dir_all(&naming.meta_dir);
+    let _ = fs::remove_dir_all(&storage.data_dir);
+    fs::create_dir_all(&storage.data_dir).unwrap();
+    thread::spawn(move || {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                tokio::spawn(async move { tiny_dfs::start_naming_server(&naming).await });
+                wait_for_port(REGISTRATION_PORT).await;
+                tiny_dfs::start_storage_server(&storage).await;
+            });
+    });
+    wait_for_port(SERVICE_PORT).await;
+    wait_for_port(CLIENT_PORT).await;
+}
+
+async fn wait_for_port(port: u16) {
+    for _ in 0..100 {
+        if TcpStream::connect(("localhost", port)).await.is_ok() {
+            return;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    panic!("nothing listens on port {}", port);
+}
+
+/// Run the dfs binary against the cluster
+fn dfs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dfs"))
+        .args(["-n", &format!("localhost:{}", SERVICE_PORT)])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Stdout of a successful run
+fn dfs_ok(args: &[&str]) -> String {
+    let output = dfs(args);
+    assert!(
+        output.status.success(),
+        "dfs {:?}: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_dfs() {
+    start_cluster().await;
+    let local_dir = "/tmp/tiny-dfs-cli-local";
+    let _ = fs::remove_dir_all(local_dir);
+    fs::create_dir_all(local_dir).unwrap();
+    let data: Vec<u8> = (0..50).collect();
+    let local = format!("{}/data", local_dir);
+    fs::write(&local, &data).unwrap();
+
+    // Parents created, existing ones left alone
+    dfs_ok(&["mkdir", "-p", "/a/b/c"]);
+    dfs_ok(&["mkdir", "-p", "/a/b"]);
+    assert_eq!(dfs_ok(&["ls", "/a"]), "b/\n");
+    assert_eq!(dfs_ok(&["ls", "/a/b"]), "c/\n");
+    assert!(!dfs(&["mkdir", "/x/y"]).status.success());
+
+    // Into an existing dir, under the local name
+    dfs_ok(&["put", &local, "/a/b"]);
+    assert_eq!(dfs_ok(&["ls", "/a/b"]), "c/\ndata\n");
+    assert!(!dfs(&["put", &local, "/a/b/data"]).status.success());
+    dfs_ok(&["put", &local, "/a/b/c/copy"]);
+    let got_dir = format!("{}/got", local_dir);
+    fs::create_dir_all(&got_dir).unwrap();
+    dfs_ok(&["get", "/a/b/data", &got_dir]);
+    assert_eq!(fs::read(format!("{}/data", got_dir)).unwrap(), data);
+    assert_eq!(dfs_ok(&["cat", "/a/b/c/copy"]).into_bytes(), data);
+
+    // Every dir after its subdirs, with the bytes below it
+    assert_eq!(dfs_ok(&["du", "/a"]), "50\t/a/b/c\n100\t/a/b\n100\t/a\n");
+    assert_eq!(dfs_ok(&["du", "/a/b/data"]), "50\t/a/b/data\n");
+    let stat = dfs_ok(&["stat", "/a/b/data"]);
+    assert!(stat.starts_with("/a/b/data: regular file, 50 bytes"));
+    assert!(stat.contains("  modified: 20") && stat.contains(" UTC\n"));
+
+    // A dir is removed with -r only
+    let output = dfs(&["rm", "/a/b"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("use -r"));
+    assert_eq!(dfs_ok(&["ls", "/a"]), "b/\n");
+    dfs_ok(&["rm", "/a/b/data"]);
+    dfs_ok(&["rm", "-r", "/a/b"]);
+    assert_eq!(dfs_ok(&["ls", "/a"]), "");
+}
@@ -0,0 +1,62 @@
+use crate::common::admin::{
+    AdminServersOkResponse, DecommissionArg, DecommissionOkResponse, FileReplicasOkResponse,
+    ReplicateArg, ReplicateOkResponse, ServerInfo,
+};
+
+use super::{parse, with_scheme, DfsError};
+
+/// Client of the admin api of the naming server
+#[derive(Clone)]
+pub struct AdminClient {
+    http: reqwest::Client,
+    addr: String,
+}
+
+impl AdminClient {
+    /// A client of the naming server serving its registration api at
+    /// `registration_addr`, e.g. `localhost:22222`
+    pub fn new(registration_addr: &str) -> Self {
+        AdminClient {
+            http: reqwest::Client::new(),
+            addr: format!("{}/admin", with_scheme(registration_addr)),
+        }
+    }
+
+    pub async fn servers(&self) -> Result<Vec<ServerInfo>, DfsError> {
+        let addr = format!("{}/servers", self.addr);
+        let resp: AdminServersOkResponse = parse(self.http.get(addr).send().await?).await?;
+        Ok(resp.servers)
+    }
+
+    /// The replicas of every chunk of the regular file at `path`
+    pub async fn file(&self, path: &str) -> Result<FileReplicasOkResponse, DfsError> {
+        let addr = format!("{}/file", self.addr);
+        let resp = self.http.get(addr).query(&[("path", path)]).send().await?;
+        parse(resp).await
+    }
+
+    /// Bring the files under `path` up to their replication now. Return the
+    /// replicas added.
+    pub async fn replicate(&self, path: &str) -> Result<usize, DfsError> {
+        let arg = ReplicateArg {
+            path: path.to_string(),
+        };
+        let addr = format!("{}/replicate", self.addr);
+        let resp: ReplicateOkResponse =
+            parse(self.http.post(addr).json(&arg).send().await?).await?;
+        Ok(resp.added)
+    }
+
+    /// Move every replica away from the server whose client api listens at
+    /// `ip:client_port`. Return the replicas left to move.
+    pub async fn decommission(&self, ip: &str, client_port: u16) -> Result<usize, DfsError> {
+        let arg = DecommissionArg {
+            storage_ip: ip.to_string(),
+            client_port,
+        };
+        let addr = format!("{}/decommission", self.addr);
+        let resp: DecommissionOkResponse =
+            parse(self.http.post(addr).json(&arg).send().await?).await?;
+        Ok(resp.chunks)
+    }
+}
@@ -0,0 +1,91 @@
+use std::{fmt, io};
+
+use crate::common::ErrResponse;
+
+/// Errors of the client, one per exception type of the servers
+#[derive(Debug)]
+pub enum DfsError {
+    /// FileNotFoundException
+    NotFound(String),
+    /// IllegalArgumentException
+    IllegalArgument(String),
+    /// IllegalStateException
+    IllegalState(String),
+    /// IndexOutOfBoundsException
+    IndexOutOfBounds(String),
+    /// IOException
+    Io(String),
+    /// ChecksumException, another replica may be intact
+    Checksum(String),
+    /// An exception this client doesn't know
+    Other {
+        exception_type: String,
+        exception_info: String,
+    },
+    /// The request didn't make it, or the response made no sense
+    Transport(reqwest::Error),
+}
+
+impl From<ErrResponse> for DfsError {
+    fn from(resp: ErrResponse) -> Self {
+        let info = resp.exception_info;
+        match resp.exception_type.as_str() {
+            "FileNotFoundException" => DfsError::NotFound(info),
+            "IllegalArgumentException" => DfsError::IllegalArgument(info),
+            "IllegalStateException" => DfsError::IllegalState(info),
+            "IndexOutOfBoundsException" => DfsError::IndexOutOfBounds(info),
+            "IOException" => DfsError::Io(info),
+            "ChecksumException" => DfsError::Checksum(info),
+            _ => DfsError::Other {
+                exception_type: resp.exception_type,
+                exception_info: info,
+            },
+        }
+    }
+}
+
+impl From<reqwest::Error> for DfsError {
+    fn from(err: reqwest::Error) -> Self {
+        DfsError::Transport(err)
+    }
+}
+
+impl fmt::Display for DfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DfsError::NotFound(info) => write!(f, "FileNotFoundException: {}", info),
+            DfsError::IllegalArgument(info) => write!(f, "IllegalArgumentException: {}", info),
+            DfsError::IllegalState(info) => write!(f, "IllegalStateException: {}", info),
+            DfsError::IndexOutOfBounds(info) => write!(f, "IndexOutOfBoundsException: {}", info),
+            DfsError::Io(info) => write!(f, "IOException: {}", info),
+            DfsError::Checksum(info) => write!(f, "ChecksumException: {}", info),
+            DfsError::Other {
+                exception_type,
+                exception_info,
+            } => write!(f, "{}: {}", exception_type, exception_info),
+            DfsError::Transport(err) => write!(f, "transport error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DfsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DfsError::Transport(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<DfsError> for io::Error {
+    fn from(err: DfsError) -> Self {
+        let kind = match err {
+            DfsError::NotFound(_) => io::ErrorKind::NotFound,
+            DfsError::IllegalArgument(_) => io::ErrorKind::InvalidInput,
+            DfsError::IndexOutOfBounds(_) => io::ErrorKind::UnexpectedEof,
+            DfsError::Checksum(_) => io::ErrorKind::InvalidData,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    future::Future,
+    io::{self, SeekFrom},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use rocket::tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use super::{DfsClient, DfsError};
+
+/// Bytes fetched by a single read at least, the rest is kept for later reads
+const READ_AHEAD: u64 = 1 << 20;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = Result<T, DfsError>> + Send>>;
+
+/// The request in flight, at most one at a time
+enum Pending {
+    Idle,
+    Read(BoxFuture<Vec<u8>>),
+    Write(BoxFuture<usize>),
+}
+
+/// A regular file opened by `DfsClient::open`.
+///
+/// Every read and write goes to the storage servers right away, so flushing
+/// only finishes a write left in flight. The size is the one at `open`, grown by the writes through
+/// this handle.
+pub struct DfsFile {
+    client: DfsClient,
+    path: String,
+    pos: u64,
+    size: u64,
+    /// Bytes right after `pos` fetched ahead of the reader
+    buffered: Vec<u8>,
+    pending: Pending,
+}
+
+impl DfsFile {
+    pub(super) fn new(client: DfsClient, path: &str, size: u64) -> Self {
+        DfsFile {
+            client,
+            path: path.to_string(),
+            pos: 0,
+            size,
+            buffered: Vec::new(),
+            pending: Pending::Idle,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Poll the write in flight, moving past the bytes written once done
+    fn poll_pending_write(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let Pending::Write(fut) = &mut self.pending else {
+            unreachable!()
+        };
+        let res = ready!(fut.as_mut().poll(cx));
+        self.pending = Pending::Idle;
+        let n = res?;
+        self.pos += n as u64;
+        self.size = self.size.max(self.pos);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncRead for DfsFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if matches!(this.pending, Pending::Write(_)) {
+            ready!(this.poll_pending_write(cx))?;
+        }
+        if this.buffered.is_empty() {
+            if !matches!(this.pending, Pending::Read(_)) {
+                if this.pos >= this.size || buf.remaining() == 0 {
+                    return Poll::Ready(Ok(()));
+                }
+                let client = this.client.clone();
+                let path = this.path.clone();
+                let offset = this.pos;
+                let length = (buf.remaining() as u64)
+                    .max(READ_AHEAD)
+                    .min(this.size - offset);
+                this.pending = Pending::Read(Box::pin(async move {
+                    client.read_at(&path, offset, length).await
+                }));
+            }
+            let Pending::Read(fut) = &mut this.pending else {
+                unreachable!()
+            };
+            let data = match fut.as_mut().poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(res) => {
+                    this.pending = Pending::Idle;
+                    res?
+                }
+            };
+            if data.is_empty() {
+                // The file has shrunk behind our back
+                return Poll::Ready(Ok(()));
+            }
+            this.buffered = data;
+        }
+        let n = this.buffered.len().min(buf.remaining());
+        buf.put_slice(&this.buffered[..n]);
+        this.buffered.drain(..n);
+        this.pos += n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for DfsFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !matches!(this.pending, Pending::Write(_)) {
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            // Whatever was read ahead may be overwritten
+            this.buffered.clear();
+            let client = this.client.clone();
+            let path = this.path.clone();
+            let offset = this.pos;
+            let data = buf.to_vec();
+            this.pending = Pending::Write(Box::pin(async move {
+                client.write_at(&path, offset, &data).await
+            }));
+        }
+        this.poll_pending_write(cx)
+    }
+
+    /// Finish the write in flight, left behind by a caller that gave up on it
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if matches!(this.pending, Pending::Write(_)) {
+            ready!(this.poll_pending_write(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl AsyncSeek for DfsFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        if matches!(this.pending, Pending::Write(_)) {
+            // Its bytes would land at the old position after the seek
+            return Err(io::Error::other("a write is in flight"));
+        }
+        let pos = match position {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => this.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => this.pos.checked_add_signed(delta),
+        };
+        let pos = pos
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        if pos != this.pos {
+            this.buffered.clear();
+            this.pos = pos;
+        }
+        // A read ahead of the old position is of no use
+        this.pending = Pending::Idle;
+        Ok(())
+    }
+
+    /// Also called before `start_seek` by `AsyncSeekExt::seek`, which thus
+    /// waits for the write in flight
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        if matches!(this.pending, Pending::Write(_)) {
+            ready!(this.poll_pending_write(cx))?;
+        }
+        Poll::Ready(Ok(this.pos))
+    }
+}
@@ -0,0 +1,52 @@
+use rocket::serde::json;
+
+use crate::common::service::FindEntry;
+
+use super::DfsError;
+
+/// Matches of `DfsClient::find`, read off the response as they arrive
+pub struct FindResults {
+    resp: reqwest::Response,
+    /// Bytes received past the last complete line
+    buf: Vec<u8>,
+}
+
+impl FindResults {
+    pub(super) fn new(resp: reqwest::Response) -> Self {
+        FindResults {
+            resp,
+            buf: Vec::new(),
+        }
+    }
+
+    /// The next match, `None` once the walk is done
+    pub async fn next(&mut self) -> Option<Result<FindEntry, DfsError>> {
+        loop {
+            if let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..=end).collect();
+                return Some(
+                    json::from_slice(&line[..end])
+                        .map_err(|err| DfsError::Io(format!("malformed find result: {}", err))),
+                );
+            }
+            match self.resp.chunk().await {
+                Ok(Some(bytes)) => self.buf.extend_from_slice(&bytes),
+                Ok(None) if self.buf.is_empty() => return None,
+                Ok(None) => {
+                    self.buf.clear();
+                    return Some(Err(DfsError::Io("truncated find result".to_string())));
+                }
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+
+    /// All the remaining matches
+    pub async fn collect(mut self) -> Result<Vec<FindEntry>, DfsError> {
+        let mut entries = Vec::new();
+        while let Some(entry) = self.next().await {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,514 @@
+//! Client of tiny-dfs.
+//!
+//! `DfsClient` talks to the naming server for the namespace and straight to
+//! the storage servers for the data. The locations of the chunks are cached
+//! per path for reads and refreshed when a replica fails. Writes lock the file
+//! exclusively, which drops its extra replicas, and look the remaining ones
+//! up again under the lock.
+
+mod admin;
+mod error;
+mod file;
+mod find;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use rocket::{
+    futures::future::join_all,
+    serde::{de::DeserializeOwned, Serialize},
+};
+
+use crate::common::{
+    service::{
+        ChunkLocation, CreateDirectoryArg, CreateFileArg, DeleteArg, FileType, FindArg,
+        GetStorageArg, GetStorageOkResponse, ListArg, ListOkResponse, LockArg, LockOkResponse,
+        RenameArg, StatArg, StatOkResponse, StorageAddr, UnlockArg,
+    },
+    storage::{AppendArg, AppendOkResponse, ChunkId, StreamWriteOkResponse, TruncateArg},
+    wire::FileData,
+    ErrResponse, OkResponse,
+};
+
+pub use admin::AdminClient;
+pub use error::DfsError;
+pub use file::DfsFile;
+pub use find::FindResults;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    pub is_dir: bool,
+    /// Bytes of a regular file, 0 for a dir
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+    /// Fewest alive replicas of a chunk of a regular file, 0 for a dir
+    pub replicas: usize,
+    pub owner: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// Bytes of a regular file, 0 for a dir
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub modified: u64,
+}
+
+/// Entries asked for at once when listing a whole dir
+const LIST_PAGE: usize = 1000;
+
+/// Known chunks of a file
+struct Locations {
+    chunk_size: u64,
+    /// Index of the chunk in the file => its location
+    chunks: BTreeMap<u64, ChunkLocation>,
+}
+
+struct Inner {
+    http: reqwest::Client,
+    naming_addr: String,
+    /// Owner of the files created
+    owner: Option<String>,
+    locations: Mutex<HashMap<String, Locations>>,
+}
+
+#[derive(Clone)]
+pub struct DfsClient {
+    inner: Arc<Inner>,
+}
+
+fn storage_addr(srv: &StorageAddr) -> String {
+    format!("http://{}", srv.server_ip.with_port(srv.server_port))
+}
+
+/// `addr` with `http://` in front if it has no scheme
+fn with_scheme(addr: &str) -> String {
+    if addr.contains("://") {
+        addr.to_string()
+    } else {
+        format!("http://{}", addr)
+    }
+}
+
+async fn parse<R: DeserializeOwned>(resp: reqwest::Response) -> Result<R, DfsError> {
+    if resp.status().is_success() {
+        Ok(resp.json().await?)
+    } else {
+        Err(resp.json::<ErrResponse>().await?.into())
+    }
+}
+
+impl DfsClient {
+    /// A client of the naming server serving its service api at `naming_addr`,
+    /// e.g. `localhost:11111`. Files are created on behalf of `$USER`.
+    pub fn new(naming_addr: &str) -> Self {
+        DfsClient {
+            inner: Arc::new(Inner {
+                http: reqwest::Client::new(),
+                naming_addr: with_scheme(naming_addr),
+                owner: std::env::var("USER").ok(),
+                locations: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    async fn call<A: Serialize, R: DeserializeOwned>(
+        &self,
+        route: &str,
+        arg: &A,
+    ) -> Result<R, DfsError> {
+        let addr = format!("{}/{}", self.inner.naming_addr, route);
+        parse(self.inner.http.post(addr).json(arg).send().await?).await
+    }
+
+    pub async fn create(&self, path: &str) -> Result<(), DfsError> {
+        let arg = CreateFileArg {
+            path: path.to_string(),
+            replication: None,
+            owner: self.inner.owner.clone(),
+        };
+        self.call::<_, OkResponse>("create_file", &arg).await?;
+        Ok(())
+    }
+
+    pub async fn mkdir(&self, path: &str) -> Result<(), DfsError> {
+        let arg = CreateDirectoryArg {
+            path: path.to_string(),
+            replication: None,
+            owner: self.inner.owner.clone(),
+        };
+        self.call::<_, OkResponse>("create_directory", &arg).await?;
+        Ok(())
+    }
+
+    /// Names of the entries of the dir at `path`, in order
+    pub async fn list(&self, path: &str) -> Result<Vec<String>, DfsError> {
+        let entries = self.list_entries(path).await?;
+        Ok(entries.into_iter().map(|entry| entry.name).collect())
+    }
+
+    /// Entries of the dir at `path`, in name order, fetched a page at a time
+    pub async fn list_entries(&self, path: &str) -> Result<Vec<DirEntry>, DfsError> {
+        let mut entries = Vec::new();
+        let mut start_after = None;
+        loop {
+            let (page, next) = self
+                .list_page(path, start_after.as_deref(), LIST_PAGE)
+                .await?;
+            entries.extend(page);
+            match next {
+                Some(next) => start_after = Some(next),
+                None => return Ok(entries),
+            }
+        }
+    }
+
+    /// Up to `limit` entries of the dir at `path` named after `start_after`,
+    /// in name order, along with the `start_after` of the next page if any
+    pub async fn list_page(
+        &self,
+        path: &str,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<DirEntry>, Option<String>), DfsError> {
+        let arg = ListArg {
+            path: path.to_string(),
+            start_after: start_after.map(str::to_string),
+            limit: Some(limit),
+            detailed: true,
+        };
+        let resp: ListOkResponse = self.call("list", &arg).await?;
+        let entries = resp
+            .entries
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| DirEntry {
+                name: entry.name,
+                is_dir: entry.file_type == FileType::Dir,
+                size: entry.size,
+                modified: entry.modified,
+            })
+            .collect();
+        Ok((entries, resp.continuation_token))
+    }
+
+    /// Files under `arg.path` passing the filters of `arg`, the walk
+    /// included, streamed by the naming server as it walks the tree
+    pub async fn find(&self, arg: &FindArg) -> Result<FindResults, DfsError> {
+        let addr = format!("{}/find", self.inner.naming_addr);
+        let resp = self.inner.http.post(addr).json(arg).send().await?;
+        if resp.status().is_success() {
+            Ok(FindResults::new(resp))
+        } else {
+            Err(resp.json::<ErrResponse>().await?.into())
+        }
+    }
+
+    pub async fn stat(&self, path: &str) -> Result<FileStat, DfsError> {
+        let arg = StatArg {
+            path: path.to_string(),
+        };
+        let resp: StatOkResponse = self.call("stat", &arg).await?;
+        Ok(FileStat {
+            is_dir: resp.file_type == FileType::Dir,
+            size: resp.size,
+            created: resp.created,
+            modified: resp.modified,
+            accessed: resp.accessed,
+            replicas: resp.replicas,
+            owner: resp.owner,
+        })
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<(), DfsError> {
+        let arg = DeleteArg {
+            path: path.to_string(),
+        };
+        self.call::<_, OkResponse>("delete", &arg).await?;
+        self.invalidate_tree(path);
+        Ok(())
+    }
+
+    /// Move the file or dir at `src` to `dst`, whose parent must exist
+    pub async fn rename(&self, src: &str, dst: &str) -> Result<(), DfsError> {
+        let arg = RenameArg {
+            src_path: src.to_string(),
+            dst_path: dst.to_string(),
+        };
+        self.call::<_, OkResponse>("rename", &arg).await?;
+        self.invalidate_tree(src);
+        Ok(())
+    }
+
+    /// Open the regular file at `path` for both reading and writing
+    pub async fn open(&self, path: &str) -> Result<DfsFile, DfsError> {
+        let stat = self.stat(path).await?;
+        if stat.is_dir {
+            return Err(DfsError::IllegalArgument(format!("{} is a dir", path)));
+        }
+        Ok(DfsFile::new(self.clone(), path, stat.size))
+    }
+
+    /// Post to a storage server holding the first chunk of `path`, trying the
+    /// next replica while one is unreachable
+    async fn call_storage<A: Serialize, R: DeserializeOwned>(
+        &self,
+        path: &str,
+        route: &str,
+        arg: &A,
+    ) -> Result<R, DfsError> {
+        let (_, chunk) = self.locate(path, 0, true).await?;
+        let mut res = Err(DfsError::Io(format!("{} has no replica", path)));
+        for srv in &chunk.servers {
+            let addr = format!("{}/{}", storage_addr(srv), route);
+            res = match self.inner.http.post(addr).json(arg).send().await {
+                Ok(resp) => return parse(resp).await,
+                Err(err) => Err(err.into()),
+            };
+        }
+        self.invalidate(path);
+        res
+    }
+
+    /// Cut the regular file at `path` down to `length` bytes
+    pub async fn truncate(&self, path: &str, length: u64) -> Result<(), DfsError> {
+        let arg = TruncateArg {
+            path: path.to_string(),
+            length,
+        };
+        let res = self
+            .call_storage::<_, OkResponse>(path, "storage_truncate", &arg)
+            .await;
+        self.invalidate(path);
+        res.map(|_| ())
+    }
+
+    /// Write `data` at the end of the regular file at `path`, returning the
+    /// offset it landed at
+    pub async fn append(&self, path: &str, data: &[u8]) -> Result<u64, DfsError> {
+        let arg = AppendArg {
+            path: path.to_string(),
+            data: FileData(data.to_vec()),
+        };
+        let res = self
+            .call_storage::<_, AppendOkResponse>(path, "storage_append", &arg)
+            .await;
+        self.invalidate(path);
+        Ok(res?.offset)
+    }
+
+    /// Ask the naming server for the chunks of `path` in a byte range and
+    /// cache them
+    async fn fetch_locations(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+        write: bool,
+    ) -> Result<(u64, Vec<ChunkLocation>), DfsError> {
+        let arg = GetStorageArg {
+            path: path.to_string(),
+            offset,
+            length,
+            write,
+        };
+        let resp: GetStorageOkResponse = self.call("getstorage", &arg).await?;
+        let mut locations = self.inner.locations.lock().unwrap();
+        let cached = locations.entry(path.to_string()).or_insert(Locations {
+            chunk_size: resp.chunk_size,
+            chunks: BTreeMap::new(),
+        });
+        cached.chunk_size = resp.chunk_size;
+        for chunk in &resp.chunks {
+            cached
+                .chunks
+                .insert(chunk.offset / resp.chunk_size, chunk.clone());
+        }
+        Ok((resp.chunk_size, resp.chunks))
+    }
+
+    /// The chunk of `path` holding the byte at `offset`, with the chunk size.
+    /// Writing allocates it if missing.
+    async fn locate(
+        &self,
+        path: &str,
+        offset: u64,
+        write: bool,
+    ) -> Result<(u64, ChunkLocation), DfsError> {
+        if let Some(cached) = self.inner.locations.lock().unwrap().get(path) {
+            if let Some(chunk) = cached.chunks.get(&(offset / cached.chunk_size)) {
+                return Ok((cached.chunk_size, chunk.clone()));
+            }
+        }
+        self.fetch_chunk(path, offset, write).await
+    }
+
+    /// `locate` bypassing the cache
+    async fn fetch_chunk(
+        &self,
+        path: &str,
+        offset: u64,
+        write: bool,
+    ) -> Result<(u64, ChunkLocation), DfsError> {
+        let (chunk_size, chunks) = self.fetch_locations(path, offset, Some(1), write).await?;
+        let chunk = chunks
+            .into_iter()
+            .find(|chunk| chunk.offset / chunk_size == offset / chunk_size)
+            .ok_or_else(|| {
+                DfsError::IndexOutOfBounds(format!("{} has no byte {}", path, offset))
+            })?;
+        Ok((chunk_size, chunk))
+    }
+
+    fn invalidate(&self, path: &str) {
+        self.inner.locations.lock().unwrap().remove(path);
+    }
+
+    /// Forget `path` and everything under it
+    fn invalidate_tree(&self, path: &str) {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        self.inner
+            .locations
+            .lock()
+            .unwrap()
+            .retain(|cached, _| cached != path && !cached.starts_with(&prefix));
+    }
+
+    async fn read_replica(
+        &self,
+        srv: &StorageAddr,
+        chunk: ChunkId,
+        offset: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, DfsError> {
+        let addr = format!(
+            "{}/storage_chunk/{}?offset={}&length={}",
+            storage_addr(srv),
+            chunk,
+            offset,
+            length
+        );
+        let resp = self.inner.http.get(addr).send().await?;
+        if !resp.status().is_success() {
+            return Err(resp.json::<ErrResponse>().await?.into());
+        }
+        Ok(resp.bytes().await?.to_vec())
+    }
+
+    async fn write_replica(
+        &self,
+        srv: &StorageAddr,
+        chunk: ChunkId,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<(), DfsError> {
+        let addr = format!(
+            "{}/storage_chunk/{}?offset={}",
+            storage_addr(srv),
+            chunk,
+            offset
+        );
+        let resp = self.inner.http.put(addr).body(data).send().await?;
+        parse::<StreamWriteOkResponse>(resp).await?;
+        Ok(())
+    }
+
+    /// Read at most `length` bytes of `path` at `offset`, stopping at the end
+    /// of the chunk. Replicas are tried in turn, then fresh locations.
+    async fn read_at(&self, path: &str, offset: u64, length: u64) -> Result<Vec<u8>, DfsError> {
+        let mut last_err = None;
+        for _ in 0..2 {
+            let (chunk_size, chunk) = self.locate(path, offset, false).await?;
+            let start = offset - chunk.offset;
+            let length = length.min(chunk_size - start);
+            for srv in &chunk.servers {
+                match self.read_replica(srv, chunk.chunk, start, length).await {
+                    Ok(data) => return Ok(data),
+                    Err(err) => {
+                        log::warn!("read_at: {:?} chunk {}, err {}", path, chunk.chunk, err);
+                        last_err = Some(err);
+                    }
+                }
+            }
+            self.invalidate(path);
+        }
+        Err(last_err.unwrap_or_else(|| DfsError::IllegalState("no replica".to_string())))
+    }
+
+    /// Lock `path`, waiting for the other holders. Return the token to unlock
+    /// it with.
+    async fn lock(&self, path: &str, exclusive: bool) -> Result<u64, DfsError> {
+        let arg = LockArg {
+            path: path.to_string(),
+            exclusive,
+        };
+        let resp: LockOkResponse = self.call("lock", &arg).await?;
+        Ok(resp.token)
+    }
+
+    /// Unlock `path`, telling the naming server how far the writes under the
+    /// lock went
+    async fn unlock(
+        &self,
+        path: &str,
+        exclusive: bool,
+        token: u64,
+        written_end: Option<u64>,
+    ) -> Result<(), DfsError> {
+        let arg = UnlockArg {
+            path: path.to_string(),
+            exclusive,
+            token,
+            written_end,
+        };
+        self.call::<_, OkResponse>("unlock", &arg).await?;
+        Ok(())
+    }
+
+    /// Write `data` into `path` at `offset` on all replicas, stopping at the
+    /// end of the chunk. Return the bytes written. The file is locked
+    /// exclusively meanwhile, so that no replica is added or left behind, and
+    /// its size covers the bytes written once unlocked.
+    async fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, DfsError> {
+        let token = self.lock(path, true).await?;
+        let res = self.write_locked(path, offset, data).await;
+        let written_end = res.as_ref().ok().map(|n| offset + *n as u64);
+        if let Err(err) = self.unlock(path, true, token, written_end).await {
+            log::warn!("write_at: unlock {:?}, err {}", path, err);
+        }
+        res
+    }
+
+    /// `write_at` with the lock held. The replicas are looked up afresh, the
+    /// cached ones may have been dropped by the lock.
+    async fn write_locked(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, DfsError> {
+        let mut last_err = None;
+        for _ in 0..2 {
+            let (chunk_size, chunk) = self.fetch_chunk(path, offset, true).await?;
+            let start = offset - chunk.offset;
+            let length = data.len().min((chunk_size - start) as usize);
+            let results =
+                join_all(chunk.servers.iter().map(|srv| {
+                    self.write_replica(srv, chunk.chunk, start, data[..length].to_vec())
+                }))
+                .await;
+            match results.into_iter().find_map(Result::err) {
+                None if !chunk.servers.is_empty() => return Ok(length),
+                None => {}
+                Some(err) => {
+                    log::warn!("write_at: {:?} chunk {}, err {}", path, chunk.chunk, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        self.invalidate(path);
+        Err(last_err.unwrap_or_else(|| DfsError::IllegalState("no replica".to_string())))
+    }
+}
@@ -0,0 +1,160 @@
+//! Addresses of the servers.
+//!
+//! A host is an IPv4 address, an IPv6 address or a hostname. It is kept in a
+//! canonical form, so that a server registered twice under different
+//! spellings is still recognized. A server is known by the socket address of
+//! one of its apis, a host along with a port.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv6Addr},
+};
+
+use rocket::serde::{Deserialize, Serialize};
+
+use super::error::TinyDfsError;
+
+/// A validated host, serialized as a plain string
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", try_from = "String", into = "String")]
+pub struct Host(String);
+
+/// A validated socket address, serialized as `host:port`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", try_from = "String", into = "String")]
+pub struct Ip {
+    host: Host,
+    port: u16,
+}
+
+/// Whether `host` is a hostname as of RFC 1123
+fn is_hostname(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    // An all-numeric top label is a mistyped IPv4 address instead
+    let numeric = |label: &str| label.bytes().all(|b| b.is_ascii_digit());
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(valid_label)
+        && !host.rsplit('.').next().is_some_and(numeric)
+}
+
+impl Host {
+    /// Accept an IPv4 address, an IPv6 address with or without brackets, or
+    /// a hostname
+    pub fn parse(host: &str) -> Result<Self, TinyDfsError> {
+        if let Some(bare) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+            return match bare.parse::<Ipv6Addr>() {
+                Ok(ip) => Ok(Host(ip.to_string())),
+                Err(_) => Err(TinyDfsError::AddressInvalid),
+            };
+        }
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(Host(ip.to_string()));
+        }
+        if is_hostname(host) {
+            Ok(Host(host.to_ascii_lowercase()))
+        } else {
+            Err(TinyDfsError::AddressInvalid)
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The address, unless this is a hostname
+    pub fn ip_addr(&self) -> Option<IpAddr> {
+        self.0.parse().ok()
+    }
+
+    /// `host:port` as in URLs, with IPv6 addresses in brackets
+    pub fn with_port(&self, port: u16) -> String {
+        match self.ip_addr() {
+            Some(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+            _ => format!("{}:{}", self.0, port),
+        }
+    }
+}
+
+impl Ip {
+    /// `port` on `host`, which can't be 0
+    pub fn new(host: Host, port: u16) -> Result<Self, TinyDfsError> {
+        if port == 0 {
+            return Err(TinyDfsError::AddressInvalid);
+        }
+        Ok(Ip { host, port })
+    }
+
+    /// Split `host:port`, where an IPv6 host must be in brackets
+    pub fn parse(addr: &str) -> Result<Self, TinyDfsError> {
+        let (host, port) = addr.rsplit_once(':').ok_or(TinyDfsError::AddressInvalid)?;
+        // A bare IPv6 address is ambiguous
+        if host.contains(':') && !host.starts_with('[') {
+            return Err(TinyDfsError::AddressInvalid);
+        }
+        let port = port.parse().or(Err(TinyDfsError::AddressInvalid))?;
+        Ip::new(Host::parse(host)?, port)
+    }
+
+    pub fn host(&self) -> &Host {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Another port on the same host, e.g. the other api of a storage server
+    pub fn with_port(&self, port: u16) -> Result<Self, TinyDfsError> {
+        Ip::new(self.host.clone(), port)
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<String> for Host {
+    type Error = String;
+
+    fn try_from(host: String) -> Result<Self, String> {
+        Host::parse(&host).map_err(|_| format!("invalid host {:?}", host))
+    }
+}
+
+impl From<Host> for String {
+    fn from(host: Host) -> Self {
+        host.0
+    }
+}
+
+impl fmt::Display for Ip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.host.with_port(self.port))
+    }
+}
+
+impl TryFrom<String> for Ip {
+    type Error = String;
+
+    fn try_from(addr: String) -> Result<Self, String> {
+        Ip::parse(&addr).map_err(|_| format!("invalid address {:?}", addr))
+    }
+}
+
+impl From<Ip> for String {
+    fn from(ip: Ip) -> Self {
+        ip.to_string()
+    }
+}
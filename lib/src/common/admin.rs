@@ -0,0 +1,105 @@
+use rocket::serde::{Deserialize, Serialize};
+
+use super::{storage::ChunkId, wire::Wire, ErrResponse, PathArg};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ServerInfo {
+    pub storage_ip: String,
+    pub client_port: u16,
+    pub command_port: u16,
+    pub alive: bool,
+    pub decommissioned: bool,
+    /// Milliseconds since the last heartbeat, missing if not heard of since
+    /// the naming server started
+    #[serde(default)]
+    pub silence_ms: Option<u64>,
+    /// Files with at least one chunk here
+    pub files: u64,
+    /// Replicas kept here
+    pub chunks: u64,
+    pub used_bytes: u64,
+    /// Bytes of the disk, 0 if unknown
+    pub capacity_bytes: u64,
+    pub available_bytes: u64,
+    pub outstanding_requests: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AdminServersOkResponse {
+    pub servers: Vec<ServerInfo>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReplicaInfo {
+    pub storage_ip: String,
+    pub client_port: u16,
+    pub command_port: u16,
+    pub alive: bool,
+    pub decommissioned: bool,
+    /// Found corrupted, kept as no healthy replica is left
+    #[serde(default)]
+    pub suspect: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChunkReplicas {
+    pub chunk: ChunkId,
+    /// Offset of the chunk in the file
+    pub offset: u64,
+    /// Every replica, dead ones included
+    pub replicas: Vec<ReplicaInfo>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FileReplicasOkResponse {
+    pub chunk_size: u64,
+    /// Replicas every chunk should have
+    pub replication: usize,
+    pub chunks: Vec<ChunkReplicas>,
+}
+
+#[derive(Responder)]
+pub enum FileReplicasResponse {
+    OkResp(Wire<FileReplicasOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+pub type ReplicateArg = PathArg;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReplicateOkResponse {
+    /// Replicas added to bring the files up to their replication
+    pub added: usize,
+}
+
+#[derive(Responder)]
+pub enum ReplicateResponse {
+    OkResp(Wire<ReplicateOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DecommissionArg {
+    pub storage_ip: String,
+    pub client_port: u16,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DecommissionOkResponse {
+    /// Replicas left to move away
+    pub chunks: usize,
+}
+
+#[derive(Responder)]
+pub enum DecommissionResponse {
+    OkResp(Wire<DecommissionOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
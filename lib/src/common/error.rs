@@ -11,6 +11,14 @@ pub enum TinyDfsError {
     PathInvalid,
     IndexOutOfBound,
     IOInterrupted,
+    JournalFailed,
+    LockNotHeld,
+    ServerNotRegistered,
+    NoServerAvailable,
+    ReplicationInvalid,
+    ChecksumMismatch,
+    AddressInvalid,
+    PatternInvalid,
     // TODO
 }
 
@@ -41,12 +49,52 @@ impl TinyDfsError {
             TinyDfsError::PathInvalid => {
                 (Status::NotFound, "IllegalArgumentException", "path invalid")
             }
+            TinyDfsError::AddressInvalid => (
+                Status::BadRequest,
+                "IllegalArgumentException",
+                "invalid host address",
+            ),
+            TinyDfsError::PatternInvalid => (
+                Status::BadRequest,
+                "IllegalArgumentException",
+                "invalid glob pattern",
+            ),
             TinyDfsError::IndexOutOfBound => (
                 Status::NotFound,
                 "IndexOutOfBoundsException",
                 "index out of bound of the file ",
             ),
             TinyDfsError::IOInterrupted => (Status::NotFound, "IOException", "IO interrupted"),
+            TinyDfsError::JournalFailed => (
+                Status::InternalServerError,
+                "IOException",
+                "metadata journal write failed",
+            ),
+            TinyDfsError::LockNotHeld => (
+                Status::Conflict,
+                "IllegalArgumentException",
+                "path not locked",
+            ),
+            TinyDfsError::ServerNotRegistered => (
+                Status::NotFound,
+                "IllegalStateException",
+                "storage server not registered",
+            ),
+            TinyDfsError::NoServerAvailable => (
+                Status::ServiceUnavailable,
+                "IllegalStateException",
+                "no storage server available",
+            ),
+            TinyDfsError::ReplicationInvalid => (
+                Status::BadRequest,
+                "IllegalArgumentException",
+                "replication must be positive",
+            ),
+            TinyDfsError::ChecksumMismatch => (
+                Status::InternalServerError,
+                "ChecksumException",
+                "checksum mismatch, try another replica",
+            ),
         }
     }
 }
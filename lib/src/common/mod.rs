@@ -1,9 +1,15 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use rocket::serde::{Deserialize, Serialize};
 
+pub mod addr;
+pub mod admin;
 pub mod error;
+pub mod path;
 pub mod registration;
 pub mod service;
 pub mod storage;
+pub mod wire;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
@@ -23,3 +29,9 @@ pub struct OkResponse {
 pub struct PathArg {
     pub path: String,
 }
+
+/// Milliseconds since the Unix epoch, the unit of every timestamp on the wire
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
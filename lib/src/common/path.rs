@@ -0,0 +1,112 @@
+//! Paths of the namespace.
+//!
+//! A path is absolute and kept in a canonical form, `/` or `/a/b`, so that
+//! both servers name a file the same way however the client spelled it.
+//! Relative components are rejected rather than resolved, which keeps a
+//! path from ever climbing out of the subtree it names.
+
+use std::fmt;
+
+use rocket::serde::{Deserialize, Serialize};
+
+use super::error::TinyDfsError;
+
+/// Bytes of a single component at most
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Bytes of a whole path at most
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// A validated absolute path, serialized as a plain string
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", try_from = "String", into = "String")]
+pub struct DfsPath(String);
+
+/// Whether `name` may be a component of a path
+fn is_valid_name(name: &str) -> bool {
+    name != "." && name != ".." && name.len() <= MAX_NAME_LEN && !name.contains('\0')
+}
+
+impl DfsPath {
+    pub fn root() -> Self {
+        DfsPath("/".to_string())
+    }
+
+    /// Accept an absolute path, dropping empty components as in `/a//b/`
+    pub fn parse(path: &str) -> Result<Self, TinyDfsError> {
+        if !path.starts_with('/') || path.len() > MAX_PATH_LEN {
+            return Err(TinyDfsError::PathInvalid);
+        }
+        let mut canonical = String::with_capacity(path.len());
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if !is_valid_name(name) {
+                return Err(TinyDfsError::PathInvalid);
+            }
+            canonical.push('/');
+            canonical.push_str(name);
+        }
+        if canonical.is_empty() {
+            return Ok(DfsPath::root());
+        }
+        Ok(DfsPath(canonical))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0 == "/"
+    }
+
+    /// Components from the root, none for the root itself
+    pub fn names(&self) -> Vec<&str> {
+        self.0.split('/').filter(|name| !name.is_empty()).collect()
+    }
+
+    /// The last component, `None` for the root
+    pub fn name(&self) -> Option<&str> {
+        self.names().pop()
+    }
+
+    /// The child `name` of this path
+    pub fn join(&self, name: &str) -> Result<Self, TinyDfsError> {
+        if name.is_empty() || name.contains('/') || !is_valid_name(name) {
+            return Err(TinyDfsError::PathInvalid);
+        }
+        let prefix = self.0.trim_end_matches('/');
+        Ok(DfsPath(format!("{}/{}", prefix, name)))
+    }
+
+    /// Whether `self` is `other` or lies under it
+    pub fn starts_with(&self, other: &DfsPath) -> bool {
+        self.names().starts_with(&other.names())
+    }
+}
+
+impl fmt::Display for DfsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// Logged as the bare string
+impl fmt::Debug for DfsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl TryFrom<String> for DfsPath {
+    type Error = String;
+
+    fn try_from(path: String) -> Result<Self, String> {
+        DfsPath::parse(&path).map_err(|_| format!("invalid path {:?}", path))
+    }
+}
+
+impl From<DfsPath> for String {
+    fn from(path: DfsPath) -> Self {
+        path.0
+    }
+}
@@ -1,5 +1,9 @@
+use std::collections::HashMap;
+
 use rocket::serde::{Deserialize, Serialize};
 
+use super::{service::ChunkLocation, storage::ChunkId};
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct RegisterArg {
@@ -7,11 +11,89 @@ pub struct RegisterArg {
     pub storage_ip: String,
     pub client_port: u16,
     pub command_port: u16,
-    pub files: Vec<String>,
+    /// Chunks stored locally
+    pub chunks: Vec<ChunkId>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct RegisterOkResponse {
-    pub files: Vec<String>,
+    /// Chunks the storage server should delete
+    pub chunks: Vec<ChunkId>,
+    /// Truncation epochs of the chunks kept, but for the never truncated ones
+    #[serde(default)]
+    pub epochs: HashMap<ChunkId, u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct HeartbeatArg {
+    pub storage_ip: String,
+    pub client_port: u16,
+    pub command_port: u16,
+    /// Bytes of the chunks stored
+    #[serde(default)]
+    pub used_bytes: u64,
+    /// Bytes of the disk holding the files
+    #[serde(default)]
+    pub capacity_bytes: u64,
+    /// Bytes left on that disk
+    #[serde(default)]
+    pub available_bytes: u64,
+    /// Client requests being served
+    #[serde(default)]
+    pub outstanding_requests: u64,
+    /// Chunks written since the last heartbeat
+    #[serde(default)]
+    pub written: Vec<ChunkWritten>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReportCorruptArg {
+    pub storage_ip: String,
+    pub client_port: u16,
+    pub command_port: u16,
+    /// Chunks that failed verification
+    pub chunks: Vec<ChunkId>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReportCorruptOkResponse {
+    /// Chunks to keep, as no healthy replica is left. The others are
+    /// dropped and may be quarantined.
+    pub kept: Vec<ChunkId>,
+}
+
+/// A chunk written since the last heartbeat
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChunkWritten {
+    pub chunk: ChunkId,
+    /// Its bytes after the writes
+    pub size: u64,
+    /// Milliseconds since the Unix epoch of the last write
+    pub modified: u64,
+    /// Truncation epoch of the replica when its size was read
+    #[serde(default)]
+    pub epoch: u64,
+}
+
+/// Make room for `length` bytes at the end of the file at `path`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReserveAppendArg {
+    pub path: String,
+    pub length: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReserveAppendOkResponse {
+    /// Start of the range reserved
+    pub offset: u64,
+    pub chunk_size: u64,
+    /// Chunks overlapping the range, with their alive replicas
+    pub chunks: Vec<ChunkLocation>,
 }
@@ -1,8 +1,6 @@
-use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::serde::{Deserialize, Serialize};
 
-use crate::naming::Ip;
-
-use super::{ErrResponse, OkResponse, PathArg};
+use super::{addr::Host, storage::ChunkId, wire::Wire, ErrResponse, OkResponse, PathArg};
 
 pub type IsValidPathArg = PathArg;
 
@@ -12,57 +10,265 @@ pub struct IsValidPathResponse {
     pub success: bool,
 }
 
-pub type GetStorageArg = PathArg;
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct GetStorageArg {
+    pub path: String,
+    /// Start of the byte range
+    #[serde(default)]
+    pub offset: u64,
+    /// Up to the end of the file if missing
+    #[serde(default)]
+    pub length: Option<u64>,
+    /// Allocate the chunks of the range the file doesn't have yet
+    #[serde(default)]
+    pub write: bool,
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
-pub struct GetStorageOkResponse {
-    pub server_ip: Ip,
+pub struct StorageAddr {
+    pub server_ip: Host,
     pub server_port: u16,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChunkLocation {
+    pub chunk: ChunkId,
+    /// Offset of the chunk in the file
+    pub offset: u64,
+    /// Alive replicas, the preferred one first
+    pub servers: Vec<StorageAddr>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct GetStorageOkResponse {
+    pub chunk_size: u64,
+    /// Chunks overlapping the byte range, in order
+    pub chunks: Vec<ChunkLocation>,
+}
+
 pub type DeleteArg = PathArg;
 
 #[derive(Responder)]
 pub enum DeleteResponse {
-    OkResp(Json<OkResponse>),
-    ErrResp(Json<ErrResponse>),
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
 }
 
-pub type CreateDirectoryArg = PathArg;
+/// A dir's replication is inherited by the files created in it
+pub type CreateDirectoryArg = CreateFileArg;
 
 #[derive(Responder)]
 pub enum CreateDirectoryResponse {
-    OkResp(Json<OkResponse>),
-    ErrResp(Json<ErrResponse>),
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
 }
 
-pub type CreateFileArg = PathArg;
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CreateFileArg {
+    pub path: String,
+    /// Inherited from the parent dir if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replication: Option<usize>,
+    /// User creating the file, shown by `/stat`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
 
 #[derive(Responder)]
 pub enum CreateFileResponse {
-    OkResp(Json<OkResponse>),
-    ErrResp(Json<ErrResponse>),
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
 }
 
-pub type ListArg = PathArg;
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ListArg {
+    pub path: String,
+    /// Only the entries named after this one, e.g. the continuation token
+    /// of the previous page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_after: Option<String>,
+    /// At most this many entries (at least 1), all of them if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// Fill `entries` as well
+    #[serde(default)]
+    pub detailed: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ListEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub file_type: FileType,
+    /// Bytes of a regular file, 0 for a dir
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub modified: u64,
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct ListOkResponse {
+    /// Names of the entries, in order
     pub files: Vec<String>,
+    /// Details of the same entries if `detailed` was set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entries: Option<Vec<ListEntry>>,
+    /// The `start_after` of the next page, missing on the last one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
 }
 
 #[derive(Responder)]
 pub enum ListResponse {
-    OkResp(Json<ListOkResponse>),
-    ErrResp(Json<ErrResponse>),
+    OkResp(Wire<ListOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FindArg {
+    /// Root of the subtree searched, included
+    pub path: String,
+    /// Glob matched against the name, or against the whole path if it has a `/`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub file_type: Option<FileType>,
+    /// Bounds of the size, inclusive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// Bounds of the mtime in milliseconds since the Unix epoch, inclusive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_after: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_before: Option<u64>,
+}
+
+/// One line of the newline-delimited JSON answer of `/find`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FindEntry {
+    pub path: String,
+    #[serde(rename = "type")]
+    pub file_type: FileType,
+    /// Bytes of a regular file, 0 for a dir
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub modified: u64,
 }
 
 pub type IsDirectoryArg = PathArg;
 
+pub type StatArg = PathArg;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum FileType {
+    File,
+    Dir,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct StatOkResponse {
+    #[serde(rename = "type")]
+    pub file_type: FileType,
+    /// Bytes of a regular file, 0 for a dir
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+    /// Fewest alive replicas of a chunk of a regular file, 0 for a dir
+    pub replicas: usize,
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+#[derive(Responder)]
+pub enum StatResponse {
+    OkResp(Wire<StatOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
 #[derive(Responder)]
 pub enum IsDirectoryResponse {
-    OkResp(Json<OkResponse>),
-    ErrResp(Json<ErrResponse>),
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RenameArg {
+    pub src_path: String,
+    pub dst_path: String,
+}
+
+#[derive(Responder)]
+pub enum RenameResponse {
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LockArg {
+    pub path: String,
+    pub exclusive: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LockOkResponse {
+    pub success: bool,
+    /// Given back to `/unlock`
+    pub token: u64,
+}
+
+#[derive(Responder)]
+pub enum LockResponse {
+    OkResp(Wire<LockOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UnlockArg {
+    pub path: String,
+    pub exclusive: bool,
+    /// Answered by the `/lock` of the path
+    pub token: u64,
+    /// End of the bytes written under an exclusive lock, counted in the size
+    /// of the file right away rather than at the next heartbeat
+    #[serde(default)]
+    pub written_end: Option<u64>,
+}
+
+#[derive(Responder)]
+pub enum UnlockResponse {
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SetReplicationArg {
+    pub path: String,
+    pub replication: usize,
+}
+
+#[derive(Responder)]
+pub enum SetReplicationResponse {
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
 }
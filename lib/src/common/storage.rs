@@ -3,28 +3,46 @@ use base64::{
     engine::{general_purpose::PAD, GeneralPurpose},
     DecodeError, Engine,
 };
-use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::serde::{Deserialize, Serialize};
 
-use super::{ErrResponse, OkResponse, PathArg};
+use super::{
+    wire::{FileData, Wire},
+    ErrResponse, OkResponse,
+};
 
-pub type SizeArg = PathArg;
+/// Name of a chunk on the storage servers
+pub type ChunkId = u64;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChunkArg {
+    pub chunk: ChunkId,
+}
+
+pub type SizeArg = ChunkArg;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct SizeOkResponse {
     pub size: u64,
+    /// Bytes the chunk may hold, 0 if unknown
+    #[serde(default)]
+    pub chunk_size: u64,
+    /// Truncation epoch of the replica, taken over by copies of it
+    #[serde(default)]
+    pub epoch: u64,
 }
 
 #[derive(Responder)]
 pub enum SizeResponse {
-    OkResp(Json<SizeOkResponse>),
-    ErrResp(Json<ErrResponse>),
+    OkResp(Wire<SizeOkResponse>),
+    ErrResp(Wire<ErrResponse>),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct ReadArg {
-    pub path: String,
+    pub chunk: ChunkId,
     pub offset: u64,
     pub length: i32,
 }
@@ -32,27 +50,146 @@ pub struct ReadArg {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct ReadOkResponse {
-    pub data: String,
+    pub data: FileData,
 }
 
 #[derive(Responder)]
 pub enum ReadResponse {
-    OkResp(Json<ReadOkResponse>),
-    ErrResp(Json<ErrResponse>),
+    OkResp(Wire<ReadOkResponse>),
+    ErrResp(Wire<ErrResponse>),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct WriteArg {
-    pub path: String,
+    pub chunk: ChunkId,
     pub offset: u64,
-    pub data: String,
+    pub data: FileData,
 }
 
 #[derive(Responder)]
 pub enum WriteResponse {
-    OkResp(Json<OkResponse>),
-    ErrResp(Json<ErrResponse>),
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CopyArg {
+    pub chunk: ChunkId,
+    /// Ip and client port of the storage server to copy from
+    pub source_ip: String,
+    pub source_port: u16,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CopyOkResponse {
+    pub bytes: u64,
+}
+
+#[derive(Responder)]
+pub enum CopyResponse {
+    OkResp(Wire<CopyOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct StreamWriteOkResponse {
+    pub bytes: u64,
+}
+
+#[derive(Responder)]
+pub enum StreamWriteResponse {
+    OkResp(Wire<StreamWriteOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CreateChunkArg {
+    pub chunk: ChunkId,
+    /// Bytes the chunk may hold, the chunk size of its file
+    #[serde(default)]
+    pub chunk_size: u64,
+}
+
+#[derive(Responder)]
+pub enum CreateChunkResponse {
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+pub type DeleteChunkArg = ChunkArg;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ScrubOkResponse {
+    /// Chunks that failed verification
+    pub corrupted: Vec<ChunkId>,
+}
+
+#[derive(Responder)]
+pub enum DeleteChunkResponse {
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+/// Cut a file, on any storage server
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TruncateArg {
+    pub path: String,
+    /// Bytes left, no more than the size of the file
+    pub length: u64,
+}
+
+#[derive(Responder)]
+pub enum TruncateResponse {
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+/// Write at the end of a file, on any storage server
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AppendArg {
+    pub path: String,
+    pub data: FileData,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AppendOkResponse {
+    /// Where the data has been written
+    pub offset: u64,
+}
+
+#[derive(Responder)]
+pub enum AppendResponse {
+    OkResp(Wire<AppendOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TruncateChunkArg {
+    pub chunk: ChunkId,
+    /// Bytes left in the replica
+    pub length: u64,
+    /// Only fill a shorter replica with zeros, a longer one is left as is
+    #[serde(default)]
+    pub grow_only: bool,
+    /// Truncation epoch of the chunk, reported back along with its writes
+    #[serde(default)]
+    pub epoch: u64,
+}
+
+#[derive(Responder)]
+pub enum TruncateChunkResponse {
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
 }
 
 const BASE64_ENGINE: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, PAD);
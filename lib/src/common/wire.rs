@@ -0,0 +1,150 @@
+//! Payloads of the apis, either in JSON or in MessagePack.
+//!
+//! A request is decoded according to its `Content-Type`. A response is
+//! encoded in MessagePack if the client prefers it by `Accept`, or sent a
+//! MessagePack request without saying, and in JSON otherwise.
+
+use std::ops::{Deref, DerefMut};
+
+use rocket::{
+    data::{self, Data, FromData},
+    http::{ContentType, MediaType, Status},
+    request::Request,
+    response::{self, Responder},
+    serde::{
+        de::{self, DeserializeOwned, Visitor},
+        json::Json,
+        msgpack::{self, MsgPack},
+        Deserialize, Deserializer, Serialize, Serializer,
+    },
+};
+
+use super::storage::{base64_decode, base64_encode};
+
+#[derive(Debug, Clone)]
+pub struct Wire<T>(pub T);
+
+impl<T> Wire<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Wire<T> {
+    fn from(value: T) -> Self {
+        Wire(value)
+    }
+}
+
+impl<T> Deref for Wire<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Wire<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+fn is_msgpack(media_type: &MediaType) -> bool {
+    media_type.top() == "application"
+        && (media_type.sub() == "msgpack" || media_type.sub() == "x-msgpack")
+}
+
+/// Whether the response to `req` should be in MessagePack
+fn wants_msgpack(req: &Request<'_>) -> bool {
+    match req.accept() {
+        Some(accept) if !accept.preferred().media_type().is_any() => {
+            is_msgpack(accept.preferred().media_type())
+        }
+        _ => req
+            .content_type()
+            .is_some_and(|content_type| is_msgpack(content_type.media_type())),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned + Send> FromData<'r> for Wire<T> {
+    type Error = String;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let is_msgpack = req
+            .content_type()
+            .is_some_and(|content_type| is_msgpack(content_type.media_type()));
+        if is_msgpack {
+            MsgPack::<T>::from_data(req, data)
+                .await
+                .map(|value| Wire(value.into_inner()))
+                .map_error(|(status, err)| (status, err.to_string()))
+        } else {
+            Json::<T>::from_data(req, data)
+                .await
+                .map(|value| Wire(value.into_inner()))
+                .map_error(|(status, err)| (status, err.to_string()))
+        }
+    }
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Wire<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        if !wants_msgpack(req) {
+            return Json(self.0).respond_to(req);
+        }
+        // Field names are kept, so that optional fields can be left out
+        let buf = msgpack::to_vec(&self.0).map_err(|err| {
+            log::error!("respond_to: msgpack encode failed, err {:?}", err);
+            Status::InternalServerError
+        })?;
+        (ContentType::MsgPack, buf).respond_to(req)
+    }
+}
+
+/// File data, in base64 within JSON and as raw bytes within MessagePack
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileData(pub Vec<u8>);
+
+impl Serialize for FileData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&base64_encode(&self.0))
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+struct FileDataVisitor;
+
+impl<'de> Visitor<'de> for FileDataVisitor {
+    type Value = FileData;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("bytes or a base64 string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<FileData, E> {
+        base64_decode(v).map(FileData).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<FileData, E> {
+        Ok(FileData(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<FileData, E> {
+        Ok(FileData(v))
+    }
+}
+
+impl<'de> Deserialize<'de> for FileData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FileData, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(FileDataVisitor)
+        } else {
+            deserializer.deserialize_bytes(FileDataVisitor)
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! Configuration of the servers.
+//!
+//! Every setting has a default, overridden in turn by a TOML file given by
+//! `--config`, by environment variables named after the settings (e.g.
+//! `TINY_DFS_NAMING_SERVICE_PORT`, `TINY_DFS_STORAGE_DATA_DIR`) and by flags
+//! (e.g. `--service-port 11111` or `--data-dir=/tmp/tiny-dfs`).
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+};
+
+use rocket::{
+    figment::{
+        providers::{Env, Format, Serialized, Toml},
+        value::{Dict, Map, Value},
+        Figment, Metadata, Profile, Provider,
+    },
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+};
+
+use crate::{
+    common::addr::{Host, Ip},
+    naming,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default, deny_unknown_fields)]
+pub struct NamingConfig {
+    /// Address both apis listen on
+    pub bind_address: IpAddr,
+    /// Port of the api serving the clients
+    pub service_port: u16,
+    /// Port of the api serving the storage servers and the admins
+    pub registration_port: u16,
+    /// Where the metadata journal and snapshots are kept
+    pub meta_dir: PathBuf,
+    /// Shared accesses to a file before it gets one more replica
+    pub replication_threshold: u64,
+    /// Replicas kept by the repair task of the files without a factor of their own
+    pub replication_factor: usize,
+    /// One of random, round-robin, least-used-capacity,
+    /// least-outstanding-requests and consistent-hashing
+    pub placement: String,
+    /// Bytes of every chunk but the last one of the new files
+    pub chunk_size: u64,
+    /// Filters in the `RUST_LOG` syntax, `RUST_LOG` itself if missing
+    pub log_level: Option<String>,
+}
+
+impl Default for NamingConfig {
+    fn default() -> Self {
+        NamingConfig {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            service_port: 11111,
+            registration_port: 22222,
+            meta_dir: PathBuf::from("/tmp/tiny-dfs-naming"),
+            replication_threshold: 20,
+            replication_factor: 1,
+            placement: "random".to_string(),
+            chunk_size: 64 << 20,
+            log_level: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Address both apis listen on
+    pub bind_address: IpAddr,
+    /// Host the naming server and the clients reach this server at, an IPv4
+    /// or IPv6 address or a hostname
+    pub advertised_host: String,
+    /// Port of the api serving the clients
+    pub client_port: u16,
+    /// Port of the api serving the naming server
+    pub command_port: u16,
+    /// host:port of the registration api of the naming server, with an IPv6
+    /// host in brackets
+    pub naming_address: String,
+    /// Where the chunks are kept
+    pub data_dir: PathBuf,
+    /// Seconds between two scrubs of the local chunks
+    pub scrub_period: u64,
+    /// Filters in the `RUST_LOG` syntax, `RUST_LOG` itself if missing
+    pub log_level: Option<String>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            advertised_host: "localhost".to_string(),
+            client_port: 33333,
+            command_port: 44444,
+            naming_address: "localhost:22222".to_string(),
+            data_dir: PathBuf::from("/tmp/tiny-dfs"),
+            scrub_period: 24 * 60 * 60,
+            log_level: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Settings given on the command line
+struct Flags(Dict);
+
+impl Provider for Flags {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("command line flags")
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, rocket::figment::Error> {
+        Ok(Profile::Default.collect(self.0.clone()))
+    }
+}
+
+/// Layer the defaults, the file given by `--config`, the environment
+/// variables starting with `env_prefix` and the other flags in `args`
+fn load<T>(env_prefix: &str, args: &[String]) -> Result<T, ConfigError>
+where
+    T: Default + Serialize + DeserializeOwned,
+{
+    let mut file = None;
+    let mut flags = Dict::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            return Err(ConfigError(format!("unexpected argument {:?}", arg)));
+        };
+        let (key, value) = match flag.split_once('=') {
+            Some((key, value)) => (key, value.to_string()),
+            None => match args.next() {
+                Some(value) => (flag, value.clone()),
+                None => return Err(ConfigError(format!("missing value of --{}", flag))),
+            },
+        };
+        if key == "config" {
+            file = Some(value);
+        } else {
+            // Parsed like environment variables, so that numbers are numbers
+            flags.insert(key.replace('-', "_"), value.parse::<Value>().unwrap());
+        }
+    }
+
+    let mut figment = Figment::from(Serialized::defaults(T::default()));
+    if let Some(file) = file {
+        figment = figment.merge(Toml::file_exact(file));
+    }
+    figment
+        .merge(Env::prefixed(env_prefix))
+        .merge(Flags(flags))
+        .extract()
+        .map_err(|err| ConfigError(err.to_string()))
+}
+
+impl NamingConfig {
+    /// Load from the command line flags `args`, see the module doc
+    pub fn load(args: &[String]) -> Result<Self, ConfigError> {
+        let config: Self = load("TINY_DFS_NAMING_", args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.service_port == self.registration_port {
+            return Err(ConfigError(
+                "service_port and registration_port must differ".to_string(),
+            ));
+        }
+        if !naming::is_placement_policy(&self.placement) {
+            return Err(ConfigError(format!(
+                "unknown placement policy {:?}",
+                self.placement
+            )));
+        }
+        if self.replication_factor == 0 || self.replication_threshold == 0 || self.chunk_size == 0 {
+            return Err(ConfigError(
+                "replication_factor, replication_threshold and chunk_size must be positive"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl StorageConfig {
+    /// Load from the command line flags `args`, see the module doc
+    pub fn load(args: &[String]) -> Result<Self, ConfigError> {
+        let config: Self = load("TINY_DFS_STORAGE_", args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.client_port == self.command_port {
+            return Err(ConfigError(
+                "client_port and command_port must differ".to_string(),
+            ));
+        }
+        if Host::parse(&self.advertised_host).is_err() {
+            return Err(ConfigError(format!(
+                "advertised_host {:?} is not an IP address or a hostname",
+                self.advertised_host
+            )));
+        }
+        if Ip::parse(&self.naming_address).is_err() {
+            return Err(ConfigError(format!(
+                "naming_address {:?} is not host:port",
+                self.naming_address
+            )));
+        }
+        if self.scrub_period == 0 {
+            return Err(ConfigError("scrub_period must be positive".to_string()));
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 #[macro_use]
 extern crate rocket;
 
+pub mod client;
 pub mod common;
+pub mod config;
 mod naming;
 mod storage;
 
@@ -0,0 +1,180 @@
+//! Cluster management, mounted under /admin of the registration port
+
+use std::{collections::HashMap, sync::Arc};
+
+use rocket::http::Status;
+
+use crate::{
+    common::{
+        addr::Host,
+        admin::{
+            AdminServersOkResponse, ChunkReplicas, DecommissionArg, DecommissionOkResponse,
+            DecommissionResponse, FileReplicasOkResponse, FileReplicasResponse, ReplicaInfo,
+            ReplicateArg, ReplicateOkResponse, ReplicateResponse, ServerInfo,
+        },
+        error::TinyDfsError,
+        path::DfsPath,
+        wire::Wire,
+        ErrResponse,
+    },
+    naming::{
+        chunk,
+        dir_tree::{self, File},
+        repair, server, Ip,
+    },
+};
+
+fn err_resp(err: TinyDfsError) -> (Status, Wire<ErrResponse>) {
+    let (status, etype, einfo) = err.exception();
+    (
+        status,
+        ErrResponse {
+            exception_type: etype.to_string(),
+            exception_info: einfo.to_string(),
+        }
+        .into(),
+    )
+}
+
+#[get("/servers")]
+pub async fn servers() -> Wire<AdminServersOkResponse> {
+    let srvs = server::all_servers().await;
+    // (files, chunks) of every server, keyed by the address of its entry
+    let mut counts: HashMap<usize, (u64, u64)> = HashMap::new();
+    for (_, chunks, _) in dir_tree::all_reg_files().await {
+        let mut held: HashMap<usize, u64> = HashMap::new();
+        for chunk in chunks {
+            for srv in chunk.servers() {
+                *held.entry(Arc::as_ptr(&srv) as usize).or_default() += 1;
+            }
+        }
+        for (srv, chunks) in held {
+            let count = counts.entry(srv).or_default();
+            count.0 += 1;
+            count.1 += chunks;
+        }
+    }
+    let servers = srvs
+        .iter()
+        .map(|srv| {
+            let (files, chunks) = counts
+                .get(&(Arc::as_ptr(srv) as usize))
+                .copied()
+                .unwrap_or_default();
+            ServerInfo {
+                storage_ip: srv.ip.host().to_string(),
+                client_port: srv.ip.port(),
+                command_port: srv.command_port,
+                alive: srv.is_alive(),
+                decommissioned: srv.is_decommissioned(),
+                silence_ms: srv.silence().map(|silence| silence.as_millis() as u64),
+                files,
+                chunks,
+                used_bytes: srv.used_bytes(),
+                capacity_bytes: srv.capacity_bytes(),
+                available_bytes: srv.available_bytes(),
+                outstanding_requests: srv.outstanding_requests(),
+            }
+        })
+        .collect();
+    AdminServersOkResponse { servers }.into()
+}
+
+#[get("/file?<path>")]
+pub async fn file_replicas(path: &str) -> (Status, FileReplicasResponse) {
+    let err_ret = |err: TinyDfsError| {
+        let (status, resp) = err_resp(err);
+        (status, FileReplicasResponse::ErrResp(resp))
+    };
+    let path = match DfsPath::parse(path) {
+        Ok(path) => path,
+        Err(err) => return err_ret(err),
+    };
+    let Ok((_, Some(target))) = dir_tree::lookup(&path).await else {
+        return err_ret(TinyDfsError::FileNotFound);
+    };
+    let File::RegFile(file) = target.as_ref() else {
+        return err_ret(TinyDfsError::PathInvalid);
+    };
+    let chunk_size = file.chunk_size();
+    let chunks = file
+        .chunks()
+        .iter()
+        .enumerate()
+        .map(|(idx, chunk)| ChunkReplicas {
+            chunk: chunk.id,
+            offset: idx as u64 * chunk_size,
+            replicas: chunk
+                .servers()
+                .iter()
+                .map(|srv| ReplicaInfo {
+                    storage_ip: srv.ip.host().to_string(),
+                    client_port: srv.ip.port(),
+                    command_port: srv.command_port,
+                    alive: srv.is_alive(),
+                    decommissioned: srv.is_decommissioned(),
+                    suspect: chunk.is_suspect(srv),
+                })
+                .collect(),
+        })
+        .collect();
+    let resp = FileReplicasOkResponse {
+        chunk_size,
+        replication: repair::replication_factor(target.replication()),
+        chunks,
+    };
+    (Status::Ok, FileReplicasResponse::OkResp(resp.into()))
+}
+
+/// Repair the files under the path now instead of at the next scan
+#[post("/replicate", data = "<arg>")]
+pub async fn replicate(arg: Wire<ReplicateArg>) -> (Status, ReplicateResponse) {
+    let path = match DfsPath::parse(&arg.path) {
+        Ok(path) => path,
+        Err(err) => {
+            let (status, resp) = err_resp(err);
+            return (status, ReplicateResponse::ErrResp(resp));
+        }
+    };
+    match dir_tree::lookup(&path).await {
+        Ok((_, Some(target))) => {
+            let added = repair::repair_tree(target, &path).await;
+            (
+                Status::Ok,
+                ReplicateResponse::OkResp(ReplicateOkResponse { added }.into()),
+            )
+        }
+        _ => {
+            let (status, resp) = err_resp(TinyDfsError::FileNotFound);
+            (status, ReplicateResponse::ErrResp(resp))
+        }
+    }
+}
+
+#[post("/decommission", data = "<arg>")]
+pub async fn decommission(arg: Wire<DecommissionArg>) -> (Status, DecommissionResponse) {
+    let res = async {
+        let ip = Ip::new(Host::parse(&arg.storage_ip)?, arg.client_port)?;
+        let srv = server::find_by_client_addr(&ip)
+            .await
+            .ok_or(TinyDfsError::ServerNotRegistered)?;
+        if !srv.is_decommissioned() {
+            log::info!("decommission: server {}", srv.ip);
+            server::decommission(srv.clone()).await?;
+            // Start moving the replicas away
+            rocket::tokio::spawn(repair::repair());
+        }
+        Ok::<_, TinyDfsError>(chunk::chunks_of(&srv).len())
+    }
+    .await;
+    match res {
+        Ok(chunks) => (
+            Status::Ok,
+            DecommissionResponse::OkResp(DecommissionOkResponse { chunks }.into()),
+        ),
+        Err(err) => {
+            let (status, resp) = err_resp(err);
+            (status, DecommissionResponse::ErrResp(resp))
+        }
+    }
+}
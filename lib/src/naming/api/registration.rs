@@ -1,52 +1,370 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::SystemTime};
 
 use rocket;
 use rocket::http::Status;
-use rocket::serde::json::Json;
 
 use crate::common::{
-    registration::{RegisterArg, RegisterOkResponse},
-    ErrResponse,
+    addr::Host,
+    error::TinyDfsError,
+    path::DfsPath,
+    registration::{
+        ChunkWritten, HeartbeatArg, RegisterArg, RegisterOkResponse, ReportCorruptArg,
+        ReportCorruptOkResponse, ReserveAppendArg, ReserveAppendOkResponse,
+    },
+    service::GetStorageArg,
+    storage::TruncateArg,
+    unix_millis,
+    wire::Wire,
+    ErrResponse, OkResponse,
 };
 use crate::naming::{
-    dir_tree::collect_files,
-    server::{register_server, StorageServer},
+    chunk::{self, collect_chunks},
+    dir_tree::{self, File},
+    journal::ServerRecord,
+    lock, replication,
+    server::{self, register_server, Load, StorageServer},
     Ip,
 };
 
+use super::service::locate_chunks;
+
 #[derive(Responder)]
 pub enum RegisterResponse {
-    OkResp(Json<RegisterOkResponse>),
-    ErrResp(Json<ErrResponse>),
+    OkResp(Wire<RegisterOkResponse>),
+    ErrResp(Wire<ErrResponse>),
 }
 
 #[post("/register", data = "<arg>")]
-pub async fn register_storage_server(arg: Json<RegisterArg>) -> (Status, RegisterResponse) {
-    let srv = Arc::new(StorageServer::new(
-        Ip(arg.storage_ip.clone()),
-        arg.client_port,
-        arg.command_port,
-    ));
-    if register_server(&srv).await.is_err() {
-        return (
-            Status::Conflict,
-            RegisterResponse::ErrResp(
-                ErrResponse {
-                    exception_type: "IllegalStateException".to_string(),
-                    exception_info: "This storage client already registered.".to_string(),
-                }
-                .into(),
-            ),
-        );
-    }
-    let duplicated_files = collect_files(&arg.files, srv).await.unwrap();
+pub async fn register_storage_server(arg: Wire<RegisterArg>) -> (Status, RegisterResponse) {
+    let ip = match Host::parse(&arg.storage_ip).and_then(|host| Ip::new(host, arg.client_port)) {
+        Ok(ip) => ip,
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            return (
+                status,
+                RegisterResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            );
+        }
+    };
+    let srv = Arc::new(StorageServer::new(ip, arg.command_port));
+    let srv = match register_server(&srv).await {
+        Ok(srv) => {
+            srv.heartbeat();
+            srv
+        }
+        Err(TinyDfsError::JournalFailed) => {
+            let (status, etype, einfo) = TinyDfsError::JournalFailed.exception();
+            return (
+                status,
+                RegisterResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            );
+        }
+        Err(_) => {
+            return (
+                Status::Conflict,
+                RegisterResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: "IllegalStateException".to_string(),
+                        exception_info: "This storage client already registered.".to_string(),
+                    }
+                    .into(),
+                ),
+            );
+        }
+    };
+    let stale_chunks = match collect_chunks(&arg.chunks, srv.clone()).await {
+        Ok(stale_chunks) => stale_chunks,
+        Err(err) => {
+            log::warn!(
+                "register_storage_server: collect chunks failed, err {:?}",
+                err
+            );
+            let (status, etype, einfo) = err.exception();
+            return (
+                status,
+                RegisterResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            );
+        }
+    };
+    // Lost by a restart of the storage server
+    let epochs = chunk::chunks_of(&srv)
+        .iter()
+        .filter(|chunk| chunk.epoch() > 0)
+        .map(|chunk| (chunk.id, chunk.epoch()))
+        .collect();
     (
         Status::Ok,
         RegisterResponse::OkResp(
             RegisterOkResponse {
-                files: duplicated_files,
+                chunks: stale_chunks,
+                epochs,
             }
             .into(),
         ),
     )
 }
+
+#[derive(Responder)]
+pub enum HeartbeatResponse {
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+/// Count the writes reported by `srv` in the sizes and times of its chunks.
+/// The heartbeat stands anyway, so failures are only logged.
+async fn record_writes(srv: &Arc<StorageServer>, written: &[ChunkWritten]) {
+    for write in written {
+        match chunk::find_chunk(write.chunk) {
+            // A replica the naming server doesn't know of says nothing about the file
+            Some(found) if found.has_server(srv) => {
+                let res =
+                    chunk::record_write(write.chunk, write.size, write.modified, write.epoch).await;
+                if let Err(err) = res {
+                    log::warn!("record_writes: chunk {}, err {:?}", write.chunk, err);
+                }
+            }
+            _ => log::debug!("record_writes: chunk {} not on {:?}", write.chunk, srv.ip),
+        }
+    }
+}
+
+#[post("/heartbeat", data = "<arg>")]
+pub async fn heartbeat(arg: Wire<HeartbeatArg>) -> (Status, HeartbeatResponse) {
+    let ip = match Host::parse(&arg.storage_ip) {
+        Ok(ip) => ip,
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            return (
+                status,
+                HeartbeatResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            );
+        }
+    };
+    let record = ServerRecord {
+        ip,
+        client_port: arg.client_port,
+        command_port: arg.command_port,
+    };
+    let load = Load {
+        used_bytes: arg.used_bytes,
+        capacity_bytes: arg.capacity_bytes,
+        available_bytes: arg.available_bytes,
+        outstanding_requests: arg.outstanding_requests,
+    };
+    match server::heartbeat(&record, &load).await {
+        Ok(srv) => {
+            record_writes(&srv, &arg.written).await;
+            (
+                Status::Ok,
+                HeartbeatResponse::OkResp(OkResponse { success: true }.into()),
+            )
+        }
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            (
+                status,
+                HeartbeatResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            )
+        }
+    }
+}
+
+#[derive(Responder)]
+pub enum ReportCorruptResponse {
+    OkResp(Wire<ReportCorruptOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[post("/report_corrupt", data = "<arg>")]
+pub async fn report_corrupt(arg: Wire<ReportCorruptArg>) -> (Status, ReportCorruptResponse) {
+    let ip = match Host::parse(&arg.storage_ip) {
+        Ok(ip) => ip,
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            return (
+                status,
+                ReportCorruptResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            );
+        }
+    };
+    let record = ServerRecord {
+        ip,
+        client_port: arg.client_port,
+        command_port: arg.command_port,
+    };
+    let res = match server::find_server(&record).await {
+        Some(srv) => chunk::drop_corrupt(&arg.chunks, srv).await,
+        None => Err(TinyDfsError::ServerNotRegistered),
+    };
+    match res {
+        Ok(kept) => (
+            Status::Ok,
+            ReportCorruptResponse::OkResp(ReportCorruptOkResponse { kept }.into()),
+        ),
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            (
+                status,
+                ReportCorruptResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            )
+        }
+    }
+}
+
+#[derive(Responder)]
+pub enum TruncateFileResponse {
+    OkResp(Wire<OkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+/// Truncate a file on behalf of a client of a storage server
+#[post("/truncate_file", data = "<arg>")]
+pub async fn truncate_file(arg: Wire<TruncateArg>) -> (Status, TruncateFileResponse) {
+    let res = async {
+        let path = DfsPath::parse(&arg.path)?;
+        // Waits for the writers and the other appends and truncations
+        let _guard = lock::lock(&path, true).await?;
+        let truncated = dir_tree::truncate(&path, arg.length).await?;
+        replication::delete_chunks(&truncated.dropped).await;
+        match truncated.cut {
+            Some((chunk, length)) => replication::truncate_replicas(&chunk, length).await,
+            None => Ok(()),
+        }
+    }
+    .await;
+    match res {
+        Ok(_) => (
+            Status::Ok,
+            TruncateFileResponse::OkResp(OkResponse { success: true }.into()),
+        ),
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            (
+                status,
+                TruncateFileResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            )
+        }
+    }
+}
+
+#[derive(Responder)]
+pub enum ReserveAppendResponse {
+    OkResp(Wire<ReserveAppendOkResponse>),
+    ErrResp(Wire<ErrResponse>),
+}
+
+/// Reserve the range at the end of a file an append of a storage server is
+/// about to write, allocating its chunks
+#[post("/reserve_append", data = "<arg>")]
+pub async fn reserve_append(arg: Wire<ReserveAppendArg>) -> (Status, ReserveAppendResponse) {
+    let res = async {
+        let path = DfsPath::parse(&arg.path)?;
+        let _guard = lock::lock(&path, true).await?;
+        let (_, target) = dir_tree::lookup(&path).await?;
+        let Some(File::RegFile(file)) = target.as_deref() else {
+            return Err(TinyDfsError::FileNotFound);
+        };
+        let offset = file.size();
+        let located = locate_chunks(&GetStorageArg {
+            path: path.to_string(),
+            offset,
+            length: Some(arg.length),
+            write: true,
+        })
+        .await?;
+        // Fill the range with zeros on every replica first, so that a reader
+        // of the new size finds the bytes there, written or not
+        let end = offset + arg.length;
+        let mut chunks = located.chunks;
+        for location in &mut chunks {
+            let chunk = chunk::find_chunk(location.chunk).ok_or(TinyDfsError::FileNotFound)?;
+            let size = (end - location.offset).min(located.chunk_size);
+            replication::extend_replicas(&chunk, size).await?;
+            // Without the replicas dropped on the way
+            let srvs = chunk.servers();
+            location.servers.retain(|addr| {
+                srvs.iter().any(|srv| {
+                    srv.ip.host() == &addr.server_ip && srv.ip.port() == addr.server_port
+                })
+            });
+        }
+        // Then count the range in the size, so that the next append starts
+        // past it whether this one gets written or not
+        let now = unix_millis(SystemTime::now());
+        for location in &chunks {
+            let size = (end - location.offset).min(located.chunk_size);
+            let chunk = chunk::find_chunk(location.chunk).ok_or(TinyDfsError::FileNotFound)?;
+            chunk::record_write(chunk.id, size, now, chunk.epoch()).await?;
+        }
+        Ok(ReserveAppendOkResponse {
+            offset,
+            chunk_size: located.chunk_size,
+            chunks,
+        })
+    }
+    .await;
+    match res {
+        Ok(resp) => (Status::Ok, ReserveAppendResponse::OkResp(resp.into())),
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            (
+                status,
+                ReserveAppendResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            )
+        }
+    }
+}
@@ -1,32 +1,52 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::SystemTime};
 
-use rand::Rng;
-use rocket::{http::Status, serde::json::Json};
+use glob::{MatchOptions, Pattern};
+use once_cell::sync::Lazy;
+use rocket::{
+    futures::{future, stream::BoxStream, StreamExt},
+    http::{ContentType, Status},
+    response::{self, stream::TextStream, Responder},
+    serde::json,
+    tokio::sync::Mutex,
+    Request,
+};
 
 use crate::{
     common::{
         error::TinyDfsError,
+        path::DfsPath,
         service::{
-            CreateDirectoryArg, CreateDirectoryResponse, CreateFileArg, CreateFileResponse,
-            DeleteArg, DeleteResponse, GetStorageArg, GetStorageOkResponse, IsDirectoryArg,
-            IsDirectoryResponse, IsValidPathArg, IsValidPathResponse, ListArg, ListOkResponse,
-            ListResponse,
+            ChunkLocation, CreateDirectoryArg, CreateDirectoryResponse, CreateFileArg,
+            CreateFileResponse, DeleteArg, DeleteResponse, FileType, FindArg, FindEntry,
+            GetStorageArg, GetStorageOkResponse, IsDirectoryArg, IsDirectoryResponse,
+            IsValidPathArg, IsValidPathResponse, ListArg, ListEntry, ListOkResponse, ListResponse,
+            LockArg, LockOkResponse, LockResponse, RenameArg, RenameResponse, SetReplicationArg,
+            SetReplicationResponse, StatArg, StatOkResponse, StatResponse, StorageAddr, UnlockArg,
+            UnlockResponse,
         },
+        storage::{ChunkId, CreateChunkArg, DeleteChunkArg},
+        unix_millis,
+        wire::Wire,
         ErrResponse, OkResponse,
     },
     naming::{
-        dir_tree,
-        server::{select_random_server, StorageServer},
+        chunk::{self, Chunk},
+        dir_tree::{self, File},
+        lock, placement, repair, replication,
+        server::{select_servers, StorageServer},
     },
 };
 
 #[post("/is_valid_path", data = "<arg>")]
-pub async fn is_valid_path(arg: Json<IsValidPathArg>) -> (Status, Json<IsValidPathResponse>) {
+pub async fn is_valid_path(arg: Wire<IsValidPathArg>) -> (Status, Wire<IsValidPathResponse>) {
     let path = &arg.path;
     let mut resp = IsValidPathResponse { success: false };
 
-    let res = dir_tree::lookup(path).await;
-    if let Some((_, target)) = res.ok() {
+    let res = match DfsPath::parse(path) {
+        Ok(path) => dir_tree::lookup(&path).await,
+        Err(err) => Err(err),
+    };
+    if let Ok((_, target)) = res {
         if target.is_some() {
             log::debug!("path {:?} is valid", path);
             resp.success = true;
@@ -39,109 +59,244 @@ pub async fn is_valid_path(arg: Json<IsValidPathArg>) -> (Status, Json<IsValidPa
 
 #[derive(Responder)]
 pub enum GetStorageResponse {
-    OkResp(Json<GetStorageOkResponse>),
-    ErrResp(Json<ErrResponse>),
+    OkResp(Wire<GetStorageOkResponse>),
+    ErrResp(Wire<ErrResponse>),
 }
 
-/// TODO: achieve load-balancing
-fn select_one_server(srvs: &mut Vec<Arc<StorageServer>>) -> Arc<StorageServer> {
-    let mut rng = rand::thread_rng();
-    let idx = rng.gen_range(0..srvs.len());
-    srvs[idx].clone()
+/// Alive replicas of `chunk`, the one to read `path` from first
+fn order_replicas(key: &str, chunk: &Chunk) -> Vec<Arc<StorageServer>> {
+    let mut alive: Vec<Arc<StorageServer>> = chunk
+        .servers()
+        .into_iter()
+        .filter(|s| s.is_alive())
+        .collect();
+    if !alive.is_empty() {
+        let preferred = placement::policy().read(key, &alive);
+        let idx = alive
+            .iter()
+            .position(|s| Arc::ptr_eq(s, &preferred))
+            .unwrap();
+        alive.swap(0, idx);
+        // Suspect replicas only serve the reads the others can't
+        alive.sort_by_key(|s| chunk.is_suspect(s));
+    }
+    alive
 }
 
-#[post("/getstorage", data = "<arg>")]
-pub async fn get_storage_server(arg: Json<GetStorageArg>) -> (Status, GetStorageResponse) {
-    let res = dir_tree::lookup(&arg.path).await;
-    if res.is_err() {
-        return (
-            Status::NotFound,
-            GetStorageResponse::ErrResp(
-                ErrResponse {
-                    exception_type: "FileNotFoundException".to_string(),
-                    exception_info: format!("{} is invalid", &arg.path),
-                }
-                .into(),
-            ),
-        );
+/// Ask every one of `srvs` to create the chunk `id` of `chunk_size` bytes
+async fn create_chunk_replicas(id: ChunkId, chunk_size: u64, srvs: &[Arc<StorageServer>]) {
+    let mut tasks = Vec::new();
+    let client = reqwest::Client::new();
+    for srv in srvs {
+        let arg = CreateChunkArg {
+            chunk: id,
+            chunk_size,
+        };
+        let client = client.clone();
+        let addr = format!("http://{}/storage_create", srv.command_addr());
+        let task = rocket::tokio::spawn(async move {
+            match client.post(&addr).json(&arg).send().await {
+                Ok(resp) if resp.status().is_success() => {}
+                Ok(resp) => log::warn!(
+                    "create_chunk_replicas: {} chunk {}, status {:?}",
+                    addr,
+                    arg.chunk,
+                    resp.status()
+                ),
+                Err(err) => log::warn!(
+                    "create_chunk_replicas: {} chunk {}, err {:?}",
+                    addr,
+                    arg.chunk,
+                    err
+                ),
+            }
+        });
+        tasks.push(task);
     }
-    let (_, target) = res.ok().unwrap();
-    if let Some(target) = target {
-        let srv = target.for_all_servers(select_one_server);
-        (
-            Status::Ok,
-            GetStorageResponse::OkResp(
-                GetStorageOkResponse {
-                    server_ip: srv.ip.clone(),
-                    server_port: srv.client_port,
-                }
-                .into(),
-            ),
-        )
-    } else {
-        // The requested file doesn't exist
-        (
-            Status::NotFound,
-            GetStorageResponse::ErrResp(
-                ErrResponse {
-                    exception_type: "FileNotFoundException".to_string(),
-                    exception_info: format!("{} cannot be found", &arg.path),
-                }
-                .into(),
-            ),
-        )
+    for task in tasks {
+        task.await.unwrap();
     }
 }
 
-#[post("/delete", data = "<arg>")]
-pub async fn delete_file(arg: Json<DeleteArg>) -> (Status, DeleteResponse) {
-    if let Some(target) = dir_tree::delete_file(&arg.path).await.ok() {
-        // TODO: inform the storage server periodically
-        // Broadcast all storage servers to delete this file
-        let mut tasks = Vec::new();
-        target.for_all_servers(|servers| {
-            // TODO: use a more efficient way to inform all servers in parallel
-            for srv in servers {
-                let arg = DeleteArg {
-                    path: arg.path.clone(),
-                };
-                let client = reqwest::Client::new();
-                let addr = format!("http://{}:{}/storage_delete", srv.ip.0, srv.command_port);
-                let task = rocket::tokio::spawn(async move {
-                    let resp = client.post(addr).json(&arg).send().await.unwrap();
-                    assert!(resp.status().is_success());
-                });
-                tasks.push(task);
+/// Chunks of a file are appended one at a time
+static CHUNK_ALLOCATION: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Chunks a single write may add to a file, so that a write far past its
+/// end doesn't allocate every chunk in between
+const MAX_NEW_CHUNKS: u64 = 1024;
+
+/// Locate the chunks overlapping the byte range in `arg`, allocating the
+/// missing ones first if it is for writing
+pub async fn locate_chunks(arg: &GetStorageArg) -> Result<GetStorageOkResponse, TinyDfsError> {
+    let path = DfsPath::parse(&arg.path)?;
+    let (_, target) = dir_tree::lookup(&path).await?;
+    let Some(File::RegFile(file)) = target.as_deref() else {
+        return Err(TinyDfsError::FileNotFound);
+    };
+    let chunk_size = file.chunk_size();
+    let first = arg.offset / chunk_size;
+    let last = match arg.length {
+        Some(0) => None,
+        Some(length) => {
+            let end = arg.offset.checked_add(length - 1);
+            Some(end.ok_or(TinyDfsError::IndexOutOfBound)? / chunk_size)
+        }
+        None => (file.chunks().len() as u64).checked_sub(1),
+    };
+    if !arg.write {
+        let target = target.as_ref().unwrap();
+        target.mark_accessed(unix_millis(SystemTime::now()));
+        // Reads take no lock, they count here
+        replication::on_access(&path, false).await;
+    }
+    if let (true, Some(last)) = (arg.write, last) {
+        if last >= file.chunks().len() as u64 + MAX_NEW_CHUNKS {
+            log::warn!("locate_chunks: chunk {} is too far past the end", last);
+            return Err(TinyDfsError::IndexOutOfBound);
+        }
+        let _guard = CHUNK_ALLOCATION.lock().await;
+        let factor = repair::replication_factor(target.as_ref().unwrap().replication());
+        while file.chunks().len() as u64 <= last {
+            let key = placement::chunk_key(path.as_str(), file.chunks().len() as u64);
+            let srvs = select_servers(&key, factor).await;
+            if srvs.is_empty() {
+                return Err(TinyDfsError::NoServerAvailable);
             }
+            let chunk = dir_tree::add_chunk(&path, srvs.clone()).await?;
+            create_chunk_replicas(chunk.id, chunk_size, &srvs).await;
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let Some(last) = last else {
+        return Ok(GetStorageOkResponse { chunk_size, chunks });
+    };
+    for (idx, chunk) in file.chunks().iter().enumerate() {
+        let idx = idx as u64;
+        if idx < first || idx > last {
+            continue;
+        }
+        let servers = order_replicas(&placement::chunk_key(path.as_str(), idx), chunk);
+        if servers.is_empty() {
+            log::warn!("locate_chunks: chunk {} has no alive replica", chunk.id);
+            return Err(TinyDfsError::NoServerAvailable);
+        }
+        chunks.push(ChunkLocation {
+            chunk: chunk.id,
+            offset: idx * chunk_size,
+            servers: servers
+                .iter()
+                .map(|srv| StorageAddr {
+                    server_ip: srv.ip.host().clone(),
+                    server_port: srv.ip.port(),
+                })
+                .collect(),
         });
-        for task in tasks {
-            task.await.unwrap();
+    }
+    Ok(GetStorageOkResponse { chunk_size, chunks })
+}
+
+#[post("/getstorage", data = "<arg>")]
+pub async fn get_storage_server(arg: Wire<GetStorageArg>) -> (Status, GetStorageResponse) {
+    match locate_chunks(&arg).await {
+        Ok(resp) => (Status::Ok, GetStorageResponse::OkResp(resp.into())),
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            (
+                status,
+                GetStorageResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            )
         }
-        (
-            Status::Ok,
-            DeleteResponse::OkResp(OkResponse { success: true }.into()),
-        )
-    } else {
-        // Delete failed
-        (
-            Status::NotFound,
-            DeleteResponse::ErrResp(
-                ErrResponse {
-                    exception_type: "FileNotFoundException".to_string(),
-                    exception_info: "the object or parent directory does not exist.".to_string(),
+    }
+}
+
+#[post("/delete", data = "<arg>")]
+pub async fn delete_file(arg: Wire<DeleteArg>) -> (Status, DeleteResponse) {
+    let res: Result<_, TinyDfsError> = async {
+        let path = DfsPath::parse(&arg.path)?;
+        // Waits for the holders of the target, and whoever holds something under it
+        let _guard = lock::lock(&path, true).await?;
+        let target = dir_tree::delete_file(&path).await?;
+        Ok(target.reg_files(&path).await)
+    }
+    .await;
+    let files = match res {
+        Ok(files) => files,
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            return (
+                status,
+                DeleteResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            );
+        }
+    };
+    // TODO: inform the storage server periodically
+    // Broadcast the owners of every chunk under the target to delete it
+    let mut tasks = Vec::new();
+    let client = reqwest::Client::new();
+    let chunks = files.into_iter().flat_map(|f| f.1);
+    for chunk in chunks {
+        // TODO: use a more efficient way to inform all servers in parallel
+        for srv in chunk.servers() {
+            let arg = DeleteChunkArg { chunk: chunk.id };
+            let client = client.clone();
+            let addr = format!("http://{}/storage_delete", srv.command_addr());
+            let task = rocket::tokio::spawn(async move {
+                match client.post(&addr).json(&arg).send().await {
+                    Ok(resp) if resp.status().is_success() => {}
+                    Ok(resp) => {
+                        log::warn!(
+                            "delete_file: {} chunk {}, status {:?}",
+                            addr,
+                            arg.chunk,
+                            resp.status()
+                        )
+                    }
+                    Err(err) => {
+                        log::warn!("delete_file: {} chunk {}, err {:?}", addr, arg.chunk, err)
+                    }
                 }
-                .into(),
-            ),
-        )
+            });
+            tasks.push(task);
+        }
     }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    (
+        Status::Ok,
+        DeleteResponse::OkResp(OkResponse { success: true }.into()),
+    )
 }
 
 #[post("/create_directory", data = "<arg>")]
-pub async fn create_directory(arg: Json<CreateDirectoryArg>) -> (Status, CreateDirectoryResponse) {
-    match dir_tree::create_file(&arg.path, true, None, false).await {
+pub async fn create_directory(arg: Wire<CreateDirectoryArg>) -> (Status, CreateDirectoryResponse) {
+    let res = async {
+        let path = DfsPath::parse(&arg.path)?;
+        let replication = match arg.replication {
+            Some(0) => return Err(TinyDfsError::ReplicationInvalid),
+            replication => replication,
+        };
+        let owner = arg.owner.clone();
+        dir_tree::create_file(&path, true, Vec::new(), replication, owner, false).await
+    }
+    .await;
+    match res {
         Err(err) => {
             let (status, exception_type, exception_info) = err.exception();
-            return (
+            (
                 status,
                 CreateDirectoryResponse::ErrResp(
                     ErrResponse {
@@ -150,25 +305,38 @@ pub async fn create_directory(arg: Json<CreateDirectoryArg>) -> (Status, CreateD
                     }
                     .into(),
                 ),
-            );
-        }
-        Ok(_) => {
-            return (
-                Status::Ok,
-                CreateDirectoryResponse::OkResp(OkResponse { success: true }.into()),
-            );
+            )
         }
+        Ok(_) => (
+            Status::Ok,
+            CreateDirectoryResponse::OkResp(OkResponse { success: true }.into()),
+        ),
     }
 }
 
 #[post("/create_file", data = "<arg>")]
-pub async fn create_file(arg: Json<CreateFileArg>) -> (Status, CreateFileResponse) {
-    let srv = select_random_server().await;
-    assert!(srv.is_some());
-    match dir_tree::create_file(&arg.path, false, srv, false).await {
+pub async fn create_file(arg: Wire<CreateFileArg>) -> (Status, CreateFileResponse) {
+    let res = async {
+        let path = DfsPath::parse(&arg.path)?;
+        let replication = match arg.replication {
+            Some(0) => return Err(TinyDfsError::ReplicationInvalid),
+            Some(replication) => Some(replication),
+            None => dir_tree::inherited_replication(&path).await,
+        };
+        // Less servers than wanted are fine, the repair task adds the others
+        let factor = repair::replication_factor(replication);
+        let srvs = select_servers(&placement::chunk_key(path.as_str(), 0), factor).await;
+        if srvs.is_empty() {
+            return Err(TinyDfsError::NoServerAvailable);
+        }
+        let owner = arg.owner.clone();
+        dir_tree::create_file(&path, false, srvs, replication, owner, false).await
+    }
+    .await;
+    match res {
         Err(err) => {
             let (status, exception_type, exception_info) = err.exception();
-            return (
+            (
                 status,
                 CreateFileResponse::ErrResp(
                     ErrResponse {
@@ -177,39 +345,25 @@ pub async fn create_file(arg: Json<CreateFileArg>) -> (Status, CreateFileRespons
                     }
                     .into(),
                 ),
-            );
+            )
         }
         Ok(target) => {
-            // Broadcast all storage servers to create this file
-            let mut tasks = Vec::new();
-            target.for_all_servers(|servers| {
-                // TODO: use a more efficient way to inform all servers in parallel
-                for srv in servers {
-                    let arg = CreateFileArg {
-                        path: arg.path.clone(),
-                    };
-                    let client = reqwest::Client::new();
-                    let addr = format!("http://{}:{}/storage_create", srv.ip.0, srv.command_port);
-                    let task = rocket::tokio::spawn(async move {
-                        let resp = client.post(addr).json(&arg).send().await.unwrap();
-                        assert!(resp.status().is_success());
-                    });
-                    tasks.push(task);
+            // Let the owners of the first chunk create it
+            if let File::RegFile(file) = target.as_ref() {
+                for chunk in file.chunks() {
+                    create_chunk_replicas(chunk.id, file.chunk_size(), &chunk.servers()).await;
                 }
-            });
-            for task in tasks {
-                task.await.unwrap();
             }
-            return (
+            (
                 Status::Ok,
                 CreateFileResponse::OkResp(OkResponse { success: true }.into()),
-            );
+            )
         }
     }
 }
 
 #[post("/list", data = "<arg>")]
-pub async fn list_dir(arg: Json<ListArg>) -> (Status, ListResponse) {
+pub async fn list_dir(arg: Wire<ListArg>) -> (Status, ListResponse) {
     let err_ret = |err: TinyDfsError| {
         let (status, etype, einfo) = err.exception();
         (
@@ -223,24 +377,161 @@ pub async fn list_dir(arg: Json<ListArg>) -> (Status, ListResponse) {
             ),
         )
     };
-    let res = dir_tree::lookup(&arg.path).await;
+    let Ok(path) = DfsPath::parse(&arg.path) else {
+        return err_ret(TinyDfsError::PathInvalid);
+    };
+    let res = dir_tree::lookup(&path).await;
     if res.is_err() {
         return err_ret(TinyDfsError::PathInvalid);
     }
     let (_, target) = res.unwrap();
-    if let Some(dir) = target {
-        let files = dir.list().await;
-        (
-            Status::Ok,
-            ListResponse::OkResp(ListOkResponse { files }.into()),
-        )
+    let Some(dir) = target else {
+        return err_ret(TinyDfsError::FileNotFound);
+    };
+    if let File::RegFile(_) = dir.as_ref() {
+        return err_ret(TinyDfsError::DirNotFound);
+    }
+    let limit = arg.limit.unwrap_or(usize::MAX).max(1);
+    // One more to tell whether another page follows
+    let mut children = dir
+        .list(arg.start_after.as_deref(), limit.saturating_add(1))
+        .await;
+    let continuation_token = if children.len() > limit {
+        children.truncate(limit);
+        children.last().map(|(name, _)| name.clone())
     } else {
-        err_ret(TinyDfsError::FileNotFound)
+        None
+    };
+    let entries = arg.detailed.then(|| {
+        children
+            .iter()
+            .map(|(name, child)| {
+                let (file_type, size) = match child.as_ref() {
+                    File::RegFile(file) => (FileType::File, file.size()),
+                    File::Dir(_) => (FileType::Dir, 0),
+                };
+                ListEntry {
+                    name: name.clone(),
+                    file_type,
+                    size,
+                    modified: child.times().modified,
+                }
+            })
+            .collect()
+    });
+    let files = children.into_iter().map(|(name, _)| name).collect();
+    (
+        Status::Ok,
+        ListResponse::OkResp(
+            ListOkResponse {
+                files,
+                entries,
+                continuation_token,
+            }
+            .into(),
+        ),
+    )
+}
+
+pub enum FindResponse {
+    OkResp((ContentType, TextStream<BoxStream<'static, String>>)),
+    ErrResp(Wire<ErrResponse>),
+}
+
+// Derived responders need a response outliving the request, which streams
+// do not provide
+impl<'r> Responder<'r, 'r> for FindResponse {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'r> {
+        match self {
+            FindResponse::OkResp(stream) => stream.respond_to(req),
+            FindResponse::ErrResp(err) => err.respond_to(req),
+        }
+    }
+}
+
+/// `file` at `path` as reported by `/find`
+fn find_entry(path: String, file: &File) -> FindEntry {
+    let (file_type, size) = match file {
+        File::RegFile(f) => (FileType::File, f.size()),
+        File::Dir(_) => (FileType::Dir, 0),
+    };
+    FindEntry {
+        path,
+        file_type,
+        size,
+        modified: file.times().modified,
     }
 }
 
+/// Whether `entry` passes the filters of `arg`, `pattern` being the parsed one
+fn find_matches(arg: &FindArg, pattern: Option<&Pattern>, entry: &FindEntry) -> bool {
+    let name_matches = pattern.is_none_or(|pattern| {
+        if pattern.as_str().contains('/') {
+            let options = MatchOptions {
+                require_literal_separator: true,
+                ..MatchOptions::new()
+            };
+            pattern.matches_with(&entry.path, options)
+        } else {
+            let name = entry.path.rsplit('/').next().unwrap_or_default();
+            pattern.matches(name)
+        }
+    });
+    name_matches
+        && arg.file_type.is_none_or(|t| t == entry.file_type)
+        && arg.min_size.is_none_or(|min| entry.size >= min)
+        && arg.max_size.is_none_or(|max| entry.size <= max)
+        && arg.modified_after.is_none_or(|t| entry.modified >= t)
+        && arg.modified_before.is_none_or(|t| entry.modified <= t)
+}
+
+/// Stream the files under `arg.path` passing its filters as newline-delimited JSON
+#[post("/find", data = "<arg>")]
+pub async fn find(arg: Wire<FindArg>) -> (Status, FindResponse) {
+    let err_ret = |err: TinyDfsError| {
+        let (status, etype, einfo) = err.exception();
+        (
+            status,
+            FindResponse::ErrResp(
+                ErrResponse {
+                    exception_info: einfo.to_string(),
+                    exception_type: etype.to_string(),
+                }
+                .into(),
+            ),
+        )
+    };
+    let path = match DfsPath::parse(&arg.path) {
+        Ok(path) => path,
+        Err(err) => return err_ret(err),
+    };
+    let root = match dir_tree::lookup(&path).await {
+        Ok((_, Some(root))) => root,
+        Ok((_, None)) => return err_ret(TinyDfsError::FileNotFound),
+        Err(err) => return err_ret(err),
+    };
+    let pattern = match arg.pattern.as_deref().map(Pattern::new).transpose() {
+        Ok(pattern) => pattern,
+        Err(_) => return err_ret(TinyDfsError::PatternInvalid),
+    };
+    let arg = arg.into_inner();
+    let lines = dir_tree::walk(&path, root).filter_map(move |(path, file)| {
+        let entry = find_entry(path.into(), &file);
+        let line = find_matches(&arg, pattern.as_ref(), &entry)
+            .then(|| format!("{}\n", json::to_string(&entry).unwrap()));
+        future::ready(line)
+    });
+    (
+        Status::Ok,
+        FindResponse::OkResp((
+            ContentType::new("application", "x-ndjson"),
+            TextStream(lines.boxed()),
+        )),
+    )
+}
+
 #[post("/is_directory", data = "<arg>")]
-pub async fn is_directory(arg: Json<IsDirectoryArg>) -> (Status, IsDirectoryResponse) {
+pub async fn is_directory(arg: Wire<IsDirectoryArg>) -> (Status, IsDirectoryResponse) {
     let err_ret = |err: TinyDfsError| {
         let (status, etype, einfo) = err.exception();
         (
@@ -254,16 +545,18 @@ pub async fn is_directory(arg: Json<IsDirectoryArg>) -> (Status, IsDirectoryResp
             ),
         )
     };
-    let res = dir_tree::lookup(&arg.path).await;
+    let Ok(path) = DfsPath::parse(&arg.path) else {
+        return err_ret(TinyDfsError::PathInvalid);
+    };
+    let res = dir_tree::lookup(&path).await;
     if res.is_err() {
         return err_ret(TinyDfsError::PathInvalid);
     }
     let (_, target) = res.unwrap();
     if let Some(target) = target {
-        let is_dir: bool;
-        match target.as_ref() {
-            dir_tree::File::RegFile(_) => is_dir = false,
-            dir_tree::File::Dir(_) => is_dir = true,
+        let is_dir = match target.as_ref() {
+            dir_tree::File::RegFile(_) => false,
+            dir_tree::File::Dir(_) => true,
         };
         (
             Status::Ok,
@@ -273,3 +566,218 @@ pub async fn is_directory(arg: Json<IsDirectoryArg>) -> (Status, IsDirectoryResp
         err_ret(TinyDfsError::FileNotFound)
     }
 }
+
+#[post("/stat", data = "<arg>")]
+pub async fn stat(arg: Wire<StatArg>) -> (Status, StatResponse) {
+    let err_ret = |err: TinyDfsError| {
+        let (status, etype, einfo) = err.exception();
+        (
+            status,
+            StatResponse::ErrResp(
+                ErrResponse {
+                    exception_info: einfo.to_string(),
+                    exception_type: etype.to_string(),
+                }
+                .into(),
+            ),
+        )
+    };
+    let path = match DfsPath::parse(&arg.path) {
+        Ok(path) => path,
+        Err(err) => return err_ret(err),
+    };
+    let target = match dir_tree::lookup(&path).await {
+        Ok((_, Some(target))) => target,
+        Ok((_, None)) => return err_ret(TinyDfsError::FileNotFound),
+        Err(err) => return err_ret(err),
+    };
+    let (file_type, size, replicas) = match target.as_ref() {
+        File::RegFile(file) => {
+            let replicas = file
+                .chunks()
+                .iter()
+                .map(|chunk| chunk.servers().iter().filter(|s| s.is_alive()).count())
+                .min()
+                .unwrap_or(0);
+            (FileType::File, file.size(), replicas)
+        }
+        File::Dir(_) => (FileType::Dir, 0, 0),
+    };
+    let times = target.times();
+    (
+        Status::Ok,
+        StatResponse::OkResp(
+            StatOkResponse {
+                file_type,
+                size,
+                created: times.created,
+                modified: times.modified,
+                accessed: times.accessed,
+                replicas,
+                owner: target.owner(),
+            }
+            .into(),
+        ),
+    )
+}
+
+#[post("/rename", data = "<arg>")]
+pub async fn rename(arg: Wire<RenameArg>) -> (Status, RenameResponse) {
+    let res = async {
+        let src_path = DfsPath::parse(&arg.src_path)?;
+        let dst_path = DfsPath::parse(&arg.dst_path)?;
+        // Waits for the holders of either path, and whoever holds something under them
+        let _guard = lock::lock_all(&[(&src_path, true), (&dst_path, true)]).await;
+        dir_tree::rename(&src_path, &dst_path).await
+    }
+    .await;
+    match res {
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            (
+                status,
+                RenameResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            )
+        }
+        // Chunks are not named after paths, storage servers have nothing to do
+        Ok(_) => (
+            Status::Ok,
+            RenameResponse::OkResp(OkResponse { success: true }.into()),
+        ),
+    }
+}
+
+#[post("/lock", data = "<arg>")]
+pub async fn lock_path(arg: Wire<LockArg>) -> (Status, LockResponse) {
+    let res: Result<u64, TinyDfsError> = async {
+        let path = DfsPath::parse(&arg.path)?;
+        let guard = lock::lock(&path, arg.exclusive).await?;
+        replication::on_access(&path, arg.exclusive).await;
+        Ok(lock::hold(guard))
+    }
+    .await;
+    match res {
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            (
+                status,
+                LockResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            )
+        }
+        Ok(token) => (
+            Status::Ok,
+            LockResponse::OkResp(
+                LockOkResponse {
+                    success: true,
+                    token,
+                }
+                .into(),
+            ),
+        ),
+    }
+}
+
+/// Count the bytes up to `end` of the file at `path` in its size, as written
+/// by the holder of its exclusive lock. The end is only taken as far as the
+/// chunk it falls in holds and its replicas have the bytes.
+async fn record_written_end(path: &DfsPath, end: u64) -> Result<(), TinyDfsError> {
+    let (_, target) = dir_tree::lookup(path).await?;
+    let Some(File::RegFile(file)) = target.as_deref() else {
+        return Err(TinyDfsError::FileNotFound);
+    };
+    if end == 0 {
+        return Ok(());
+    }
+    let idx = (end - 1) / file.chunk_size();
+    let Some(chunk) = file.chunks().get(idx as usize).cloned() else {
+        return Err(TinyDfsError::IndexOutOfBound);
+    };
+    let Some(stored) = replication::replica_size(&chunk).await else {
+        // Left to the heartbeats of the replicas
+        return Ok(());
+    };
+    let size = (end - idx * file.chunk_size()).min(stored);
+    let now = unix_millis(SystemTime::now());
+    chunk::record_write(chunk.id, size, now, chunk.epoch()).await
+}
+
+#[post("/unlock", data = "<arg>")]
+pub async fn unlock_path(arg: Wire<UnlockArg>) -> (Status, UnlockResponse) {
+    let res = async {
+        let path = DfsPath::parse(&arg.path)?;
+        let guard = lock::take_held(&path, arg.exclusive, arg.token)?;
+        // Under the lock, so that no truncation or append comes in between
+        let res = match arg.written_end {
+            Some(end) if arg.exclusive => record_written_end(&path, end).await,
+            _ => Ok(()),
+        };
+        drop(guard);
+        res
+    }
+    .await;
+    match res {
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            (
+                status,
+                UnlockResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            )
+        }
+        Ok(_) => (
+            Status::Ok,
+            UnlockResponse::OkResp(OkResponse { success: true }.into()),
+        ),
+    }
+}
+
+#[post("/set_replication", data = "<arg>")]
+pub async fn set_replication(arg: Wire<SetReplicationArg>) -> (Status, SetReplicationResponse) {
+    let res: Result<(), TinyDfsError> = async {
+        let path = DfsPath::parse(&arg.path)?;
+        if arg.replication == 0 {
+            return Err(TinyDfsError::ReplicationInvalid);
+        }
+        let target = dir_tree::set_replication(&path, arg.replication).await?;
+        // Replicas are added or dropped in the background
+        repair::resize(target, &path);
+        Ok(())
+    }
+    .await;
+    match res {
+        Err(err) => {
+            let (status, etype, einfo) = err.exception();
+            (
+                status,
+                SetReplicationResponse::ErrResp(
+                    ErrResponse {
+                        exception_type: etype.to_string(),
+                        exception_info: einfo.to_string(),
+                    }
+                    .into(),
+                ),
+            )
+        }
+        Ok(_) => (
+            Status::Ok,
+            SetReplicationResponse::OkResp(OkResponse { success: true }.into()),
+        ),
+    }
+}
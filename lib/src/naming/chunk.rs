@@ -0,0 +1,340 @@
+//! Chunks, the pieces regular files are split into.
+//!
+//! Every chunk has an id of its own, which is the name storage servers keep
+//! it under, and its own set of replicas. All chunks live in a table so that
+//! they can be found by id when storage servers report what they hold.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use once_cell::sync::Lazy;
+
+use crate::common::{error::TinyDfsError, storage::ChunkId};
+
+use super::{
+    journal::{self, Operation, ServerRecord},
+    repair,
+    server::StorageServer,
+};
+
+/// Bytes of every chunk but the last one of the files created from now on
+static CHUNK_SIZE: AtomicU64 = AtomicU64::new(64 << 20);
+
+pub fn set_chunk_size(size: u64) {
+    CHUNK_SIZE.store(size.max(1), Ordering::Relaxed);
+}
+
+pub fn chunk_size() -> u64 {
+    CHUNK_SIZE.load(Ordering::Relaxed)
+}
+
+pub struct Chunk {
+    pub id: ChunkId,
+    /// Several servers may own this chunk
+    srvs: std::sync::Mutex<Vec<Arc<StorageServer>>>,
+    /// Owners which found their replica corrupted, kept as no healthy one
+    /// was left. Not journaled, the scrubbers report them again.
+    suspects: std::sync::Mutex<Vec<Arc<StorageServer>>>,
+    /// Bytes in the chunk, as reported by its replicas after writes
+    size: AtomicU64,
+    /// Milliseconds since the Unix epoch of the last reported write
+    modified: AtomicU64,
+    /// Truncations so far. Replicas report their writes with the epoch they
+    /// know, so that a size read before a truncation cannot grow it back.
+    epoch: AtomicU64,
+}
+
+impl Chunk {
+    pub fn servers(&self) -> Vec<Arc<StorageServer>> {
+        self.srvs.lock().unwrap().clone()
+    }
+
+    pub fn has_server(&self, srv: &Arc<StorageServer>) -> bool {
+        self.srvs
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|s| Arc::ptr_eq(s, srv))
+    }
+
+    pub fn add_server(&self, srv: Arc<StorageServer>) {
+        let mut srvs = self.srvs.lock().unwrap();
+        if !srvs.iter().any(|s| Arc::ptr_eq(s, &srv)) {
+            srvs.push(srv);
+        }
+    }
+
+    pub fn remove_server(&self, srv: &Arc<StorageServer>) {
+        self.srvs.lock().unwrap().retain(|s| !Arc::ptr_eq(s, srv));
+        self.suspects
+            .lock()
+            .unwrap()
+            .retain(|s| !Arc::ptr_eq(s, srv));
+    }
+
+    pub fn is_suspect(&self, srv: &Arc<StorageServer>) -> bool {
+        self.suspects
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|s| Arc::ptr_eq(s, srv))
+    }
+
+    pub fn has_suspect(&self) -> bool {
+        !self.suspects.lock().unwrap().is_empty()
+    }
+
+    fn mark_suspect(&self, srv: Arc<StorageServer>) {
+        let mut suspects = self.suspects.lock().unwrap();
+        if !suspects.iter().any(|s| Arc::ptr_eq(s, &srv)) {
+            suspects.push(srv);
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    pub fn modified(&self) -> u64 {
+        self.modified.load(Ordering::Relaxed)
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Relaxed)
+    }
+
+    pub fn server_records(&self) -> Vec<ServerRecord> {
+        self.srvs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|srv| srv.as_ref().into())
+            .collect()
+    }
+}
+
+/// Chunk id => chunk
+static CHUNK_TABLE: Lazy<std::sync::Mutex<HashMap<ChunkId, Arc<Chunk>>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// Id of the next new chunk. Ids are never reused, so that a stale replica
+/// left on a storage server cannot be taken for a new chunk.
+static NEXT_CHUNK_ID: AtomicU64 = AtomicU64::new(1);
+
+pub fn next_chunk_id() -> ChunkId {
+    NEXT_CHUNK_ID.load(Ordering::Relaxed)
+}
+
+/// Make sure no chunk gets an id below `id` from now on
+pub fn reserve_chunk_ids(id: ChunkId) {
+    NEXT_CHUNK_ID.fetch_max(id, Ordering::Relaxed);
+}
+
+pub fn alloc_chunk_id() -> ChunkId {
+    NEXT_CHUNK_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Put a chunk owned by `srvs` into the table
+pub fn insert_chunk(id: ChunkId, srvs: Vec<Arc<StorageServer>>) -> Arc<Chunk> {
+    reserve_chunk_ids(id + 1);
+    let chunk = Arc::new(Chunk {
+        id,
+        srvs: std::sync::Mutex::new(srvs),
+        suspects: std::sync::Mutex::new(Vec::new()),
+        size: AtomicU64::new(0),
+        modified: AtomicU64::new(0),
+        epoch: AtomicU64::new(0),
+    });
+    CHUNK_TABLE.lock().unwrap().insert(id, chunk.clone());
+    chunk
+}
+
+pub fn remove_chunk(id: ChunkId) {
+    CHUNK_TABLE.lock().unwrap().remove(&id);
+}
+
+pub fn find_chunk(id: ChunkId) -> Option<Arc<Chunk>> {
+    CHUNK_TABLE.lock().unwrap().get(&id).cloned()
+}
+
+/// All chunks owned by `srv`
+pub fn chunks_of(srv: &Arc<StorageServer>) -> Vec<Arc<Chunk>> {
+    CHUNK_TABLE
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|chunk| chunk.has_server(srv))
+        .cloned()
+        .collect()
+}
+
+/// Add `srv` to the servers owning the chunk `id`
+pub async fn add_replica(id: ChunkId, srv: Arc<StorageServer>) -> Result<(), TinyDfsError> {
+    log::debug!("add_replica: chunk {}, server {:?}", id, srv.ip);
+    let op = Operation::AddReplica {
+        chunk: id,
+        server: srv.as_ref().into(),
+    };
+    journal::commit(op, || async { apply_add_replica(id, srv) }).await
+}
+
+/// Add a replica without logging, used by both `add_replica` and the journal replay
+pub fn apply_add_replica(id: ChunkId, srv: Arc<StorageServer>) -> Result<(), TinyDfsError> {
+    let chunk = find_chunk(id).ok_or(TinyDfsError::FileNotFound)?;
+    chunk.add_server(srv);
+    Ok(())
+}
+
+/// Remove `srv` from the servers owning the chunk `id`
+pub async fn remove_replica(id: ChunkId, srv: Arc<StorageServer>) -> Result<(), TinyDfsError> {
+    log::debug!("remove_replica: chunk {}, server {:?}", id, srv.ip);
+    let op = Operation::RemoveReplica {
+        chunk: id,
+        server: srv.as_ref().into(),
+    };
+    journal::commit(op, || async { apply_remove_replica(id, srv) }).await
+}
+
+/// Remove a replica without logging, used by both `remove_replica` and the journal replay
+pub fn apply_remove_replica(id: ChunkId, srv: Arc<StorageServer>) -> Result<(), TinyDfsError> {
+    let chunk = find_chunk(id).ok_or(TinyDfsError::FileNotFound)?;
+    chunk.remove_server(&srv);
+    Ok(())
+}
+
+/// Record a write to the chunk `id` reported by one of its replicas at
+/// truncation `epoch`. Chunks only grow between truncations, so the largest
+/// size and the latest time win, and reports from before the last truncation
+/// are dropped. Only a growth is journaled, a newer time alone waits for the
+/// next snapshot.
+pub async fn record_write(
+    id: ChunkId,
+    size: u64,
+    modified: u64,
+    epoch: u64,
+) -> Result<(), TinyDfsError> {
+    let chunk = find_chunk(id).ok_or(TinyDfsError::FileNotFound)?;
+    if epoch < chunk.epoch() {
+        log::debug!(
+            "record_write: chunk {}, epoch {} stale, now {}",
+            id,
+            epoch,
+            chunk.epoch()
+        );
+        return Ok(());
+    }
+    if size <= chunk.size() {
+        // An overwrite, or another replica reporting the same write
+        chunk.modified.fetch_max(modified, Ordering::Relaxed);
+        return Ok(());
+    }
+    log::debug!(
+        "record_write: chunk {}, size {}, modified {}, epoch {}",
+        id,
+        size,
+        modified,
+        epoch
+    );
+    let op = Operation::WriteChunk {
+        chunk: id,
+        size,
+        modified,
+        epoch: Some(epoch),
+    };
+    journal::commit(op, || async {
+        apply_write(id, size, modified, Some(epoch))
+    })
+    .await
+}
+
+/// Record a write without logging, used by both `record_write` and the journal
+/// replay. The journal is locked around the check, so that no truncation
+/// comes in between. A write without an epoch is from an older journal.
+pub fn apply_write(
+    id: ChunkId,
+    size: u64,
+    modified: u64,
+    epoch: Option<u64>,
+) -> Result<(), TinyDfsError> {
+    let chunk = find_chunk(id).ok_or(TinyDfsError::FileNotFound)?;
+    if let Some(epoch) = epoch {
+        if epoch < chunk.epoch() {
+            return Ok(());
+        }
+        // Only a snapshot is ahead, restoring the epoch
+        chunk.epoch.fetch_max(epoch, Ordering::Relaxed);
+    }
+    chunk.size.fetch_max(size, Ordering::Relaxed);
+    chunk.modified.fetch_max(modified, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Cut the chunk `id` to at most `size` bytes without logging, part of
+/// truncating its file. Starts a new epoch, the writes reported before are
+/// stale.
+pub fn apply_truncate(id: ChunkId, size: u64) -> Result<(), TinyDfsError> {
+    let chunk = find_chunk(id).ok_or(TinyDfsError::FileNotFound)?;
+    chunk.size.fetch_min(size, Ordering::Relaxed);
+    chunk.epoch.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Forget the replicas of `ids` on `srv`, which has found them corrupted,
+/// and have them copied from the healthy ones. A replica without a healthy
+/// one left is the best copy there is, so it is kept as a suspect until the
+/// repair task has made a healthy one. Return the chunks kept.
+pub async fn drop_corrupt(
+    ids: &[ChunkId],
+    srv: Arc<StorageServer>,
+) -> Result<Vec<ChunkId>, TinyDfsError> {
+    let mut kept = Vec::new();
+    for id in ids {
+        let Some(chunk) = find_chunk(*id).filter(|chunk| chunk.has_server(&srv)) else {
+            continue;
+        };
+        let healthy = chunk
+            .servers()
+            .iter()
+            .any(|s| !Arc::ptr_eq(s, &srv) && s.is_alive() && !chunk.is_suspect(s));
+        if healthy {
+            log::warn!("drop_corrupt: chunk {} corrupted on {:?}", id, srv.ip);
+            remove_replica(*id, srv.clone()).await?;
+        } else {
+            log::warn!(
+                "drop_corrupt: chunk {} corrupted on {:?}, no healthy replica left, keep it",
+                id,
+                srv.ip
+            );
+            chunk.mark_suspect(srv.clone());
+            kept.push(*id);
+        }
+    }
+    rocket::tokio::spawn(repair::repair());
+    Ok(kept)
+}
+
+/// Match the chunks stored by `srv` against the table. Forget the replicas
+/// it has lost and return the chunks it should delete.
+pub async fn collect_chunks(
+    stored: &[ChunkId],
+    srv: Arc<StorageServer>,
+) -> Result<Vec<ChunkId>, TinyDfsError> {
+    let stale: Vec<ChunkId> = stored
+        .iter()
+        .filter(|id| !find_chunk(**id).is_some_and(|chunk| chunk.has_server(&srv)))
+        .cloned()
+        .collect();
+    let stored: HashSet<ChunkId> = stored.iter().cloned().collect();
+    for chunk in chunks_of(&srv) {
+        if !stored.contains(&chunk.id) {
+            log::warn!("collect_chunks: chunk {} lost by {:?}", chunk.id, srv.ip);
+            remove_replica(chunk.id, srv.clone()).await?;
+        }
+    }
+    Ok(stale)
+}
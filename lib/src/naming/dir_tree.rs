@@ -1,11 +1,64 @@
-use std::{collections::BTreeMap, future::Future, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
 
 use once_cell::sync::Lazy;
-use rocket::tokio::sync::Mutex;
+use rocket::{
+    futures::{stream, Stream},
+    tokio::sync::Mutex,
+};
 
-use crate::common::error::TinyDfsError;
+use crate::common::{error::TinyDfsError, path::DfsPath, storage::ChunkId, unix_millis};
 
-use super::server::StorageServer;
+use super::{
+    chunk::{self, Chunk},
+    journal::{self, ChunkRecord, Operation},
+    server::StorageServer,
+};
+
+/// Milliseconds since the Unix epoch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Times {
+    pub created: u64,
+    /// Of the content of a file, or of the entries of a dir
+    pub modified: u64,
+    /// Kept in memory only between two checkpoints
+    pub accessed: u64,
+}
+
+impl Times {
+    fn new(created: u64) -> Self {
+        Times {
+            created,
+            modified: created,
+            accessed: created,
+        }
+    }
+}
+
+/// Who created a file and when
+#[derive(Debug, Clone, Default)]
+pub struct Creation {
+    pub owner: Option<String>,
+    /// Milliseconds since the Unix epoch, now if 0
+    pub time: u64,
+}
+
+impl Creation {
+    fn times(&self) -> Times {
+        match self.time {
+            0 => Times::new(unix_millis(SystemTime::now())),
+            time => Times::new(time),
+        }
+    }
+}
 
 pub enum File {
     RegFile(RegFile),
@@ -13,23 +66,73 @@ pub enum File {
 }
 
 impl File {
-    fn name(&self) -> &str {
+    fn name(&self) -> String {
         match self {
-            File::RegFile(f) => &f.name,
-            File::Dir(f) => &f.name,
+            File::RegFile(f) => f.name.lock().unwrap().clone(),
+            File::Dir(f) => f.name.lock().unwrap().clone(),
         }
     }
 
-    pub fn for_all_servers<F, T>(&self, mut func: F) -> T
-    where
-        F: FnMut(&mut Vec<Arc<StorageServer>>) -> T,
-    {
+    fn set_name(&self, name: &str) {
+        let mut guard = match self {
+            File::RegFile(f) => f.name.lock().unwrap(),
+            File::Dir(f) => f.name.lock().unwrap(),
+        };
+        *guard = name.to_string();
+    }
+
+    /// Replication factor of a file, or the one inherited by the new
+    /// children of a dir. None means the cluster default.
+    pub fn replication(&self) -> Option<usize> {
         match self {
-            File::RegFile(f) => {
-                let mut servers = f.srvs.lock().unwrap();
-                func(&mut servers)
-            }
-            File::Dir(_) => panic!(),
+            File::RegFile(f) => *f.replication.lock().unwrap(),
+            File::Dir(f) => *f.replication.lock().unwrap(),
+        }
+    }
+
+    fn set_replication(&self, replication: usize) {
+        let mut guard = match self {
+            File::RegFile(f) => f.replication.lock().unwrap(),
+            File::Dir(f) => f.replication.lock().unwrap(),
+        };
+        *guard = Some(replication);
+    }
+
+    fn raw_times(&self) -> &std::sync::Mutex<Times> {
+        match self {
+            File::RegFile(f) => &f.times,
+            File::Dir(f) => &f.times,
+        }
+    }
+
+    /// The times of a regular file count the writes to its chunks
+    pub fn times(&self) -> Times {
+        let mut times = *self.raw_times().lock().unwrap();
+        if let File::RegFile(f) = self {
+            let written = f.chunks().iter().map(|chunk| chunk.modified()).max();
+            times.modified = times.modified.max(written.unwrap_or(0));
+            times.accessed = times.accessed.max(times.modified);
+        }
+        times
+    }
+
+    /// Note a change of the entries of a dir, or of the length of a file, at `time`
+    fn touch(&self, time: u64) {
+        let mut times = self.raw_times().lock().unwrap();
+        times.modified = times.modified.max(time);
+        times.accessed = times.accessed.max(time);
+    }
+
+    /// Note a read at `time`, which isn't logged
+    pub fn mark_accessed(&self, time: u64) {
+        let mut times = self.raw_times().lock().unwrap();
+        times.accessed = times.accessed.max(time);
+    }
+
+    pub fn owner(&self) -> Option<String> {
+        match self {
+            File::RegFile(f) => f.owner.clone(),
+            File::Dir(f) => f.owner.clone(),
         }
     }
 
@@ -47,51 +150,127 @@ impl File {
         }
     }
 
-    async fn create_file(
-        &self,
-        child: &str,
-        is_dir: bool,
-        srv: Option<Arc<StorageServer>>,
-    ) -> Arc<File> {
+    async fn insert(&self, file: Arc<File>) -> Arc<File> {
         match self {
             File::RegFile(_) => panic!(),
-            File::Dir(f) => f.create_file(child, is_dir, srv).await,
+            File::Dir(f) => f.insert(file).await,
         }
     }
 
-    pub async fn list(&self) -> Vec<String> {
+    /// Collect all regular files under this one (itself included) located
+    /// at `path`, along with their chunks and replication
+    pub async fn reg_files(
+        self: &Arc<Self>,
+        path: &DfsPath,
+    ) -> Vec<(DfsPath, Vec<Arc<Chunk>>, Option<usize>)> {
+        let mut files = Vec::new();
+        let mut stack = vec![(path.clone(), self.clone())];
+        while let Some((path, file)) = stack.pop() {
+            match file.as_ref() {
+                File::RegFile(f) => files.push((path, f.chunks(), *f.replication.lock().unwrap())),
+                File::Dir(d) => {
+                    for (name, child) in d.children.lock().await.iter() {
+                        stack.push((child_path(&path, name), child.clone()));
+                    }
+                }
+            }
+        }
+        files
+    }
+
+    /// Up to `limit` children named after `start_after` (from the first one
+    /// if None), in name order
+    pub async fn list(&self, start_after: Option<&str>, limit: usize) -> Vec<(String, Arc<File>)> {
         match self {
             File::RegFile(_) => panic!(),
-            File::Dir(f) => f.list().await,
+            File::Dir(f) => f.list(start_after, limit).await,
         }
     }
 }
 
 pub struct RegFile {
-    /// Several servers may own this file
-    srvs: std::sync::Mutex<Vec<Arc<StorageServer>>>,
-    name: String,
+    /// In order, every one but the last is `chunk_size` bytes long
+    chunks: std::sync::Mutex<Vec<Arc<Chunk>>>,
+    chunk_size: u64,
+    /// Changed by renaming
+    name: std::sync::Mutex<String>,
+    /// Shared accesses since the last replication
+    accesses: AtomicU64,
+    /// Wanted number of alive replicas
+    replication: std::sync::Mutex<Option<usize>>,
+    times: std::sync::Mutex<Times>,
+    owner: Option<String>,
 }
 
 impl RegFile {
-    fn new(name: &str, srv: Arc<StorageServer>) -> Self {
+    fn new(
+        name: &str,
+        chunks: Vec<Arc<Chunk>>,
+        chunk_size: u64,
+        replication: Option<usize>,
+        creation: Creation,
+    ) -> Self {
         Self {
-            srvs: std::sync::Mutex::new(vec![srv]),
-            name: name.to_string(),
+            chunks: std::sync::Mutex::new(chunks),
+            chunk_size,
+            name: std::sync::Mutex::new(name.to_string()),
+            accesses: AtomicU64::new(0),
+            replication: std::sync::Mutex::new(replication),
+            times: std::sync::Mutex::new(creation.times()),
+            owner: creation.owner,
         }
     }
+
+    /// Count a shared access. Return true (and restart counting) once
+    /// `threshold` accesses are reached.
+    pub fn count_access(&self, threshold: u64) -> bool {
+        let accesses = self.accesses.fetch_add(1, Ordering::Relaxed) + 1;
+        if accesses >= threshold {
+            self.accesses.store(0, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn chunks(&self) -> Vec<Arc<Chunk>> {
+        self.chunks.lock().unwrap().clone()
+    }
+
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Bytes up to the end of the furthest write, holes included
+    pub fn size(&self) -> u64 {
+        self.chunks()
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| chunk.size() > 0)
+            .map(|(idx, chunk)| idx as u64 * self.chunk_size + chunk.size())
+            .max()
+            .unwrap_or(0)
+    }
 }
 
 pub struct Dir {
     children: Mutex<BTreeMap<String, Arc<File>>>,
-    name: String,
+    /// Changed by renaming
+    name: std::sync::Mutex<String>,
+    /// Inherited by the new children
+    replication: std::sync::Mutex<Option<usize>>,
+    times: std::sync::Mutex<Times>,
+    owner: Option<String>,
 }
 
 impl Dir {
-    fn new(name: &str) -> Self {
+    fn new(name: &str, replication: Option<usize>, creation: Creation) -> Self {
         Self {
             children: Mutex::new(BTreeMap::new()),
-            name: name.to_string(),
+            name: std::sync::Mutex::new(name.to_string()),
+            replication: std::sync::Mutex::new(replication),
+            times: std::sync::Mutex::new(creation.times()),
+            owner: creation.owner,
         }
     }
 
@@ -103,35 +282,27 @@ impl Dir {
         self.children.lock().await.remove(child)
     }
 
-    async fn create_file(
-        &self,
-        child: &str,
-        is_dir: bool,
-        srv: Option<Arc<StorageServer>>,
-    ) -> Arc<File> {
-        let file = if is_dir {
-            Arc::new(File::Dir(Dir::new(child)))
-        } else {
-            Arc::new(File::RegFile(RegFile::new(child, srv.unwrap())))
-        };
-        self.children
-            .lock()
-            .await
-            .insert(child.to_string(), file.clone());
+    async fn insert(&self, file: Arc<File>) -> Arc<File> {
+        self.children.lock().await.insert(file.name(), file.clone());
         file
     }
 
-    async fn list(&self) -> Vec<String> {
-        self.children
-            .lock()
-            .await
-            .iter()
-            .map(|kv| kv.0.clone())
+    async fn list(&self, start_after: Option<&str>, limit: usize) -> Vec<(String, Arc<File>)> {
+        let children = self.children.lock().await;
+        let after = match start_after {
+            Some(name) => Bound::Excluded(name),
+            None => Bound::Unbounded,
+        };
+        children
+            .range::<str, _>((after, Bound::Unbounded))
+            .take(limit)
+            .map(|(name, child)| (name.clone(), child.clone()))
             .collect()
     }
 }
 
-static ROOT_DIR: Lazy<Arc<File>> = Lazy::new(|| Arc::new(File::Dir(Dir::new("/"))));
+static ROOT_DIR: Lazy<Arc<File>> =
+    Lazy::new(|| Arc::new(File::Dir(Dir::new("/", None, Creation::default()))));
 
 #[derive(Default)]
 struct WalkDirTreeOption {
@@ -167,9 +338,15 @@ impl From<WalkDirTreeTarget> for Option<Arc<File>> {
     }
 }
 
+/// Path of the child `name` of the dir at `dir`, the names in the tree
+/// having been checked on creation
+fn child_path(dir: &DfsPath, name: &str) -> DfsPath {
+    dir.join(name).unwrap()
+}
+
 /// cb: callback for parent dir and target file
 async fn walk_dir_tree<F, Fut, T>(
-    path: &str,
+    path: &DfsPath,
     option: WalkDirTreeOption,
     cb: F,
 ) -> Result<T, TinyDfsError>
@@ -177,20 +354,22 @@ where
     F: FnOnce(Option<Arc<File>>, WalkDirTreeTarget) -> Fut,
     Fut: Future<Output = T>,
 {
-    if path.is_empty() || path.chars().nth(0).unwrap() != '/' {
-        return Err(TinyDfsError::PathInvalid);
+    let split_path = path.names();
+    if split_path.is_empty() {
+        // The root dir has no parent
+        return Ok(cb(None, WalkDirTreeTarget::Some(ROOT_DIR.clone())).await);
     }
-    let split_path: Vec<&str> = path.split("/").collect();
     let mut parent_dir = ROOT_DIR.clone();
     for (i, name) in split_path.iter().enumerate() {
-        if (*name).eq("") {
-            continue;
-        }
         // println!("------------------{} name {} ---------------------", i, name);
         let mut target = parent_dir.lookup(name).await;
-        if target.is_some() {
+        if let Some(found) = target.clone() {
             if i != split_path.len() - 1 {
-                parent_dir = target.unwrap();
+                if let File::RegFile(_) = found.as_ref() {
+                    log::info!("{:?} in Path {:?} is not a dir", name, path);
+                    return Ok(cb(None, WalkDirTreeTarget::Name(None)).await);
+                }
+                parent_dir = found;
             } else {
                 return Ok(cb(Some(parent_dir), WalkDirTreeTarget::from_file(target, None)).await);
             }
@@ -199,7 +378,12 @@ where
             if i == split_path.len() - 1 {
                 // Cannot find the target
                 if option.create_target {
-                    parent_dir.create_file(name, true, None).await;
+                    let creation = Creation {
+                        owner: parent_dir.owner(),
+                        time: 0,
+                    };
+                    let dir = Dir::new(name, parent_dir.replication(), creation);
+                    parent_dir.insert(Arc::new(File::Dir(dir))).await;
                     target = parent_dir.lookup(name).await;
                 }
                 let name = if option.need_target_name {
@@ -219,7 +403,12 @@ where
                     };
                     return Ok(cb(None, WalkDirTreeTarget::from_file(None, name)).await);
                 }
-                parent_dir.create_file(name, true, None).await;
+                let creation = Creation {
+                    owner: parent_dir.owner(),
+                    time: 0,
+                };
+                let dir = Dir::new(name, parent_dir.replication(), creation);
+                parent_dir.insert(Arc::new(File::Dir(dir))).await;
                 parent_dir = parent_dir.lookup(name).await.unwrap();
             }
         }
@@ -228,7 +417,9 @@ where
 }
 
 /// Return parent dir and target file (if any)
-pub async fn lookup(path: &str) -> Result<(Option<Arc<File>>, Option<Arc<File>>), TinyDfsError> {
+pub async fn lookup(
+    path: &DfsPath,
+) -> Result<(Option<Arc<File>>, Option<Arc<File>>), TinyDfsError> {
     walk_dir_tree(
         path,
         WalkDirTreeOption::default(),
@@ -237,8 +428,18 @@ pub async fn lookup(path: &str) -> Result<(Option<Arc<File>>, Option<Arc<File>>)
     .await
 }
 
-pub async fn delete_file(path: &str) -> Result<Arc<File>, TinyDfsError> {
+pub async fn delete_file(path: &DfsPath) -> Result<Arc<File>, TinyDfsError> {
     log::debug!("delete_file: path {:?}", path,);
+    let time = unix_millis(SystemTime::now());
+    let op = Operation::DeleteFile {
+        path: path.to_string(),
+        time,
+    };
+    journal::commit(op, || apply_delete_file(path, time)).await
+}
+
+/// Delete without logging, used by both `delete_file` and the journal replay
+pub async fn apply_delete_file(path: &DfsPath, time: u64) -> Result<Arc<File>, TinyDfsError> {
     walk_dir_tree(
         path,
         WalkDirTreeOption::default(),
@@ -246,32 +447,110 @@ pub async fn delete_file(path: &str) -> Result<Arc<File>, TinyDfsError> {
             if let Some(parent) = parent {
                 match target {
                     WalkDirTreeTarget::Some(target) => {
-                        let child = parent.delete_file(target.name()).await;
-                        return Ok(child.unwrap());
+                        let child = parent.delete_file(&target.name()).await.unwrap();
+                        parent.touch(time);
+                        for (_, chunks, _) in child.reg_files(path).await {
+                            for chunk in chunks {
+                                chunk::remove_chunk(chunk.id);
+                            }
+                        }
+                        Ok(child)
                     }
-                    WalkDirTreeTarget::Name(_) => return Err(TinyDfsError::FileNotFound),
+                    WalkDirTreeTarget::Name(_) => Err(TinyDfsError::FileNotFound),
                 }
             } else {
                 log::warn!("delete_file: Path {:?} has missing one", path);
-                return Err(TinyDfsError::DirNotFound);
+                Err(TinyDfsError::DirNotFound)
             }
         },
     )
     .await?
 }
 
+/// Replication factor inherited by a new file at `path`, i.e. the one of
+/// its nearest existing ancestor
+pub async fn inherited_replication(path: &DfsPath) -> Option<usize> {
+    let mut dir = ROOT_DIR.clone();
+    let names = path.names();
+    for name in names.iter().take(names.len().saturating_sub(1)) {
+        match dir.lookup(name).await {
+            Some(child) if matches!(child.as_ref(), File::Dir(_)) => dir = child,
+            _ => break,
+        }
+    }
+    dir.replication()
+}
+
+/// Create a file whose first chunk is owned by `srvs` (empty for a dir).
+/// A file without an explicit `replication` inherits the one of its parent.
 pub async fn create_file(
-    path: &str,
+    path: &DfsPath,
     is_dir: bool,
-    srv: Option<Arc<StorageServer>>,
+    srvs: Vec<Arc<StorageServer>>,
+    replication: Option<usize>,
+    owner: Option<String>,
     create_missing_one: bool,
 ) -> Result<Arc<File>, TinyDfsError> {
     log::debug!(
-        "create_file: path {:?}, is_dir {:?}, auto_create {:?}",
+        "create_file: path {:?}, is_dir {:?}, replication {:?}, auto_create {:?}",
         path,
         is_dir,
+        replication,
         create_missing_one
     );
+    let replication = match replication {
+        Some(replication) => Some(replication),
+        None => inherited_replication(path).await,
+    };
+    let (chunks, chunk_size) = if is_dir {
+        (Vec::new(), 0)
+    } else {
+        (vec![(chunk::alloc_chunk_id(), srvs)], chunk::chunk_size())
+    };
+    let creation = Creation {
+        owner,
+        time: unix_millis(SystemTime::now()),
+    };
+    let op = Operation::CreateFile {
+        path: path.to_string(),
+        is_dir,
+        chunks: chunks
+            .iter()
+            .map(|(id, srvs)| ChunkRecord {
+                id: *id,
+                srvs: srvs.iter().map(|srv| srv.as_ref().into()).collect(),
+            })
+            .collect(),
+        chunk_size,
+        replication,
+        create_missing_one,
+        created: creation.time,
+        owner: creation.owner.clone(),
+    };
+    journal::commit(op, || {
+        apply_create_file(
+            path,
+            is_dir,
+            chunks,
+            chunk_size,
+            replication,
+            creation,
+            create_missing_one,
+        )
+    })
+    .await
+}
+
+/// Create without logging, used by both `create_file` and the journal replay
+pub async fn apply_create_file(
+    path: &DfsPath,
+    is_dir: bool,
+    chunks: Vec<(ChunkId, Vec<Arc<StorageServer>>)>,
+    chunk_size: u64,
+    replication: Option<usize>,
+    creation: Creation,
+    create_missing_one: bool,
+) -> Result<Arc<File>, TinyDfsError> {
     walk_dir_tree(
         path,
         WalkDirTreeOption {
@@ -286,16 +565,33 @@ pub async fn create_file(
                         WalkDirTreeTarget::Some(_) => {
                             // The new file has existed
                             log::warn!("Path {:?} has existed", path);
-                            return Err(TinyDfsError::FileExists);
+                            Err(TinyDfsError::FileExists)
                         }
                         WalkDirTreeTarget::Name(name) => {
                             let name = name.unwrap();
-                            return Ok(parent.create_file(&name, is_dir, srv).await);
+                            let file = if is_dir {
+                                File::Dir(Dir::new(&name, replication, creation))
+                            } else {
+                                let chunks = chunks
+                                    .into_iter()
+                                    .map(|(id, srvs)| chunk::insert_chunk(id, srvs))
+                                    .collect();
+                                File::RegFile(RegFile::new(
+                                    &name,
+                                    chunks,
+                                    chunk_size,
+                                    replication,
+                                    creation,
+                                ))
+                            };
+                            let file = parent.insert(Arc::new(file)).await;
+                            parent.touch(file.times().created);
+                            Ok(file)
                         }
                     }
                 } else {
                     log::warn!("create_file: Path {:?} has missing one", path);
-                    return Err(TinyDfsError::DirNotFound);
+                    Err(TinyDfsError::DirNotFound)
                 }
             }
         },
@@ -303,19 +599,335 @@ pub async fn create_file(
     .await?
 }
 
-/// Collect necessary files and retrive all duplicated ones
-pub async fn collect_files(
-    files: &Vec<String>,
-    srv: Arc<StorageServer>,
-) -> Result<Vec<String>, TinyDfsError> {
-    let mut duplicated_files: Vec<String> = Vec::new();
-    for file in files {
-        let (_, target) = lookup(&file).await?;
-        if target.is_some() {
-            duplicated_files.push(file.clone());
-            continue;
+/// Move the file at `src_path` to `dst_path`, whose parent dir must exist
+pub async fn rename(src_path: &DfsPath, dst_path: &DfsPath) -> Result<Arc<File>, TinyDfsError> {
+    log::debug!("rename: src path {:?}, dst path {:?}", src_path, dst_path);
+    let time = unix_millis(SystemTime::now());
+    let op = Operation::Rename {
+        src_path: src_path.to_string(),
+        dst_path: dst_path.to_string(),
+        time,
+    };
+    journal::commit(op, || apply_rename(src_path, dst_path, time)).await
+}
+
+/// Rename without logging, used by both `rename` and the journal replay
+pub async fn apply_rename(
+    src_path: &DfsPath,
+    dst_path: &DfsPath,
+    time: u64,
+) -> Result<Arc<File>, TinyDfsError> {
+    let src_names = src_path.names();
+    let dst_names = dst_path.names();
+    if src_names.is_empty() || dst_names.is_empty() || dst_path.starts_with(src_path) {
+        // Cannot move the root or move a dir into itself
+        return Err(TinyDfsError::PathInvalid);
+    }
+    let src_name = *src_names.last().unwrap();
+    let dst_name = *dst_names.last().unwrap();
+
+    let src_parent = match lookup(src_path).await? {
+        (Some(parent), Some(_)) => parent,
+        (Some(_), None) => return Err(TinyDfsError::FileNotFound),
+        (None, _) => return Err(TinyDfsError::DirNotFound),
+    };
+    let dst_parent = match lookup(dst_path).await? {
+        (Some(parent), None) => parent,
+        (Some(_), Some(_)) => return Err(TinyDfsError::FileExists),
+        (None, _) => return Err(TinyDfsError::DirNotFound),
+    };
+    let (File::Dir(src_dir), File::Dir(dst_dir)) = (src_parent.as_ref(), dst_parent.as_ref())
+    else {
+        return Err(TinyDfsError::DirNotFound);
+    };
+
+    let moved = if Arc::ptr_eq(&src_parent, &dst_parent) {
+        let mut children = src_dir.children.lock().await;
+        move_child(&mut children, src_name, None, dst_name)
+    } else {
+        // Always lock the dir with the lower address first to avoid deadlock
+        let (mut src_children, mut dst_children) =
+            if Arc::as_ptr(&src_parent) < Arc::as_ptr(&dst_parent) {
+                let src_children = src_dir.children.lock().await;
+                (src_children, dst_dir.children.lock().await)
+            } else {
+                let dst_children = dst_dir.children.lock().await;
+                (src_dir.children.lock().await, dst_children)
+            };
+        move_child(
+            &mut src_children,
+            src_name,
+            Some(&mut dst_children),
+            dst_name,
+        )
+    }?;
+    src_parent.touch(time);
+    dst_parent.touch(time);
+    Ok(moved)
+}
+
+/// Move `src_name` in `src` to `dst_name` in `dst` (or `src` itself if `dst` is None)
+fn move_child(
+    src: &mut BTreeMap<String, Arc<File>>,
+    src_name: &str,
+    dst: Option<&mut BTreeMap<String, Arc<File>>>,
+    dst_name: &str,
+) -> Result<Arc<File>, TinyDfsError> {
+    let dst_has = match &dst {
+        Some(dst) => dst.contains_key(dst_name),
+        None => src.contains_key(dst_name),
+    };
+    if dst_has {
+        return Err(TinyDfsError::FileExists);
+    }
+    let file = src.remove(src_name).ok_or(TinyDfsError::FileNotFound)?;
+    file.set_name(dst_name);
+    dst.unwrap_or(src)
+        .insert(dst_name.to_string(), file.clone());
+    Ok(file)
+}
+
+/// Append a chunk owned by `srvs` to the regular file at `path`
+pub async fn add_chunk(
+    path: &DfsPath,
+    srvs: Vec<Arc<StorageServer>>,
+) -> Result<Arc<Chunk>, TinyDfsError> {
+    let id = chunk::alloc_chunk_id();
+    log::debug!("add_chunk: path {:?}, chunk {}", path, id);
+    let op = Operation::AddChunk {
+        path: path.to_string(),
+        chunk: ChunkRecord {
+            id,
+            srvs: srvs.iter().map(|srv| srv.as_ref().into()).collect(),
+        },
+    };
+    journal::commit(op, || apply_add_chunk(path, id, srvs)).await
+}
+
+/// Append a chunk without logging, used by both `add_chunk` and the journal replay
+pub async fn apply_add_chunk(
+    path: &DfsPath,
+    id: ChunkId,
+    srvs: Vec<Arc<StorageServer>>,
+) -> Result<Arc<Chunk>, TinyDfsError> {
+    match lookup(path).await? {
+        (_, Some(target)) => match target.as_ref() {
+            File::RegFile(f) => {
+                let chunk = chunk::insert_chunk(id, srvs);
+                f.chunks.lock().unwrap().push(chunk.clone());
+                Ok(chunk)
+            }
+            File::Dir(_) => Err(TinyDfsError::FileNotFound),
+        },
+        (_, None) => Err(TinyDfsError::FileNotFound),
+    }
+}
+
+/// What is left to do on the storage servers after a truncation
+pub struct Truncated {
+    /// Chunks wholly past the new end, no longer in the table
+    pub dropped: Vec<Arc<Chunk>>,
+    /// The new last chunk with its bytes left
+    pub cut: Option<(Arc<Chunk>, u64)>,
+}
+
+/// Cut the regular file at `path` to `length` bytes, no more than its size.
+/// The first chunk is always kept, as on creation.
+pub async fn truncate(path: &DfsPath, length: u64) -> Result<Truncated, TinyDfsError> {
+    log::debug!("truncate: path {:?}, length {}", path, length);
+    let time = unix_millis(SystemTime::now());
+    let op = Operation::Truncate {
+        path: path.to_string(),
+        length,
+        time,
+    };
+    journal::commit(op, || apply_truncate(path, length, time)).await
+}
+
+/// Truncate without logging, used by both `truncate` and the journal replay
+pub async fn apply_truncate(
+    path: &DfsPath,
+    length: u64,
+    time: u64,
+) -> Result<Truncated, TinyDfsError> {
+    let (_, Some(target)) = lookup(path).await? else {
+        return Err(TinyDfsError::FileNotFound);
+    };
+    let File::RegFile(f) = target.as_ref() else {
+        return Err(TinyDfsError::FileNotFound);
+    };
+    if length > f.size() {
+        return Err(TinyDfsError::IndexOutOfBound);
+    }
+    let keep = length.div_ceil(f.chunk_size).max(1);
+    let dropped = {
+        let mut chunks = f.chunks.lock().unwrap();
+        let keep = (keep as usize).min(chunks.len());
+        chunks.split_off(keep)
+    };
+    for chunk in &dropped {
+        chunk::remove_chunk(chunk.id);
+    }
+    let cut = match f.chunks().pop() {
+        Some(last) => {
+            let left = length - (keep - 1) * f.chunk_size;
+            chunk::apply_truncate(last.id, left)?;
+            Some((last, left))
+        }
+        None => None,
+    };
+    target.touch(time);
+    Ok(Truncated { dropped, cut })
+}
+
+/// Set the replication factor of the file at `path`. For a dir, it is set
+/// on everything under it as well as inherited by its new children.
+pub async fn set_replication(
+    path: &DfsPath,
+    replication: usize,
+) -> Result<Arc<File>, TinyDfsError> {
+    log::debug!(
+        "set_replication: path {:?}, replication {:?}",
+        path,
+        replication
+    );
+    let op = Operation::SetReplication {
+        path: path.to_string(),
+        replication,
+    };
+    journal::commit(op, || apply_set_replication(path, replication)).await
+}
+
+/// Set the replication without logging, used by both `set_replication` and
+/// the journal replay
+pub async fn apply_set_replication(
+    path: &DfsPath,
+    replication: usize,
+) -> Result<Arc<File>, TinyDfsError> {
+    let (_, Some(target)) = lookup(path).await? else {
+        return Err(TinyDfsError::FileNotFound);
+    };
+    let mut stack = vec![target.clone()];
+    while let Some(file) = stack.pop() {
+        file.set_replication(replication);
+        if let File::Dir(d) = file.as_ref() {
+            stack.extend(d.children.lock().await.values().cloned());
+        }
+    }
+    Ok(target)
+}
+
+/// Restore the times of the file at `path` without logging, used by the
+/// journal replay of snapshots
+pub async fn apply_set_times(
+    path: &DfsPath,
+    modified: u64,
+    accessed: u64,
+) -> Result<(), TinyDfsError> {
+    let (_, Some(target)) = lookup(path).await? else {
+        return Err(TinyDfsError::FileNotFound);
+    };
+    let mut times = target.raw_times().lock().unwrap();
+    times.modified = modified;
+    times.accessed = accessed;
+    Ok(())
+}
+
+/// Walk the subtree of `root` located at `path`, parents first and children
+/// in name order. A dir is only locked while its children are collected, so
+/// the tree may change between two steps.
+pub fn walk(path: &DfsPath, root: Arc<File>) -> impl Stream<Item = (DfsPath, Arc<File>)> {
+    let stack = vec![(path.clone(), root)];
+    stream::unfold(stack, |mut stack| async move {
+        let (path, file) = stack.pop()?;
+        if let File::Dir(d) = file.as_ref() {
+            for (name, child) in d.children.lock().await.iter().rev() {
+                stack.push((child_path(&path, name), child.clone()));
+            }
+        }
+        Some(((path, file), stack))
+    })
+}
+
+/// Collect all regular files in the tree along with their chunks and
+/// replication
+pub async fn all_reg_files() -> Vec<(DfsPath, Vec<Arc<Chunk>>, Option<usize>)> {
+    ROOT_DIR.reg_files(&DfsPath::root()).await
+}
+
+/// Dump the whole tree as the operations that rebuild it, parents first.
+/// The times and the chunk sizes come last, as creating the children
+/// changes the times of their parents.
+pub async fn snapshot() -> Vec<Operation> {
+    let mut ops = Vec::new();
+    let mut late_ops = Vec::new();
+    if let Some(replication) = ROOT_DIR.replication() {
+        // Set before any child exists, so that only the root gets it
+        ops.push(Operation::SetReplication {
+            path: "/".to_string(),
+            replication,
+        });
+    }
+    let mut stack = vec![(String::new(), ROOT_DIR.clone())];
+    while let Some((path, file)) = stack.pop() {
+        let times = *file.raw_times().lock().unwrap();
+        late_ops.push(Operation::SetTimes {
+            path: if path.is_empty() { "/" } else { &path }.to_string(),
+            modified: times.modified,
+            accessed: times.accessed,
+        });
+        match file.as_ref() {
+            File::RegFile(f) => {
+                ops.push(Operation::CreateFile {
+                    path,
+                    is_dir: false,
+                    chunks: f
+                        .chunks()
+                        .iter()
+                        .map(|chunk| ChunkRecord {
+                            id: chunk.id,
+                            srvs: chunk.server_records(),
+                        })
+                        .collect(),
+                    chunk_size: f.chunk_size,
+                    replication: file.replication(),
+                    create_missing_one: false,
+                    created: times.created,
+                    owner: f.owner.clone(),
+                });
+                late_ops.extend(
+                    f.chunks()
+                        .iter()
+                        .filter(|chunk| {
+                            chunk.size() > 0 || chunk.modified() > 0 || chunk.epoch() > 0
+                        })
+                        .map(|chunk| Operation::WriteChunk {
+                            chunk: chunk.id,
+                            size: chunk.size(),
+                            modified: chunk.modified(),
+                            epoch: Some(chunk.epoch()),
+                        }),
+                );
+            }
+            File::Dir(d) => {
+                if !path.is_empty() {
+                    ops.push(Operation::CreateFile {
+                        path: path.clone(),
+                        is_dir: true,
+                        chunks: Vec::new(),
+                        chunk_size: 0,
+                        replication: file.replication(),
+                        create_missing_one: false,
+                        created: times.created,
+                        owner: d.owner.clone(),
+                    });
+                }
+                for (name, child) in d.children.lock().await.iter().rev() {
+                    stack.push((format!("{}/{}", path, name), child.clone()));
+                }
+            }
         }
-        create_file(&file, false, Some(srv.clone()), true).await?;
     }
-    Ok(duplicated_files)
+    ops.extend(late_ops);
+    ops
 }
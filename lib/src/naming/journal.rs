@@ -0,0 +1,464 @@
+//! Persistence of the naming server metadata.
+//!
+//! Every mutation of the namespace (and every storage server registration) is
+//! appended to an fsync'd journal before it is applied in memory. From time to
+//! time the whole state is written into a snapshot and the journal is truncated.
+//! On startup the snapshot is loaded and the journal is replayed on top of it.
+
+use std::{
+    fs,
+    future::Future,
+    io::{self, Seek, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use once_cell::sync::Lazy;
+use rocket::{
+    serde::{json, Deserialize, Serialize},
+    tokio::sync::Mutex,
+};
+
+use crate::common::{addr::Host, error::TinyDfsError, path::DfsPath, storage::ChunkId};
+
+use super::{
+    chunk,
+    dir_tree::{self, Creation},
+    server::{self, StorageServer},
+    Ip,
+};
+
+const JOURNAL_FILE: &str = "journal";
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+
+/// Take a checkpoint once this many records have been appended
+const CHECKPOINT_INTERVAL: u64 = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ServerRecord {
+    pub ip: Host,
+    pub client_port: u16,
+    pub command_port: u16,
+}
+
+impl From<&StorageServer> for ServerRecord {
+    fn from(srv: &StorageServer) -> Self {
+        Self {
+            ip: srv.ip.host().clone(),
+            client_port: srv.ip.port(),
+            command_port: srv.command_port,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChunkRecord {
+    pub id: ChunkId,
+    pub srvs: Vec<ServerRecord>,
+}
+
+/// A single mutation of the naming server metadata
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", tag = "op")]
+pub enum Operation {
+    RegisterServer {
+        server: ServerRecord,
+    },
+    CreateFile {
+        path: String,
+        is_dir: bool,
+        chunks: Vec<ChunkRecord>,
+        chunk_size: u64,
+        replication: Option<usize>,
+        create_missing_one: bool,
+        /// Milliseconds since the Unix epoch, the replay time if missing
+        #[serde(default)]
+        created: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        owner: Option<String>,
+    },
+    DeleteFile {
+        path: String,
+        /// Milliseconds since the Unix epoch, the replay time if missing
+        #[serde(default)]
+        time: u64,
+    },
+    Rename {
+        src_path: String,
+        dst_path: String,
+        /// Milliseconds since the Unix epoch, the replay time if missing
+        #[serde(default)]
+        time: u64,
+    },
+    AddChunk {
+        path: String,
+        chunk: ChunkRecord,
+    },
+    AddReplica {
+        chunk: ChunkId,
+        server: ServerRecord,
+    },
+    RemoveReplica {
+        chunk: ChunkId,
+        server: ServerRecord,
+    },
+    SetReplication {
+        path: String,
+        replication: usize,
+    },
+    Decommission {
+        server: ServerRecord,
+    },
+    WriteChunk {
+        chunk: ChunkId,
+        size: u64,
+        modified: u64,
+        /// Truncation epoch of the report, restored from snapshots
+        #[serde(default)]
+        epoch: Option<u64>,
+    },
+    Truncate {
+        path: String,
+        length: u64,
+        time: u64,
+    },
+    /// Only in snapshots, times changed since the creation
+    SetTimes {
+        path: String,
+        modified: u64,
+        accessed: u64,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Record {
+    seq: u64,
+    #[serde(flatten)]
+    op: Operation,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SnapshotHeader {
+    /// Seq of the last record included in this snapshot
+    seq: u64,
+    /// Id of the next new chunk, which may be beyond all existing ones
+    next_chunk: ChunkId,
+}
+
+struct Journal {
+    dir: PathBuf,
+    file: fs::File,
+    /// Seq of the last appended record
+    seq: u64,
+    /// Records appended since the last checkpoint
+    dirty: u64,
+}
+
+impl Journal {
+    fn append(&mut self, op: &Operation) -> io::Result<()> {
+        let record = Record {
+            seq: self.seq + 1,
+            op: op.clone(),
+        };
+        let mut line = json::to_string(&record).map_err(io::Error::other)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        self.seq += 1;
+        self.dirty += 1;
+        Ok(())
+    }
+
+    async fn checkpoint(&mut self) -> io::Result<()> {
+        log::info!("checkpoint: seq {}", self.seq);
+        let srvs = server::all_servers().await;
+        let mut ops: Vec<Operation> = srvs
+            .iter()
+            .map(|srv| Operation::RegisterServer {
+                server: srv.as_ref().into(),
+            })
+            .collect();
+        ops.extend(
+            srvs.iter()
+                .filter(|srv| srv.is_decommissioned())
+                .map(|srv| Operation::Decommission {
+                    server: srv.as_ref().into(),
+                }),
+        );
+        ops.extend(dir_tree::snapshot().await);
+
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut tmp = fs::File::create(&tmp_path)?;
+        let header = SnapshotHeader {
+            seq: self.seq,
+            next_chunk: chunk::next_chunk_id(),
+        };
+        writeln!(
+            tmp,
+            "{}",
+            json::to_string(&header).map_err(io::Error::other)?
+        )?;
+        for op in ops {
+            writeln!(tmp, "{}", json::to_string(&op).map_err(io::Error::other)?)?;
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+        fs::File::open(&self.dir)?.sync_all()?;
+
+        // All records are in the snapshot now
+        self.file.set_len(0)?;
+        // Otherwise the next record lands at the old end, after a hole of zeros
+        self.file.seek(io::SeekFrom::Start(0))?;
+        self.file.sync_all()?;
+        self.dirty = 0;
+        Ok(())
+    }
+}
+
+static JOURNAL: Lazy<Mutex<Option<Journal>>> = Lazy::new(|| Mutex::new(None));
+
+/// Log `op` and then apply it by `apply`.
+/// The journal stays locked until `apply` finishes so that the order of the
+/// records is the same as the order of the mutations in memory.
+pub async fn commit<F, Fut, T>(op: Operation, apply: F) -> Result<T, TinyDfsError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, TinyDfsError>>,
+{
+    let mut guard = JOURNAL.lock().await;
+    if let Some(journal) = guard.as_mut() {
+        if let Err(err) = journal.append(&op) {
+            log::error!("commit: append {:?} failed, err {:?}", op, err);
+            return Err(TinyDfsError::JournalFailed);
+        }
+    }
+    let res = apply().await;
+    if let Some(journal) = guard.as_mut() {
+        if journal.dirty >= CHECKPOINT_INTERVAL {
+            if let Err(err) = journal.checkpoint().await {
+                log::warn!("commit: checkpoint failed, err {:?}", err);
+            }
+        }
+    }
+    res
+}
+
+/// Take a checkpoint if anything has been logged since the last one
+pub async fn checkpoint() {
+    let mut guard = JOURNAL.lock().await;
+    if let Some(journal) = guard.as_mut() {
+        if journal.dirty == 0 {
+            return;
+        }
+        if let Err(err) = journal.checkpoint().await {
+            log::warn!("checkpoint: failed, err {:?}", err);
+        }
+    }
+}
+
+async fn replay(op: Operation) -> Result<(), TinyDfsError> {
+    match op {
+        Operation::RegisterServer { server } => {
+            let ip = Ip::new(server.ip, server.client_port)?;
+            let srv = Arc::new(StorageServer::new(ip, server.command_port));
+            server::restore_server(srv).await.map(|_| ())
+        }
+        Operation::CreateFile {
+            path,
+            is_dir,
+            chunks,
+            chunk_size,
+            replication,
+            create_missing_one,
+            created,
+            owner,
+        } => {
+            let mut resolved = Vec::new();
+            for chunk in chunks {
+                resolved.push((chunk.id, find_servers(&chunk.srvs).await?));
+            }
+            dir_tree::apply_create_file(
+                &DfsPath::parse(&path)?,
+                is_dir,
+                resolved,
+                chunk_size,
+                replication,
+                Creation {
+                    owner,
+                    time: created,
+                },
+                create_missing_one,
+            )
+            .await
+            .map(|_| ())
+        }
+        Operation::AddChunk { path, chunk } => {
+            let srvs = find_servers(&chunk.srvs).await?;
+            dir_tree::apply_add_chunk(&DfsPath::parse(&path)?, chunk.id, srvs)
+                .await
+                .map(|_| ())
+        }
+        Operation::DeleteFile { path, time } => {
+            dir_tree::apply_delete_file(&DfsPath::parse(&path)?, time)
+                .await
+                .map(|_| ())
+        }
+        Operation::AddReplica { chunk, server } => {
+            let srv = server::find_server(&server)
+                .await
+                .ok_or(TinyDfsError::RegisterFailed)?;
+            chunk::apply_add_replica(chunk, srv)
+        }
+        Operation::RemoveReplica { chunk, server } => {
+            let srv = server::find_server(&server)
+                .await
+                .ok_or(TinyDfsError::RegisterFailed)?;
+            chunk::apply_remove_replica(chunk, srv)
+        }
+        Operation::Rename {
+            src_path,
+            dst_path,
+            time,
+        } => {
+            let (src_path, dst_path) = (DfsPath::parse(&src_path)?, DfsPath::parse(&dst_path)?);
+            dir_tree::apply_rename(&src_path, &dst_path, time)
+                .await
+                .map(|_| ())
+        }
+        Operation::SetReplication { path, replication } => {
+            dir_tree::apply_set_replication(&DfsPath::parse(&path)?, replication)
+                .await
+                .map(|_| ())
+        }
+        Operation::Decommission { server } => {
+            let srv = server::find_server(&server)
+                .await
+                .ok_or(TinyDfsError::RegisterFailed)?;
+            server::apply_decommission(&srv);
+            Ok(())
+        }
+        Operation::WriteChunk {
+            chunk,
+            size,
+            modified,
+            epoch,
+        } => chunk::apply_write(chunk, size, modified, epoch),
+        Operation::Truncate { path, length, time } => {
+            dir_tree::apply_truncate(&DfsPath::parse(&path)?, length, time)
+                .await
+                .map(|_| ())
+        }
+        Operation::SetTimes {
+            path,
+            modified,
+            accessed,
+        } => dir_tree::apply_set_times(&DfsPath::parse(&path)?, modified, accessed).await,
+    }
+}
+
+async fn find_servers(records: &[ServerRecord]) -> Result<Vec<Arc<StorageServer>>, TinyDfsError> {
+    let mut srvs = Vec::new();
+    for record in records {
+        srvs.push(
+            server::find_server(record)
+                .await
+                .ok_or(TinyDfsError::RegisterFailed)?,
+        );
+    }
+    Ok(srvs)
+}
+
+/// Load the snapshot in `dir`. Return the seq it was taken at.
+async fn load_snapshot(dir: &Path) -> io::Result<u64> {
+    let content = match fs::read_to_string(dir.join(SNAPSHOT_FILE)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut lines = content.lines();
+    let header: SnapshotHeader = match lines.next() {
+        Some(line) => json::from_str(line).map_err(io::Error::other)?,
+        None => return Ok(0),
+    };
+    chunk::reserve_chunk_ids(header.next_chunk);
+    for line in lines {
+        let op: Operation = json::from_str(line).map_err(io::Error::other)?;
+        if let Err(err) = replay(op.clone()).await {
+            log::warn!("load_snapshot: apply {:?} failed, err {:?}", op, err);
+        }
+    }
+    Ok(header.seq)
+}
+
+/// Replay the journal in `dir` on top of a snapshot taken at `snapshot_seq`.
+/// Return the seq of the last valid record and the length of the valid prefix.
+async fn replay_journal(dir: &Path, snapshot_seq: u64) -> io::Result<(u64, u64)> {
+    let content = match fs::read_to_string(dir.join(JOURNAL_FILE)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((snapshot_seq, 0)),
+        Err(err) => return Err(err),
+    };
+    let mut seq = snapshot_seq;
+    let mut valid_len = 0;
+    for line in content.split_inclusive('\n') {
+        // A torn record can only be the last one, left by a crash during append
+        let record: Record = match line.strip_suffix('\n').map(json::from_str) {
+            Some(Ok(record)) => record,
+            _ => {
+                log::warn!("replay_journal: drop torn record {:?}", line);
+                break;
+            }
+        };
+        valid_len += line.len() as u64;
+        if record.seq <= seq {
+            // Already included in the snapshot
+            continue;
+        }
+        seq = record.seq;
+        // Failed mutations are logged as well, they fail the same way here
+        if let Err(err) = replay(record.op.clone()).await {
+            log::debug!(
+                "replay_journal: apply {:?} failed, err {:?}",
+                record.op,
+                err
+            );
+        }
+    }
+    Ok((seq, valid_len))
+}
+
+/// Restore the metadata persisted in `dir` and start logging into it
+pub async fn recover(dir: &Path) -> io::Result<()> {
+    log::info!("recover metadata from {:?}...", dir);
+    fs::create_dir_all(dir)?;
+    let mut guard = JOURNAL.lock().await;
+
+    let snapshot_seq = load_snapshot(dir).await?;
+    let (seq, valid_len) = replay_journal(dir, snapshot_seq).await?;
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(JOURNAL_FILE))?;
+    file.set_len(valid_len)?;
+    file.seek(io::SeekFrom::End(0))?;
+
+    let mut journal = Journal {
+        dir: dir.to_path_buf(),
+        file,
+        seq,
+        dirty: 0,
+    };
+    if valid_len > 0 {
+        journal.checkpoint().await?;
+    }
+    *guard = Some(journal);
+    log::info!("recover metadata done, seq {}", seq);
+    Ok(())
+}
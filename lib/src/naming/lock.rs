@@ -0,0 +1,309 @@
+//! Path-level shared/exclusive locks.
+//!
+//! Locking a path takes shared locks on all of its ancestors (from the root
+//! down) and then a shared or exclusive lock on the path itself. Each path lock
+//! grants waiters in FIFO order, so a writer is never starved by a stream of
+//! readers arriving after it.
+//!
+//! Locks are held by guards, so a lock given up half way (e.g. a cancelled
+//! request) releases whatever it took. The locks of the clients are kept
+//! under a token until they unlock, which takes them back.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use once_cell::sync::Lazy;
+use rocket::tokio::sync::oneshot;
+
+use crate::common::{error::TinyDfsError, path::DfsPath};
+
+use super::dir_tree;
+
+#[derive(Default)]
+struct PathLock {
+    readers: usize,
+    writer: bool,
+    /// (exclusive, waker)
+    waiters: VecDeque<(bool, oneshot::Sender<()>)>,
+}
+
+impl PathLock {
+    fn compatible(&self, exclusive: bool) -> bool {
+        if exclusive {
+            self.readers == 0 && !self.writer
+        } else {
+            !self.writer
+        }
+    }
+
+    fn grant(&mut self, exclusive: bool) {
+        if exclusive {
+            self.writer = true;
+        } else {
+            self.readers += 1;
+        }
+    }
+
+    fn release(&mut self, exclusive: bool) -> Result<(), TinyDfsError> {
+        if exclusive {
+            if !self.writer {
+                return Err(TinyDfsError::LockNotHeld);
+            }
+            self.writer = false;
+        } else {
+            if self.readers == 0 {
+                return Err(TinyDfsError::LockNotHeld);
+            }
+            self.readers -= 1;
+        }
+        self.wake_up();
+        Ok(())
+    }
+
+    /// Grant the lock to the waiters at the head of the queue while possible
+    fn wake_up(&mut self) {
+        while let Some((exclusive, _)) = self.waiters.front() {
+            if !self.compatible(*exclusive) {
+                break;
+            }
+            let (exclusive, waker) = self.waiters.pop_front().unwrap();
+            // The waiter may have gone away
+            if waker.send(()).is_ok() {
+                self.grant(exclusive);
+            }
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.readers == 0 && !self.writer && self.waiters.is_empty()
+    }
+}
+
+/// Canonical path => lock
+static LOCK_TABLE: Lazy<std::sync::Mutex<HashMap<String, PathLock>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// Token => lock taken by `/lock` and not given back by `/unlock` yet
+static HELD_LOCKS: Lazy<std::sync::Mutex<HashMap<u64, LockGuard>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(1);
+
+/// A granted lock on a single path, released when dropped
+struct Held {
+    path: String,
+    exclusive: bool,
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        release(&self.path, self.exclusive);
+    }
+}
+
+/// A lock on a path waited for, released when dropped after being granted
+struct Waiting {
+    path: String,
+    exclusive: bool,
+    /// None once granted
+    waiter: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        let Some(mut waiter) = self.waiter.take() else {
+            return;
+        };
+        // Granted between the wake up and the cancellation of the waiting task
+        waiter.close();
+        if waiter.try_recv().is_ok() {
+            release(&self.path, self.exclusive);
+        }
+    }
+}
+
+/// Grant the lock on `path` at once if nobody holds or waits for it
+/// incompatibly, otherwise queue a waker if `wait` is set
+fn try_acquire(
+    path: &str,
+    exclusive: bool,
+    wait: bool,
+) -> Result<Held, Option<oneshot::Receiver<()>>> {
+    let mut table = LOCK_TABLE.lock().unwrap();
+    let lock = table.entry(path.to_string()).or_default();
+    if lock.waiters.is_empty() && lock.compatible(exclusive) {
+        lock.grant(exclusive);
+        return Ok(Held {
+            path: path.to_string(),
+            exclusive,
+        });
+    }
+    if !wait {
+        // Held or waited for by somebody else, so the entry stays
+        return Err(None);
+    }
+    let (waker, waiter) = oneshot::channel();
+    lock.waiters.push_back((exclusive, waker));
+    Err(Some(waiter))
+}
+
+async fn acquire(path: &str, exclusive: bool) -> Held {
+    let waiter = match try_acquire(path, exclusive, true) {
+        Ok(held) => return held,
+        Err(waiter) => waiter.unwrap(),
+    };
+    let mut waiting = Waiting {
+        path: path.to_string(),
+        exclusive,
+        waiter: Some(waiter),
+    };
+    // The waker is only dropped after sending
+    let _ = waiting.waiter.as_mut().unwrap().await;
+    // Granted, the lock is owned by the `Held` from now on
+    waiting.waiter = None;
+    Held {
+        path: path.to_string(),
+        exclusive,
+    }
+}
+
+fn release(path: &str, exclusive: bool) {
+    let mut table = LOCK_TABLE.lock().unwrap();
+    let Some(lock) = table.get_mut(path) else {
+        log::error!("release: path {:?} is not locked", path);
+        return;
+    };
+    if let Err(err) = lock.release(exclusive) {
+        log::error!("release: path {:?}, err {:?}", path, err);
+    }
+    if lock.is_idle() {
+        table.remove(path);
+    }
+}
+
+/// "/a/b/c" => ["/", "/a", "/a/b", "/a/b/c"]
+fn lock_paths(path: &DfsPath) -> Vec<String> {
+    let mut paths = vec!["/".to_string()];
+    let mut prefix = String::new();
+    for name in path.names() {
+        prefix = prefix + "/" + name;
+        paths.push(prefix.clone());
+    }
+    paths
+}
+
+/// A lock on a path and its ancestors, released when dropped
+pub struct LockGuard {
+    path: String,
+    exclusive: bool,
+    /// From the root down
+    held: Vec<Held>,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        // The path first, then its ancestors
+        while self.held.pop().is_some() {}
+    }
+}
+
+/// Block until `path` is locked.
+/// Dropping the future before it is done gives back what it took so far.
+pub async fn lock(path: &DfsPath, exclusive: bool) -> Result<LockGuard, TinyDfsError> {
+    log::debug!("lock: path {:?}, exclusive {:?}", path, exclusive);
+    if let (_, None) = dir_tree::lookup(path).await? {
+        return Err(TinyDfsError::FileNotFound);
+    }
+    let paths = lock_paths(path);
+    let mut guard = LockGuard {
+        path: path.as_str().to_string(),
+        exclusive,
+        held: Vec::with_capacity(paths.len()),
+    };
+    let (target, ancestors) = paths.split_last().unwrap();
+    for ancestor in ancestors {
+        guard.held.push(acquire(ancestor, false).await);
+    }
+    guard.held.push(acquire(target, exclusive).await);
+    Ok(guard)
+}
+
+/// Locks on several paths and their ancestors, released when dropped
+pub struct PathsGuard {
+    _held: Vec<Held>,
+}
+
+/// Block until all of `paths` are locked, exclusively or not, whether they
+/// exist or not (as the destination of a rename). A path both locked and an
+/// ancestor of another is locked the strongest way. The locks are taken in
+/// the order of the paths, ancestors first as by `lock`, so that no two
+/// lockers wait for each other.
+pub async fn lock_all(paths: &[(&DfsPath, bool)]) -> PathsGuard {
+    log::debug!("lock_all: paths {:?}", paths);
+    let mut wanted: BTreeMap<String, bool> = BTreeMap::new();
+    for (path, exclusive) in paths {
+        let chain = lock_paths(path);
+        let (target, ancestors) = chain.split_last().unwrap();
+        for ancestor in ancestors {
+            wanted.entry(ancestor.clone()).or_insert(false);
+        }
+        *wanted.entry(target.clone()).or_insert(false) |= exclusive;
+    }
+    let mut held = Vec::with_capacity(wanted.len());
+    for (path, exclusive) in wanted {
+        held.push(acquire(&path, exclusive).await);
+    }
+    PathsGuard { _held: held }
+}
+
+/// Lock `path` if that doesn't need to wait, None otherwise
+pub async fn try_lock(path: &DfsPath, exclusive: bool) -> Result<Option<LockGuard>, TinyDfsError> {
+    if let (_, None) = dir_tree::lookup(path).await? {
+        return Err(TinyDfsError::FileNotFound);
+    }
+    let paths = lock_paths(path);
+    let mut guard = LockGuard {
+        path: path.as_str().to_string(),
+        exclusive,
+        held: Vec::with_capacity(paths.len()),
+    };
+    let (target, ancestors) = paths.split_last().unwrap();
+    for (path, exclusive) in ancestors
+        .iter()
+        .map(|ancestor| (ancestor, false))
+        .chain([(target, exclusive)])
+    {
+        match try_acquire(path, exclusive, false) {
+            Ok(held) => guard.held.push(held),
+            Err(_) => return Ok(None),
+        }
+    }
+    Ok(Some(guard))
+}
+
+/// Keep `guard` until `unlock` is called with the returned token
+pub fn hold(guard: LockGuard) -> u64 {
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    HELD_LOCKS.lock().unwrap().insert(token, guard);
+    token
+}
+
+/// Take back the lock kept by `hold`, if `token` was given for that lock. It
+/// is released once the guard is dropped.
+pub fn take_held(path: &DfsPath, exclusive: bool, token: u64) -> Result<LockGuard, TinyDfsError> {
+    log::debug!(
+        "take_held: path {:?}, exclusive {:?}, token {}",
+        path,
+        exclusive,
+        token
+    );
+    let mut held = HELD_LOCKS.lock().unwrap();
+    match held.get(&token) {
+        Some(guard) if guard.path == path.as_str() && guard.exclusive == exclusive => {
+            Ok(held.remove(&token).unwrap())
+        }
+        _ => Err(TinyDfsError::LockNotHeld),
+    }
+}
@@ -1,33 +1,71 @@
 //! Code of naming server
 
 mod api;
+mod chunk;
 mod dir_tree;
+mod journal;
+mod lock;
+mod placement;
+mod repair;
+mod replication;
 mod server;
 
-use api::registration::register_storage_server;
+use std::time::Duration;
+
+use api::admin::{decommission, file_replicas, replicate, servers};
+use api::registration::{
+    heartbeat, register_storage_server, report_corrupt, reserve_append, truncate_file,
+};
 use api::service::{
-    create_directory, create_file, delete_file, get_storage_server, is_directory, is_valid_path,
-    list_dir,
+    create_directory, create_file, delete_file, find, get_storage_server, is_directory,
+    is_valid_path, list_dir, lock_path, rename, set_replication, stat, unlock_path,
 };
-use rocket::serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Deserialize, Serialize)]
-#[serde(crate = "rocket::serde")]
-pub struct Ip(pub String);
+pub use crate::common::addr::Ip;
+use crate::config::NamingConfig;
 
-/// args[2]: service port;
-/// args[3]: registration port
-pub async fn start_naming_server(args: &Vec<String>) {
+/// Interval between two periodic checkpoints of the metadata
+const CHECKPOINT_PERIOD: Duration = Duration::from_secs(60);
+
+/// Whether `name` names a placement policy
+pub fn is_placement_policy(name: &str) -> bool {
+    placement::from_name(name).is_some()
+}
+
+pub async fn start_naming_server(config: &NamingConfig) {
     log::info!("start a new naming server...");
-    let service_port = args[2].parse::<u16>().unwrap();
-    let registration_port = args[3].parse::<u16>().unwrap();
+    replication::set_replication_threshold(config.replication_threshold);
+    repair::set_replication_factor(config.replication_factor);
+    chunk::set_chunk_size(config.chunk_size);
+    match placement::from_name(&config.placement) {
+        Some(policy) => placement::set_policy(policy),
+        None => {
+            log::error!("unknown placement policy {:?}", config.placement);
+            panic!();
+        }
+    }
+
+    if let Some(err) = journal::recover(&config.meta_dir).await.err() {
+        log::error!("recover metadata failed, err {:?}", err);
+        panic!();
+    }
+    rocket::tokio::spawn(async {
+        let mut interval = rocket::tokio::time::interval(CHECKPOINT_PERIOD);
+        loop {
+            interval.tick().await;
+            journal::checkpoint().await;
+        }
+    });
+    repair::start_repair_task();
 
     let service_config = rocket::Config {
-        port: service_port,
+        address: config.bind_address,
+        port: config.service_port,
         ..rocket::Config::debug_default()
     };
     let registration_config = rocket::Config {
-        port: registration_port,
+        address: config.bind_address,
+        port: config.registration_port,
         ..rocket::Config::debug_default()
     };
 
@@ -44,6 +82,12 @@ pub async fn start_naming_server(args: &Vec<String>) {
                     create_file,
                     list_dir,
                     is_directory,
+                    stat,
+                    find,
+                    rename,
+                    lock_path,
+                    unlock_path,
+                    set_replication,
                 ],
             )
             // .mount("/test", routes![hello])
@@ -55,7 +99,20 @@ pub async fn start_naming_server(args: &Vec<String>) {
     let registration_task = rocket::tokio::spawn(async move {
         rocket::build()
             .configure(registration_config)
-            .mount("/", routes![register_storage_server])
+            .mount(
+                "/",
+                routes![
+                    register_storage_server,
+                    heartbeat,
+                    report_corrupt,
+                    truncate_file,
+                    reserve_append
+                ],
+            )
+            .mount(
+                "/admin",
+                routes![servers, file_replicas, replicate, decommission],
+            )
             .launch()
             .await
             .unwrap();
@@ -0,0 +1,175 @@
+//! Policies choosing the storage servers new replicas are put on and the
+//! replicas reads are served from.
+//!
+//! The policy is chosen once at startup. Load-aware policies rely on the load
+//! reported by the storage servers in their heartbeats.
+
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use once_cell::sync::OnceCell;
+use rand::Rng;
+use xxhash_rust::xxh64::xxh64;
+
+use super::{server::StorageServer, Ip};
+
+/// What the policies place a chunk by, its file and its index in it, so that
+/// the chunks of a file are spread as the files are
+pub fn chunk_key(path: &str, idx: u64) -> String {
+    format!("{}#{}", path, idx)
+}
+
+pub trait PlacementPolicy: Send + Sync {
+    /// Choose the server to put a new replica of the chunk `key` on.
+    /// `candidates` are alive and never empty.
+    fn place(&self, key: &str, candidates: &[Arc<StorageServer>]) -> Arc<StorageServer>;
+
+    /// Choose the replica to read the chunk `key` from.
+    /// `replicas` are alive and never empty.
+    fn read(&self, key: &str, replicas: &[Arc<StorageServer>]) -> Arc<StorageServer> {
+        self.place(key, replicas)
+    }
+}
+
+pub struct Random;
+
+impl PlacementPolicy for Random {
+    fn place(&self, _key: &str, candidates: &[Arc<StorageServer>]) -> Arc<StorageServer> {
+        let idx = rand::thread_rng().gen_range(0..candidates.len());
+        candidates[idx].clone()
+    }
+}
+
+#[derive(Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl PlacementPolicy for RoundRobin {
+    fn place(&self, _key: &str, candidates: &[Arc<StorageServer>]) -> Arc<StorageServer> {
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+        candidates[idx].clone()
+    }
+}
+
+/// Prefer the server storing the fewest bytes
+pub struct LeastUsedCapacity;
+
+impl PlacementPolicy for LeastUsedCapacity {
+    fn place(&self, _key: &str, candidates: &[Arc<StorageServer>]) -> Arc<StorageServer> {
+        candidates
+            .iter()
+            .min_by_key(|srv| srv.used_bytes())
+            .unwrap()
+            .clone()
+    }
+}
+
+/// Prefer the server serving the fewest client requests
+pub struct LeastOutstandingRequests;
+
+impl PlacementPolicy for LeastOutstandingRequests {
+    fn place(&self, _key: &str, candidates: &[Arc<StorageServer>]) -> Arc<StorageServer> {
+        let srv = candidates
+            .iter()
+            .min_by_key(|srv| srv.outstanding_requests())
+            .unwrap();
+        // Until the next heartbeat tells the truth, assume a client is coming
+        srv.expect_request();
+        srv.clone()
+    }
+
+    fn read(&self, _key: &str, replicas: &[Arc<StorageServer>]) -> Arc<StorageServer> {
+        // Only the order of the replicas handed out, a client may read from
+        // another one or not at all
+        replicas
+            .iter()
+            .min_by_key(|srv| srv.outstanding_requests())
+            .unwrap()
+            .clone()
+    }
+}
+
+/// Map every chunk to the same server as long as the set of servers is
+/// stable, moving few chunks when it changes
+#[derive(Default)]
+pub struct ConsistentHashing {
+    ring: Mutex<Ring>,
+}
+
+/// The points of every server seen so far, only extended when a new one
+/// shows up, as servers are never forgotten
+#[derive(Default)]
+struct Ring {
+    servers: HashSet<Ip>,
+    /// Sorted
+    points: Vec<(u64, Ip)>,
+}
+
+/// Points of every server on the ring
+const VIRTUAL_NODES: u64 = 64;
+
+/// Same on every naming server and across restarts, unlike the std hasher
+fn hash(bytes: &[u8]) -> u64 {
+    xxh64(bytes, 0)
+}
+
+impl PlacementPolicy for ConsistentHashing {
+    fn place(&self, key: &str, candidates: &[Arc<StorageServer>]) -> Arc<StorageServer> {
+        let mut ring = self.ring.lock().unwrap();
+        let ring = &mut *ring;
+        let mut added = false;
+        for srv in candidates {
+            if ring.servers.insert(srv.ip.clone()) {
+                ring.points.extend((0..VIRTUAL_NODES).map(|i| {
+                    let point = format!("{}#{}", srv.ip, i);
+                    (hash(point.as_bytes()), srv.ip.clone())
+                }));
+                added = true;
+            }
+        }
+        if added {
+            ring.points.sort_unstable();
+        }
+        // The first point of a candidate clockwise from the chunk
+        let key = hash(key.as_bytes());
+        let start = ring.points.partition_point(|(point, _)| *point < key);
+        ring.points[start..]
+            .iter()
+            .chain(&ring.points[..start])
+            .find_map(|(_, ip)| candidates.iter().find(|srv| srv.ip == *ip))
+            .unwrap()
+            .clone()
+    }
+}
+
+/// Build the policy called `name`
+pub fn from_name(name: &str) -> Option<Box<dyn PlacementPolicy>> {
+    let policy: Box<dyn PlacementPolicy> = match name {
+        "random" => Box::new(Random),
+        "round-robin" => Box::<RoundRobin>::default(),
+        "least-used-capacity" => Box::new(LeastUsedCapacity),
+        "least-outstanding-requests" => Box::new(LeastOutstandingRequests),
+        "consistent-hashing" => Box::<ConsistentHashing>::default(),
+        _ => return None,
+    };
+    Some(policy)
+}
+
+static POLICY: OnceCell<Box<dyn PlacementPolicy>> = OnceCell::new();
+
+pub fn set_policy(policy: Box<dyn PlacementPolicy>) {
+    if POLICY.set(policy).is_err() {
+        panic!("placement policy has been set");
+    }
+}
+
+/// The policy in use, random by default
+pub fn policy() -> &'static dyn PlacementPolicy {
+    POLICY.get_or_init(|| Box::new(Random)).as_ref()
+}
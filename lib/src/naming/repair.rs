@@ -0,0 +1,165 @@
+//! Background repair of the files that lost replicas.
+//!
+//! The tree is scanned periodically. A chunk with fewer alive replicas than
+//! the replication factor of its file is copied from a surviving replica to
+//! healthy servers, and its dead replicas are dropped once it is fully
+//! replicated. Replicas on decommissioned servers are moved the same way, and
+//! so are the suspect ones, found corrupted while no healthy copy was left.
+//! Files whose factor has been lowered are shrunk on request only, so the
+//! replicas added for hot files survive the scans.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::common::path::DfsPath;
+
+use super::{
+    chunk::{self, Chunk},
+    dir_tree::{self, File},
+    replication,
+};
+
+/// Interval between two scans of the tree
+const REPAIR_PERIOD: Duration = Duration::from_secs(5);
+
+/// Alive replicas of the files without a replication factor of their own
+static REPLICATION_FACTOR: AtomicUsize = AtomicUsize::new(1);
+
+pub fn set_replication_factor(factor: usize) {
+    REPLICATION_FACTOR.store(factor.max(1), Ordering::Relaxed);
+}
+
+/// Alive replicas a file with the given replication should have
+pub fn replication_factor(replication: Option<usize>) -> usize {
+    replication.unwrap_or_else(|| REPLICATION_FACTOR.load(Ordering::Relaxed))
+}
+
+/// Return the replicas added. Unless `wait` is set, a file locked
+/// exclusively is left for the next scan.
+async fn repair_chunk(
+    path: &DfsPath,
+    chunk: &Chunk,
+    factor: usize,
+    shrink: bool,
+    wait: bool,
+) -> usize {
+    let (alive, dead): (Vec<_>, Vec<_>) = chunk
+        .servers()
+        .into_iter()
+        .partition(|s| s.in_service() && !chunk.is_suspect(s));
+    if !chunk.servers().iter().any(|s| s.is_alive()) {
+        // Hope that one of them comes back
+        log::warn!(
+            "repair_chunk: path {:?}, chunk {} has no alive replica",
+            path,
+            chunk.id
+        );
+        return 0;
+    }
+    let mut replicas = alive.len();
+    let mut added = 0;
+    if replicas < factor {
+        log::info!(
+            "repair_chunk: path {:?}, chunk {} has {} alive replicas, {} dead, leaving or suspect",
+            path,
+            chunk.id,
+            replicas,
+            dead.len()
+        );
+        match replication::add_replicas(path, Some(chunk.id), factor - replicas, wait).await {
+            Ok(count) => {
+                replicas += count;
+                added += count;
+            }
+            Err(err) => log::warn!("repair_chunk: path {:?}, err {:?}", path, err),
+        }
+    }
+    if replicas < factor {
+        // Keep the dead ones, they may come back before healthy servers do,
+        // and the leaving and suspect ones, they may be the only copies
+        return added;
+    }
+    if shrink && replicas > factor {
+        log::info!(
+            "repair_chunk: path {:?}, chunk {} has {} alive replicas, shrink to {}",
+            path,
+            chunk.id,
+            replicas,
+            factor
+        );
+        if let Err(err) = replication::remove_replicas(path, Some(chunk.id), factor).await {
+            log::warn!("repair_chunk: path {:?}, err {:?}", path, err);
+        }
+    }
+    // Leaving and suspect servers are alive, let them delete their copies too
+    let (leaving, dead): (Vec<_>, Vec<_>) = dead.into_iter().partition(|s| s.is_alive());
+    replication::drop_replicas(chunk.id, leaving).await;
+    for srv in dead {
+        log::info!(
+            "repair_chunk: chunk {}, drop dead server {:?}",
+            chunk.id,
+            srv.ip
+        );
+        if let Err(err) = chunk::remove_replica(chunk.id, srv).await {
+            log::warn!("repair_chunk: chunk {}, err {:?}", chunk.id, err);
+        }
+    }
+    added
+}
+
+/// Scan the whole tree once
+pub async fn repair() {
+    for (path, chunks, replication) in dir_tree::all_reg_files().await {
+        let factor = replication_factor(replication);
+        for chunk in chunks {
+            let srvs = chunk.servers();
+            let healthy = srvs.iter().filter(|s| s.in_service()).count() >= factor
+                && srvs.iter().all(|s| s.in_service())
+                && !chunk.has_suspect();
+            if !healthy {
+                repair_chunk(&path, &chunk, factor, false, false).await;
+            }
+        }
+    }
+}
+
+/// Repair the files under `path` right away. Return the replicas added.
+pub async fn repair_tree(target: Arc<File>, path: &DfsPath) -> usize {
+    let mut added = 0;
+    for (path, chunks, replication) in target.reg_files(path).await {
+        let factor = replication_factor(replication);
+        for chunk in chunks {
+            added += repair_chunk(&path, &chunk, factor, false, true).await;
+        }
+    }
+    added
+}
+
+/// Grow or shrink the replica sets of the files under `path` to match their
+/// replication factor, in the background
+pub fn resize(target: Arc<File>, path: &DfsPath) {
+    let path = path.clone();
+    rocket::tokio::spawn(async move {
+        for (path, chunks, replication) in target.reg_files(&path).await {
+            for chunk in chunks {
+                let factor = replication_factor(replication);
+                repair_chunk(&path, &chunk, factor, true, true).await;
+            }
+        }
+    });
+}
+
+pub fn start_repair_task() {
+    rocket::tokio::spawn(async {
+        let mut interval = rocket::tokio::time::interval(REPAIR_PERIOD);
+        loop {
+            interval.tick().await;
+            repair().await;
+        }
+    });
+}
@@ -0,0 +1,316 @@
+//! Replica management driven by the accesses of the clients.
+//!
+//! Every chunk of a file locked shared often enough is copied to one more
+//! storage server, while the chunks of a file locked exclusively lose the
+//! replicas beyond its replication factor, so that writes go to as few places
+//! as the file asks for and no stale extra copy survives.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use rocket::serde::{de::DeserializeOwned, Serialize};
+
+use crate::common::{
+    error::TinyDfsError,
+    path::DfsPath,
+    storage::{
+        ChunkId, CopyArg, CopyOkResponse, DeleteChunkArg, SizeArg, SizeOkResponse, TruncateChunkArg,
+    },
+};
+
+use super::{
+    chunk::{self, Chunk},
+    dir_tree::{self, File},
+    lock, placement, repair,
+    server::{self, StorageServer},
+};
+
+/// Shared accesses to a file before it gets one more replica
+static REPLICATION_THRESHOLD: AtomicU64 = AtomicU64::new(20);
+
+pub fn set_replication_threshold(threshold: u64) {
+    REPLICATION_THRESHOLD.store(threshold.max(1), Ordering::Relaxed);
+}
+
+/// Called once `path` has been locked, or located for a read
+pub async fn on_access(path: &DfsPath, exclusive: bool) {
+    let Ok((_, Some(target))) = dir_tree::lookup(path).await else {
+        return;
+    };
+    let File::RegFile(file) = target.as_ref() else {
+        return;
+    };
+    if exclusive {
+        let factor = repair::replication_factor(target.replication());
+        for chunk in file.chunks() {
+            invalidate(&chunk, factor).await;
+        }
+    } else if file.count_access(REPLICATION_THRESHOLD.load(Ordering::Relaxed)) {
+        let path = path.clone();
+        rocket::tokio::spawn(async move {
+            if let Err(err) = replicate(&path).await {
+                log::warn!("replicate: path {:?}, err {:?}", path, err);
+            }
+        });
+    }
+}
+
+/// Drop all replicas but `factor` of them (at least one), in service ones
+/// first, then alive ones
+async fn invalidate(chunk: &Chunk, factor: usize) {
+    let mut srvs = chunk.servers();
+    if !srvs.iter().any(|s| s.is_alive()) {
+        // Hope that one of them comes back
+        log::warn!("invalidate: chunk {} has no alive replica", chunk.id);
+        return;
+    }
+    // Stable, so the order of the replicas is kept otherwise
+    srvs.sort_by_key(|s| (!s.in_service(), !s.is_alive()));
+    drop_replicas(chunk.id, srvs.into_iter().skip(factor.max(1))).await;
+}
+
+/// Detach `srvs` from the chunk `id` and let them delete their copies
+pub async fn drop_replicas(id: ChunkId, srvs: impl IntoIterator<Item = Arc<StorageServer>>) {
+    let client = reqwest::Client::new();
+    for srv in srvs {
+        log::info!("drop_replicas: chunk {}, server {:?}", id, srv.ip);
+        if let Err(err) = chunk::remove_replica(id, srv.clone()).await {
+            log::warn!("drop_replicas: chunk {}, err {:?}", id, err);
+            continue;
+        }
+        let arg = DeleteChunkArg { chunk: id };
+        let addr = format!("http://{}/storage_delete", srv.command_addr());
+        if let Err(err) = post::<_, rocket::serde::json::Value>(&client, &addr, &arg).await {
+            log::warn!("drop_replicas: {} chunk {}, err {:?}", addr, id, err);
+        }
+    }
+}
+
+/// Copy every chunk of the file at `path` to a server that doesn't own it yet
+async fn replicate(path: &DfsPath) -> Result<(), TinyDfsError> {
+    add_replicas(path, None, 1, true).await.map(|_| ())
+}
+
+/// Copy the chunk `id` (or every chunk if None) of the file at `path` from an
+/// alive replica to up to `count` alive servers that don't own it yet.
+/// Return the fewest replicas added to a chunk, 0 if the file is locked
+/// exclusively and `wait` isn't set.
+pub async fn add_replicas(
+    path: &DfsPath,
+    id: Option<ChunkId>,
+    count: usize,
+    wait: bool,
+) -> Result<usize, TinyDfsError> {
+    // Nobody can write the file while it is being copied
+    let _guard = if wait {
+        lock::lock(path, false).await?
+    } else {
+        match lock::try_lock(path, false).await? {
+            Some(guard) => guard,
+            None => {
+                log::debug!("add_replicas: path {:?} is locked, skip it", path);
+                return Ok(0);
+            }
+        }
+    };
+    let mut fewest = count;
+    for (idx, chunk) in file_chunks(path, id).await? {
+        fewest = fewest.min(add_chunk_replicas(path, idx, &chunk, count).await?);
+    }
+    Ok(fewest)
+}
+
+/// The chunk `id` of the file at `path`, or all of them if None, along with
+/// their index in the file
+async fn file_chunks(
+    path: &DfsPath,
+    id: Option<ChunkId>,
+) -> Result<Vec<(u64, Arc<Chunk>)>, TinyDfsError> {
+    let (_, target) = dir_tree::lookup(path).await?;
+    let Some(File::RegFile(file)) = target.as_deref() else {
+        return Err(TinyDfsError::FileNotFound);
+    };
+    let chunks = (0..).zip(file.chunks());
+    match id {
+        None => Ok(chunks.collect()),
+        Some(id) => match chunks.clone().find(|(_, chunk)| chunk.id == id) {
+            Some(found) => Ok(vec![found]),
+            None => Err(TinyDfsError::FileNotFound),
+        },
+    }
+}
+
+async fn add_chunk_replicas(
+    path: &DfsPath,
+    idx: u64,
+    chunk: &Chunk,
+    count: usize,
+) -> Result<usize, TinyDfsError> {
+    let mut owners = chunk.servers();
+    // A suspect replica only serves as the source when it is the last one
+    let src = owners
+        .iter()
+        .filter(|s| s.is_alive())
+        .min_by_key(|s| chunk.is_suspect(s))
+        .cloned();
+    let Some(src) = src else {
+        return Err(TinyDfsError::NoServerAvailable);
+    };
+    let key = placement::chunk_key(path.as_str(), idx);
+    let mut added = 0;
+    while added < count {
+        let Some(dst) = server::select_server_except(&key, &owners).await else {
+            log::debug!("add_replicas: chunk {} is on every alive server", chunk.id);
+            break;
+        };
+        log::info!(
+            "add_replicas: path {:?}, chunk {}, {:?} -> {:?}",
+            path,
+            chunk.id,
+            src.ip,
+            dst.ip
+        );
+        copy_chunk(chunk.id, &src, &dst).await?;
+        chunk::add_replica(chunk.id, dst.clone()).await?;
+        owners.push(dst);
+        added += 1;
+    }
+    Ok(added)
+}
+
+/// Drop alive replicas of the chunk `id` (or every chunk if None) of the
+/// file at `path` until `keep` of them are left
+pub async fn remove_replicas(
+    path: &DfsPath,
+    id: Option<ChunkId>,
+    keep: usize,
+) -> Result<(), TinyDfsError> {
+    // Don't pull a replica out from under a reader
+    let _guard = lock::lock(path, true).await?;
+    for (_, chunk) in file_chunks(path, id).await? {
+        // Leaving servers are dropped by the repair task
+        let alive = chunk.servers().into_iter().filter(|s| s.in_service());
+        drop_replicas(chunk.id, alive.skip(keep)).await;
+    }
+    Ok(())
+}
+
+/// Let the owners of `chunks`, already out of the table, delete them
+pub async fn delete_chunks(chunks: &[Arc<Chunk>]) {
+    let client = reqwest::Client::new();
+    for chunk in chunks {
+        for srv in chunk.servers() {
+            let arg = DeleteChunkArg { chunk: chunk.id };
+            let addr = format!("http://{}/storage_delete", srv.command_addr());
+            if let Err(err) = post::<_, rocket::serde::json::Value>(&client, &addr, &arg).await {
+                log::warn!("delete_chunks: {} chunk {}, err {:?}", addr, chunk.id, err);
+            }
+        }
+    }
+}
+
+/// Cut every replica of `chunk` to `length` bytes. The replicas which fail
+/// are dropped for the repair task to copy the chunk again, unless none of
+/// them succeeds.
+pub async fn truncate_replicas(chunk: &Chunk, length: u64) -> Result<(), TinyDfsError> {
+    let arg = TruncateChunkArg {
+        chunk: chunk.id,
+        length,
+        grow_only: false,
+        epoch: chunk.epoch(),
+    };
+    resize_replicas(chunk, &arg).await
+}
+
+/// Fill every replica of `chunk` with zeros up to `length` bytes, leaving the
+/// longer ones alone. The replicas which fail are dropped as by
+/// `truncate_replicas`.
+pub async fn extend_replicas(chunk: &Chunk, length: u64) -> Result<(), TinyDfsError> {
+    let arg = TruncateChunkArg {
+        chunk: chunk.id,
+        length,
+        grow_only: true,
+        epoch: chunk.epoch(),
+    };
+    resize_replicas(chunk, &arg).await
+}
+
+async fn resize_replicas(chunk: &Chunk, arg: &TruncateChunkArg) -> Result<(), TinyDfsError> {
+    let client = reqwest::Client::new();
+    let mut failed = Vec::new();
+    let srvs = chunk.servers();
+    for srv in &srvs {
+        let addr = format!("http://{}/storage_truncate_chunk", srv.command_addr());
+        if let Err(err) = post::<_, rocket::serde::json::Value>(&client, &addr, arg).await {
+            log::warn!(
+                "resize_replicas: {} chunk {}, err {:?}",
+                addr,
+                chunk.id,
+                err
+            );
+            failed.push(srv.clone());
+        }
+    }
+    if !srvs.is_empty() && failed.len() == srvs.len() {
+        return Err(TinyDfsError::IOInterrupted);
+    }
+    if !failed.is_empty() {
+        drop_replicas(chunk.id, failed).await;
+        rocket::tokio::spawn(repair::repair());
+    }
+    Ok(())
+}
+
+/// Bytes held by the longest alive replica of `chunk`, None if none of
+/// them answers
+pub async fn replica_size(chunk: &Chunk) -> Option<u64> {
+    let client = reqwest::Client::new();
+    let arg = SizeArg { chunk: chunk.id };
+    let mut size = None;
+    for srv in chunk.servers().iter().filter(|srv| srv.is_alive()) {
+        let addr = format!("http://{}/storage_size", srv.ip);
+        match post::<_, SizeOkResponse>(&client, &addr, &arg).await {
+            Ok(resp) => size = size.max(Some(resp.size)),
+            Err(err) => log::warn!("replica_size: {} chunk {}, err {:?}", addr, chunk.id, err),
+        }
+    }
+    size
+}
+
+/// Let `dst` pull the chunk `id` from the client api of `src`
+async fn copy_chunk(
+    id: ChunkId,
+    src: &StorageServer,
+    dst: &StorageServer,
+) -> Result<(), TinyDfsError> {
+    let client = reqwest::Client::new();
+    let arg = CopyArg {
+        chunk: id,
+        source_ip: src.ip.host().to_string(),
+        source_port: src.ip.port(),
+    };
+    let addr = format!("http://{}/storage_copy", dst.command_addr());
+    let resp = post::<_, CopyOkResponse>(&client, &addr, &arg).await?;
+    log::debug!("copy_chunk: chunk {}, {} bytes", id, resp.bytes);
+    Ok(())
+}
+
+async fn post<A: Serialize, R: DeserializeOwned>(
+    client: &reqwest::Client,
+    addr: &str,
+    arg: &A,
+) -> Result<R, TinyDfsError> {
+    let resp = client
+        .post(addr)
+        .json(arg)
+        .send()
+        .await
+        .or(Err(TinyDfsError::IOInterrupted))?;
+    if !resp.status().is_success() {
+        log::warn!("post: {} status {:?}", addr, resp.status());
+        return Err(TinyDfsError::IOInterrupted);
+    }
+    resp.json().await.or(Err(TinyDfsError::IOInterrupted))
+}
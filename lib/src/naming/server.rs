@@ -1,27 +1,125 @@
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use once_cell::sync::Lazy;
-use rand::Rng;
 use rocket::tokio::sync::Mutex;
 
 use crate::common::error::TinyDfsError;
 
-use super::Ip;
+use super::{
+    journal::{self, Operation, ServerRecord},
+    placement, Ip,
+};
+
+/// A server missing heartbeats for this long is considered dead
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct StorageServer {
+    /// Where the client api listens
     pub ip: Ip,
-    pub client_port: u16,
     pub command_port: u16,
+    /// Time of the last heartbeat (or registration), None if not heard of
+    /// since the naming server started, e.g. restored from the journal
+    last_seen: std::sync::Mutex<Option<Instant>>,
+    /// Load reported by the last heartbeat
+    used_bytes: AtomicU64,
+    capacity_bytes: AtomicU64,
+    available_bytes: AtomicU64,
+    outstanding_requests: AtomicU64,
+    /// No new replica goes here, the existing ones are moved away
+    decommissioned: AtomicBool,
 }
 
 impl StorageServer {
-    pub fn new(ip: Ip, client_port: u16, command_port: u16) -> Self {
+    pub fn new(ip: Ip, command_port: u16) -> Self {
         Self {
             ip,
-            client_port,
             command_port,
+            last_seen: std::sync::Mutex::new(None),
+            used_bytes: AtomicU64::new(0),
+            capacity_bytes: AtomicU64::new(0),
+            available_bytes: AtomicU64::new(0),
+            outstanding_requests: AtomicU64::new(0),
+            decommissioned: AtomicBool::new(false),
         }
     }
+
+    /// `host:port` of the command api
+    pub fn command_addr(&self) -> String {
+        self.ip.host().with_port(self.command_port)
+    }
+
+    pub fn heartbeat(&self) {
+        *self.last_seen.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub fn report_load(&self, load: &Load) {
+        self.used_bytes.store(load.used_bytes, Ordering::Relaxed);
+        self.capacity_bytes
+            .store(load.capacity_bytes, Ordering::Relaxed);
+        self.available_bytes
+            .store(load.available_bytes, Ordering::Relaxed);
+        self.outstanding_requests
+            .store(load.outstanding_requests, Ordering::Relaxed);
+    }
+
+    /// Bytes of the chunks stored
+    pub fn used_bytes(&self) -> u64 {
+        self.used_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of the disk holding the files, 0 if unknown
+    pub fn capacity_bytes(&self) -> u64 {
+        self.capacity_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Bytes left on that disk
+    pub fn available_bytes(&self) -> u64 {
+        self.available_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Client requests being served
+    pub fn outstanding_requests(&self) -> u64 {
+        self.outstanding_requests.load(Ordering::Relaxed)
+    }
+
+    /// Count a client request sent here before the server reports it
+    pub fn expect_request(&self) {
+        self.outstanding_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Time since the last heartbeat, None if never seen
+    pub fn silence(&self) -> Option<Duration> {
+        self.last_seen.lock().unwrap().map(|seen| seen.elapsed())
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.silence()
+            .is_some_and(|silence| silence < SERVER_TIMEOUT)
+    }
+
+    pub fn is_decommissioned(&self) -> bool {
+        self.decommissioned.load(Ordering::Relaxed)
+    }
+
+    /// Alive and not decommissioned, so that its replicas count
+    pub fn in_service(&self) -> bool {
+        self.is_alive() && !self.is_decommissioned()
+    }
+}
+
+/// Load reported by a heartbeat
+#[derive(Debug, Default)]
+pub struct Load {
+    pub used_bytes: u64,
+    pub capacity_bytes: u64,
+    pub available_bytes: u64,
+    pub outstanding_requests: u64,
 }
 
 struct ServerManager {
@@ -36,31 +134,143 @@ impl ServerManager {
         }
     }
 
-    fn register_server(&mut self, srv: &Arc<StorageServer>) -> Result<(), TinyDfsError> {
-        if self.servers.iter().any(|s| s.ip == srv.ip) {
-            Err(TinyDfsError::StorageServerExists)
+    /// Return the registered one, which is the existing one if `srv` has
+    /// registered before (e.g. the storage server restarted).
+    /// Several servers may share an ip as long as they listen on other ports.
+    fn register_server(
+        &mut self,
+        srv: &Arc<StorageServer>,
+    ) -> Result<Arc<StorageServer>, TinyDfsError> {
+        let same_port = |s: &&Arc<StorageServer>| {
+            s.ip.host() == srv.ip.host()
+                && (s.ip.port() == srv.ip.port() || s.command_port == srv.command_port)
+        };
+        if let Some(existing) = self.servers.iter().find(same_port) {
+            if existing.ip == srv.ip && existing.command_port == srv.command_port {
+                Ok(existing.clone())
+            } else {
+                Err(TinyDfsError::StorageServerExists)
+            }
         } else {
-            Ok(self.servers.push(srv.clone()))
+            self.servers.push(srv.clone());
+            Ok(srv.clone())
         }
     }
 
+    fn find(&self, record: &ServerRecord) -> Option<Arc<StorageServer>> {
+        self.servers
+            .iter()
+            .find(|s| ServerRecord::from(s.as_ref()) == *record)
+            .cloned()
+    }
+
     // fn get(&self, idx: usize) -> Option<Arc<StorageServer>> {
     //     self.servers.get(idx).cloned()
     // }
 
-    fn get_random(&self) -> Option<Arc<StorageServer>> {
-        let mut rng = rand::thread_rng();
-        let idx = rng.gen_range(0..self.servers.len());
-        self.servers.get(idx).cloned()
+    fn find_by_client_addr(&self, ip: &Ip) -> Option<Arc<StorageServer>> {
+        self.servers.iter().find(|s| s.ip == *ip).cloned()
+    }
+
+    /// Only servers in service are selected
+    fn select_except(
+        &self,
+        key: &str,
+        excluded: &[Arc<StorageServer>],
+    ) -> Option<Arc<StorageServer>> {
+        let candidates: Vec<Arc<StorageServer>> = self
+            .servers
+            .iter()
+            .filter(|s| s.in_service() && !excluded.iter().any(|e| Arc::ptr_eq(s, e)))
+            .cloned()
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(placement::policy().place(key, &candidates))
     }
 }
 
 static SERVER_MANAGER: Lazy<Mutex<ServerManager>> = Lazy::new(|| Mutex::new(ServerManager::new()));
 
-pub async fn register_server(srv: &Arc<StorageServer>) -> Result<(), TinyDfsError> {
-    SERVER_MANAGER.lock().await.register_server(srv)
+pub async fn register_server(srv: &Arc<StorageServer>) -> Result<Arc<StorageServer>, TinyDfsError> {
+    let op = Operation::RegisterServer {
+        server: srv.as_ref().into(),
+    };
+    journal::commit(op, || restore_server(srv.clone())).await
+}
+
+/// Register without logging, used by both `register_server` and the journal replay
+pub async fn restore_server(srv: Arc<StorageServer>) -> Result<Arc<StorageServer>, TinyDfsError> {
+    SERVER_MANAGER.lock().await.register_server(&srv)
+}
+
+/// Select a server that isn't one of `excluded` for a new replica of the
+/// chunk `key`, as made by `placement::chunk_key`
+pub async fn select_server_except(
+    key: &str,
+    excluded: &[Arc<StorageServer>],
+) -> Option<Arc<StorageServer>> {
+    SERVER_MANAGER.lock().await.select_except(key, excluded)
+}
+
+/// Select up to `count` distinct servers for the replicas of the chunk `key`
+pub async fn select_servers(key: &str, count: usize) -> Vec<Arc<StorageServer>> {
+    let manager = SERVER_MANAGER.lock().await;
+    let mut srvs = Vec::new();
+    while srvs.len() < count {
+        match manager.select_except(key, &srvs) {
+            Some(srv) => srvs.push(srv),
+            None => break,
+        }
+    }
+    srvs
+}
+
+pub async fn find_server(record: &ServerRecord) -> Option<Arc<StorageServer>> {
+    SERVER_MANAGER.lock().await.find(record)
+}
+
+/// The server whose client api listens at `ip`, which is unique
+pub async fn find_by_client_addr(ip: &Ip) -> Option<Arc<StorageServer>> {
+    SERVER_MANAGER.lock().await.find_by_client_addr(ip)
+}
+
+/// Record a heartbeat of the server described by `record`, along with the
+/// load it reports. Return the server.
+pub async fn heartbeat(
+    record: &ServerRecord,
+    load: &Load,
+) -> Result<Arc<StorageServer>, TinyDfsError> {
+    let srv = find_server(record)
+        .await
+        .ok_or(TinyDfsError::ServerNotRegistered)?;
+    if !srv.is_alive() {
+        log::info!("heartbeat: server {:?} is alive again", record);
+    }
+    srv.heartbeat();
+    srv.report_load(load);
+    Ok(srv)
+}
+
+pub async fn all_servers() -> Vec<Arc<StorageServer>> {
+    SERVER_MANAGER.lock().await.servers.clone()
+}
+
+/// Stop placing replicas on `srv` and let the repair task move its replicas
+/// away. The server stays registered so that it can be watched draining.
+pub async fn decommission(srv: Arc<StorageServer>) -> Result<(), TinyDfsError> {
+    let op = Operation::Decommission {
+        server: srv.as_ref().into(),
+    };
+    journal::commit(op, || async {
+        apply_decommission(&srv);
+        Ok(())
+    })
+    .await
 }
 
-pub async fn select_random_server() -> Option<Arc<StorageServer>> {
-    SERVER_MANAGER.lock().await.get_random()
+/// Decommission without logging, used by both `decommission` and the journal replay
+pub fn apply_decommission(srv: &StorageServer) {
+    srv.decommissioned.store(true, Ordering::Relaxed);
 }
@@ -1,34 +1,50 @@
-use std::fs;
+use std::{
+    fs,
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+};
 
-use rocket::{http::Status, serde::json::Json};
+use rocket::http::Status;
 
 use crate::{
     common::{
-        service::{CreateFileArg, CreateFileResponse, DeleteArg, DeleteResponse},
+        addr::{Host, Ip},
+        error::TinyDfsError,
+        storage::{
+            ChunkId, CopyArg, CopyOkResponse, CopyResponse, CreateChunkArg, CreateChunkResponse,
+            DeleteChunkArg, DeleteChunkResponse, ReadArg, ReadOkResponse, ScrubOkResponse, SizeArg,
+            SizeOkResponse, TruncateChunkArg, TruncateChunkResponse,
+        },
+        wire::Wire,
         ErrResponse, OkResponse,
     },
-    storage::path,
+    storage::{capacity, checksum, forget_chunk, forget_write, load, lock, path, scrub, set_epoch},
 };
 
 #[post("/storage_delete", data = "<arg>")]
-pub fn delete_file(arg: Json<DeleteArg>) -> (Status, DeleteResponse) {
-    let global_path: &str = &arg.path;
-    let local_path = path::global_to_local(global_path);
+pub async fn delete_chunk(arg: Wire<DeleteChunkArg>) -> (Status, DeleteChunkResponse) {
+    let local_path = path::chunk_to_local(arg.chunk);
 
-    log::info!("delete_file: local path {:?}", local_path);
-    // todo!("delete empty directory");
+    log::info!("delete_chunk: local path {:?}", local_path);
+    let _guard = lock::write(arg.chunk).await;
+    let bytes = load::chunk_bytes(arg.chunk);
     if fs::remove_file(local_path).is_ok() {
+        load::chunk_resized(bytes, 0);
+        let _ = fs::remove_file(path::checksum_to_local(arg.chunk));
+        capacity::remove(arg.chunk);
+        forget_chunk(arg.chunk);
         (
             Status::Ok,
-            DeleteResponse::OkResp(OkResponse { success: true }.into()),
+            DeleteChunkResponse::OkResp(OkResponse { success: true }.into()),
         )
     } else {
+        let (status, etype, einfo) = TinyDfsError::FileNotFound.exception();
         (
-            Status::NotFound,
-            DeleteResponse::ErrResp(
+            status,
+            DeleteChunkResponse::ErrResp(
                 ErrResponse {
-                    exception_type: "FileNotFoundException".to_string(),
-                    exception_info: "the object or parent directory does not exist.".to_string(),
+                    exception_type: etype.to_string(),
+                    exception_info: einfo.to_string(),
                 }
                 .into(),
             ),
@@ -37,31 +53,219 @@ pub fn delete_file(arg: Json<DeleteArg>) -> (Status, DeleteResponse) {
 }
 
 #[post("/storage_create", data = "<arg>")]
-pub fn create_file(arg: Json<CreateFileArg>) -> (Status, CreateFileResponse) {
-    let global_path: &str = &arg.path;
-    let local_path = path::global_to_local(global_path);
+pub async fn create_chunk(arg: Wire<CreateChunkArg>) -> (Status, CreateChunkResponse) {
+    let local_path = path::chunk_to_local(arg.chunk);
 
-    log::info!("create_file: local path {:?}", local_path);
-    // Also create the missing intermediate ones
-    let local_path = std::path::Path::new(&local_path);
-    if let Some(parent_dir) = local_path.parent() {
-        fs::create_dir_all(parent_dir).unwrap();
-    }
-    if fs::File::create(local_path).is_ok() {
+    log::info!("create_chunk: local path {:?}", local_path);
+    let _guard = lock::write(arg.chunk).await;
+    let bytes = load::chunk_bytes(arg.chunk);
+    if fs::File::create(local_path).is_ok()
+        && fs::File::create(path::checksum_to_local(arg.chunk)).is_ok()
+        && capacity::store(arg.chunk, arg.chunk_size).is_ok()
+    {
+        load::chunk_resized(bytes, 0);
         (
             Status::Ok,
-            CreateFileResponse::OkResp(OkResponse { success: true }.into()),
+            CreateChunkResponse::OkResp(OkResponse { success: true }.into()),
         )
     } else {
+        let (status, etype, einfo) = TinyDfsError::PathInvalid.exception();
         (
-            Status::NotFound,
-            CreateFileResponse::ErrResp(
+            status,
+            CreateChunkResponse::ErrResp(
                 ErrResponse {
-                    exception_type: "IllegalArgumentException".into(),
-                    exception_info: "IllegalArgumentException: path invalid.".into(),
+                    exception_type: etype.to_string(),
+                    exception_info: einfo.to_string(),
                 }
                 .into(),
             ),
         )
     }
 }
+
+/// Bytes fetched from the source by a single read
+const COPY_CHUNK: u64 = 1 << 20;
+
+/// Pull the chunk `chunk` from the client api at `source_addr` into
+/// `tmp_path`, then move it to `local_path`. Return the bytes copied.
+async fn pull_chunk(
+    source_addr: &str,
+    chunk: ChunkId,
+    local_path: &Path,
+    tmp_path: &Path,
+) -> Result<u64, TinyDfsError> {
+    let client = reqwest::Client::new();
+    let arg = SizeArg { chunk };
+    let resp = client
+        .post(format!("{}/storage_size", source_addr))
+        .json(&arg)
+        .send()
+        .await
+        .or(Err(TinyDfsError::IOInterrupted))?;
+    if !resp.status().is_success() {
+        return Err(TinyDfsError::FileNotFound);
+    }
+    let resp: SizeOkResponse = resp.json().await.or(Err(TinyDfsError::IOInterrupted))?;
+    let size = resp.size;
+    let chunk_size = resp.chunk_size;
+    let epoch = resp.epoch;
+
+    // Write into a temp file first so that nobody sees a partial copy
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .read(true)
+        .write(true)
+        .open(tmp_path)
+        .or(Err(TinyDfsError::PathInvalid))?;
+    let mut offset = 0;
+    while offset < size {
+        let length = COPY_CHUNK.min(size - offset);
+        let arg = ReadArg {
+            chunk,
+            offset,
+            length: length as i32,
+        };
+        let resp = client
+            .post(format!("{}/storage_read", source_addr))
+            .json(&arg)
+            .send()
+            .await
+            .or(Err(TinyDfsError::IOInterrupted))?;
+        if !resp.status().is_success() {
+            log::warn!("pull_chunk:{}: status {:?}", line!(), resp.status());
+            return Err(TinyDfsError::IOInterrupted);
+        }
+        let resp: ReadOkResponse = resp.json().await.or(Err(TinyDfsError::IOInterrupted))?;
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.write_all(&resp.data.0))
+            .or(Err(TinyDfsError::IOInterrupted))?;
+        offset += length;
+    }
+    file.sync_all().or(Err(TinyDfsError::IOInterrupted))?;
+    // The checksums are rebuilt in place, so only the move is left to do
+    let _guard = lock::write(chunk).await;
+    checksum::rebuild(chunk, &mut file)?;
+    capacity::store(chunk, chunk_size).or(Err(TinyDfsError::IOInterrupted))?;
+    let old_bytes = load::chunk_bytes(chunk);
+    fs::rename(tmp_path, local_path).or(Err(TinyDfsError::IOInterrupted))?;
+    load::chunk_resized(old_bytes, size);
+    set_epoch(chunk, epoch);
+    Ok(size)
+}
+
+#[post("/storage_copy", data = "<arg>")]
+pub async fn copy_chunk(arg: Wire<CopyArg>) -> (Status, CopyResponse) {
+    let err_ret = |err: TinyDfsError| {
+        let (status, etype, einfo) = err.exception();
+        (
+            status,
+            CopyResponse::ErrResp(
+                ErrResponse {
+                    exception_info: einfo.to_string(),
+                    exception_type: etype.to_string(),
+                }
+                .into(),
+            ),
+        )
+    };
+    let local_path = path::chunk_to_local(arg.chunk);
+
+    log::info!(
+        "copy_chunk: local path {:?} from {}:{}",
+        local_path,
+        arg.source_ip,
+        arg.source_port
+    );
+    let local_path = Path::new(&local_path);
+    let source_addr =
+        match Host::parse(&arg.source_ip).and_then(|host| Ip::new(host, arg.source_port)) {
+            Ok(ip) => format!("http://{}", ip),
+            Err(err) => return err_ret(err),
+        };
+    let tmp_path = path::copying_to_local(arg.chunk);
+    let tmp_path = Path::new(&tmp_path);
+    match pull_chunk(&source_addr, arg.chunk, local_path, tmp_path).await {
+        Ok(bytes) => (
+            Status::Ok,
+            CopyResponse::OkResp(CopyOkResponse { bytes }.into()),
+        ),
+        Err(err) => {
+            log::warn!("copy_chunk: chunk {} failed, err {:?}", arg.chunk, err);
+            let _ = fs::remove_file(tmp_path);
+            err_ret(err)
+        }
+    }
+}
+
+#[post("/storage_truncate_chunk", data = "<arg>")]
+pub async fn truncate_chunk(arg: Wire<TruncateChunkArg>) -> (Status, TruncateChunkResponse) {
+    let err_ret = |err: TinyDfsError| {
+        let (status, etype, einfo) = err.exception();
+        (
+            status,
+            TruncateChunkResponse::ErrResp(
+                ErrResponse {
+                    exception_info: einfo.to_string(),
+                    exception_type: etype.to_string(),
+                }
+                .into(),
+            ),
+        )
+    };
+    let local_path = path::chunk_to_local(arg.chunk);
+
+    log::info!(
+        "truncate_chunk: local path {:?}, length {}",
+        local_path,
+        arg.length
+    );
+    let _guard = lock::write(arg.chunk).await;
+    let Ok(mut file) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(local_path)
+    else {
+        return err_ret(TinyDfsError::FileNotFound);
+    };
+    let Ok(old_length) = file.metadata().map(|metadata| metadata.len()) else {
+        return err_ret(TinyDfsError::FileNotFound);
+    };
+    let length = if arg.grow_only {
+        arg.length.max(old_length)
+    } else {
+        arg.length
+    };
+    // The block the new end falls in keeps some of its bytes, as by a write there
+    if let Err(err) = checksum::verify_kept(arg.chunk, &mut file, length, Some(0)) {
+        log::warn!("truncate_chunk: chunk {}, err {:?}", arg.chunk, err);
+        return err_ret(err);
+    }
+    // A replica missing the tail of a failed append is filled with zeros
+    if file.set_len(length).is_err() {
+        return err_ret(TinyDfsError::IOInterrupted);
+    }
+    load::chunk_resized(old_length, length);
+    if !arg.grow_only {
+        // Sizes from before the truncation no longer hold
+        forget_write(arg.chunk);
+    }
+    set_epoch(arg.chunk, arg.epoch);
+    match checksum::resize(arg.chunk, &mut file, old_length) {
+        Ok(_) => (
+            Status::Ok,
+            TruncateChunkResponse::OkResp(OkResponse { success: true }.into()),
+        ),
+        Err(err) => err_ret(err),
+    }
+}
+
+/// Verify all local chunks right away, as the scrubber does periodically
+#[post("/storage_scrub")]
+pub async fn scrub_chunks() -> Wire<ScrubOkResponse> {
+    log::info!("scrub_chunks: start to scrub local chunks...");
+    ScrubOkResponse {
+        corrupted: scrub::scrub().await,
+    }
+    .into()
+}
@@ -3,22 +3,26 @@ use std::{
     io::{ErrorKind, Read, Seek, SeekFrom, Write},
 };
 
-use rocket::{http::Status, serde::json::Json};
+use rocket::{futures::future::join_all, http::Status};
 
 use crate::{
     common::{
         error::TinyDfsError,
+        path::DfsPath,
+        registration::{ReserveAppendArg, ReserveAppendOkResponse},
         storage::{
-            base64_decode, base64_encode, ReadArg, ReadOkResponse, ReadResponse, SizeArg,
-            SizeOkResponse, SizeResponse, WriteArg, WriteResponse,
+            AppendArg, AppendOkResponse, AppendResponse, ReadArg, ReadOkResponse, ReadResponse,
+            SizeArg, SizeOkResponse, SizeResponse, TruncateArg, TruncateResponse, WriteArg,
+            WriteResponse,
         },
+        wire::{FileData, Wire},
         ErrResponse, OkResponse,
     },
-    storage::path::{self, path_is_invalid},
+    storage::{ask_naming, capacity, checksum, epoch, err_resp, load, lock, note_write, path},
 };
 
 #[post("/storage_size", data = "<arg>")]
-pub fn get_size(arg: Json<SizeArg>) -> (Status, SizeResponse) {
+pub async fn get_size(arg: Wire<SizeArg>) -> (Status, SizeResponse) {
     let err_ret = |err: TinyDfsError| {
         let (status, etype, einfo) = err.exception();
         (
@@ -32,21 +36,20 @@ pub fn get_size(arg: Json<SizeArg>) -> (Status, SizeResponse) {
             ),
         )
     };
-    let global_path: &str = &arg.path;
-    if path_is_invalid(global_path) {
-        return err_ret(TinyDfsError::PathInvalid);
-    }
-    let local_path = path::global_to_local(global_path);
+    let local_path = path::chunk_to_local(arg.chunk);
 
     log::info!("get_size: local path {:?}", local_path);
 
+    let _guard = lock::read(arg.chunk).await;
     let metadata = fs::metadata(local_path);
-    if let Some(metadata) = metadata.ok() {
+    if let Ok(metadata) = metadata {
         (
             Status::Ok,
             SizeResponse::OkResp(
                 SizeOkResponse {
                     size: metadata.len(),
+                    chunk_size: capacity::load(arg.chunk).unwrap_or(0),
+                    epoch: epoch(arg.chunk),
                 }
                 .into(),
             ),
@@ -57,7 +60,7 @@ pub fn get_size(arg: Json<SizeArg>) -> (Status, SizeResponse) {
 }
 
 #[post("/storage_read", data = "<arg>")]
-pub fn read_file(arg: Json<ReadArg>) -> (Status, ReadResponse) {
+pub async fn read_file(arg: Wire<ReadArg>) -> (Status, ReadResponse) {
     let err_ret = |err: TinyDfsError| {
         let (status, etype, einfo) = err.exception();
         (
@@ -72,44 +75,56 @@ pub fn read_file(arg: Json<ReadArg>) -> (Status, ReadResponse) {
         )
     };
 
-    let global_path: &str = &arg.path;
-    if path_is_invalid(global_path) {
-        return err_ret(TinyDfsError::PathInvalid);
-    }
-    let local_path = path::global_to_local(global_path);
+    let local_path = path::chunk_to_local(arg.chunk);
 
     log::info!("read_file: local path {:?}", local_path);
 
     if arg.length < 0 {
         return err_ret(TinyDfsError::IndexOutOfBound);
     }
+    let _guard = lock::read(arg.chunk).await;
     let file = fs::File::open(local_path);
     if file.is_err() {
         return err_ret(TinyDfsError::FileNotFound);
     }
     let mut file = file.unwrap();
-    if file.seek(SeekFrom::Start(arg.offset)).is_err() {
+    let Ok(size) = file.metadata().map(|metadata| metadata.len()) else {
+        return err_ret(TinyDfsError::FileNotFound);
+    };
+    let end = arg.offset.saturating_add(arg.length as u64);
+    if end > capacity::readable(arg.chunk, size) {
         return err_ret(TinyDfsError::IndexOutOfBound);
     }
+    // Only the bytes on the disk are read, a hole past them reads as zeros
     let mut buf = vec![0; arg.length as usize];
-    if let Some(err) = file.read_exact(&mut buf).err() {
+    let stored = end.min(size).saturating_sub(arg.offset);
+    if file.seek(SeekFrom::Start(arg.offset)).is_err() {
+        return err_ret(TinyDfsError::IndexOutOfBound);
+    }
+    if let Some(err) = file.read_exact(&mut buf[..stored as usize]).err() {
         let resp_err = match err.kind() {
             ErrorKind::UnexpectedEof => TinyDfsError::IndexOutOfBound,
             ErrorKind::Interrupted => TinyDfsError::IOInterrupted,
             _ => TinyDfsError::FileNotFound,
         };
-        return err_ret(resp_err);
+        err_ret(resp_err)
+    } else if let Err(err) = checksum::verify(arg.chunk, &mut file, arg.offset, stored) {
+        err_ret(err)
     } else {
-        let encoded = base64_encode(buf);
         (
             Status::Ok,
-            ReadResponse::OkResp(ReadOkResponse { data: encoded }.into()),
+            ReadResponse::OkResp(
+                ReadOkResponse {
+                    data: FileData(buf),
+                }
+                .into(),
+            ),
         )
     }
 }
 
 #[post("/storage_write", data = "<arg>")]
-pub fn write_file(arg: Json<WriteArg>) -> (Status, WriteResponse) {
+pub async fn write_file(arg: Wire<WriteArg>) -> (Status, WriteResponse) {
     let err_ret = |err: TinyDfsError| {
         let (status, etype, einfo) = err.exception();
         (
@@ -124,14 +139,17 @@ pub fn write_file(arg: Json<WriteArg>) -> (Status, WriteResponse) {
         )
     };
 
-    let global_path: &str = &arg.path;
-    if path_is_invalid(global_path) {
-        return err_ret(TinyDfsError::PathInvalid);
-    }
-    let local_path = path::global_to_local(global_path);
+    let local_path = path::chunk_to_local(arg.chunk);
 
     log::info!("write_file: local path {:?}", local_path);
 
+    let end = arg.offset.checked_add(arg.data.0.len() as u64);
+    if end.is_none_or(|end| capacity::load(arg.chunk).is_some_and(|capacity| end > capacity)) {
+        log::warn!("write_file:{}: past the end of the chunk", line!());
+        return err_ret(TinyDfsError::IndexOutOfBound);
+    }
+    // Kept until the write is noted, so that a truncation drops the note
+    let _guard = lock::write(arg.chunk).await;
     let file = fs::OpenOptions::new()
         .read(true)
         .write(true)
@@ -141,23 +159,131 @@ pub fn write_file(arg: Json<WriteArg>) -> (Status, WriteResponse) {
         return err_ret(TinyDfsError::FileNotFound);
     }
     let mut file = file.unwrap();
+    let decoded = &arg.data.0;
+    if let Err(err) =
+        checksum::verify_kept(arg.chunk, &mut file, arg.offset, Some(decoded.len() as u64))
+    {
+        log::warn!("write_file:{}: checksum err {:?}", line!(), err);
+        return err_ret(err);
+    }
     if file.seek(SeekFrom::Start(arg.offset)).is_err() {
         log::warn!("write_file:{}: seek failed", line!());
         return err_ret(TinyDfsError::IndexOutOfBound);
     }
-    let decoded = base64_decode(&arg.data).unwrap();
-    if let Some(err) = file.write_all(&decoded).err() {
+    let old_bytes = file.metadata().map_or(0, |meta| meta.len());
+    let written = file.write_all(decoded);
+    load::chunk_resized(
+        old_bytes,
+        file.metadata().map_or(old_bytes, |meta| meta.len()),
+    );
+    if let Some(err) = written.err() {
         let resp_err = match err.kind() {
             ErrorKind::UnexpectedEof => TinyDfsError::IndexOutOfBound,
             ErrorKind::Interrupted => TinyDfsError::IOInterrupted,
             _ => TinyDfsError::FileNotFound,
         };
         log::warn!("write_file:{}: write err, kind {:?}", line!(), err.kind());
-        return err_ret(resp_err);
+        err_ret(resp_err)
+    } else if let Err(err) =
+        checksum::update(arg.chunk, &mut file, arg.offset, decoded.len() as u64)
+    {
+        log::warn!("write_file:{}: checksum err {:?}", line!(), err);
+        err_ret(err)
     } else {
+        note_write(arg.chunk);
         (
             Status::Ok,
             WriteResponse::OkResp(OkResponse { success: true }.into()),
         )
     }
 }
+
+/// Cut a file. The naming server updates the metadata and has every replica
+/// of the chunks involved cut.
+#[post("/storage_truncate", data = "<arg>")]
+pub async fn truncate_file(arg: Wire<TruncateArg>) -> (Status, TruncateResponse) {
+    let res = async {
+        let path = DfsPath::parse(&arg.path).map_err(err_resp)?;
+        log::info!("truncate_file: path {:?}, length {}", path, arg.length);
+        let arg = TruncateArg {
+            path: path.to_string(),
+            length: arg.length,
+        };
+        ask_naming::<_, OkResponse>("truncate_file", &arg).await
+    }
+    .await;
+    match res {
+        Ok(resp) => (Status::Ok, TruncateResponse::OkResp(resp.into())),
+        Err((status, resp)) => (status, TruncateResponse::ErrResp(resp)),
+    }
+}
+
+/// Write `data` into the range of a file reserved at `offset` on every alive
+/// replica of its chunks
+async fn write_replicas(
+    reserved: &ReserveAppendOkResponse,
+    data: &[u8],
+) -> Result<(), TinyDfsError> {
+    let client = reqwest::Client::new();
+    let end = reserved.offset + data.len() as u64;
+    let mut requests = Vec::new();
+    for chunk in &reserved.chunks {
+        let start = reserved.offset.max(chunk.offset);
+        let stop = end.min(chunk.offset + reserved.chunk_size);
+        let part = &data[(start - reserved.offset) as usize..(stop - reserved.offset) as usize];
+        for srv in &chunk.servers {
+            let arg = WriteArg {
+                chunk: chunk.chunk,
+                offset: start - chunk.offset,
+                data: FileData(part.to_vec()),
+            };
+            let addr = format!(
+                "http://{}/storage_write",
+                srv.server_ip.with_port(srv.server_port)
+            );
+            requests.push(client.post(addr).json(&arg).send());
+        }
+    }
+    let mut res = Ok(());
+    for resp in join_all(requests).await {
+        match resp {
+            Ok(resp) if resp.status().is_success() => {}
+            Ok(resp) => {
+                log::warn!("write_replicas: {} status {:?}", resp.url(), resp.status());
+                res = Err(TinyDfsError::IOInterrupted);
+            }
+            Err(err) => {
+                log::warn!("write_replicas: err {:?}", err);
+                res = Err(TinyDfsError::IOInterrupted);
+            }
+        }
+    }
+    res
+}
+
+/// Write at the end of a file, whatever its size. Concurrent appends never
+/// overlap, as the naming server reserves their ranges one at a time. A
+/// failed append leaves its range to zeros or partly written.
+#[post("/storage_append", data = "<arg>")]
+pub async fn append_file(arg: Wire<AppendArg>) -> (Status, AppendResponse) {
+    let res = async {
+        let path = DfsPath::parse(&arg.path).map_err(err_resp)?;
+        let data = &arg.data.0;
+        log::info!("append_file: path {:?}, {} bytes", path, data.len());
+        let reserve = ReserveAppendArg {
+            path: path.to_string(),
+            length: data.len() as u64,
+        };
+        let reserved: ReserveAppendOkResponse = ask_naming("reserve_append", &reserve).await?;
+        write_replicas(&reserved, data).await.map_err(err_resp)?;
+        Ok(reserved.offset)
+    }
+    .await;
+    match res {
+        Ok(offset) => (
+            Status::Ok,
+            AppendResponse::OkResp(AppendOkResponse { offset }.into()),
+        ),
+        Err((status, resp)) => (status, AppendResponse::ErrResp(resp)),
+    }
+}
@@ -0,0 +1,323 @@
+//! Raw `application/octet-stream` access to the chunks, without the base64
+//! inflation of the JSON api. Reads honour a single `Range` header.
+
+use std::{
+    convert::Infallible,
+    fs,
+    io::SeekFrom,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use rocket::{
+    data::{ByteUnit, Data},
+    http::{ContentType, Status},
+    request::{FromRequest, Outcome},
+    response::{self, Responder},
+    tokio::{
+        fs::File,
+        io::{self, AsyncRead, AsyncReadExt, AsyncSeekExt, ReadBuf},
+        sync::OwnedRwLockReadGuard,
+    },
+    Request, Response,
+};
+
+use crate::{
+    common::{
+        error::TinyDfsError,
+        storage::{ChunkId, StreamWriteOkResponse, StreamWriteResponse},
+        wire::Wire,
+        ErrResponse,
+    },
+    storage::{capacity, checksum, load, lock, note_write, path},
+};
+
+/// Bytes accepted by a single write into a chunk of unknown capacity, beyond
+/// any sane chunk size
+const WRITE_LIMIT: ByteUnit = ByteUnit::Gibibyte(1);
+
+/// Value of the `Range` header, if any
+pub struct RangeHeader(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeHeader {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RangeHeader(
+            req.headers().get_one("Range").map(str::to_string),
+        ))
+    }
+}
+
+/// Value of the `Content-Length` header, if any
+pub struct ContentLength(Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ContentLength {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ContentLength(
+            req.headers()
+                .get_one("Content-Length")
+                .and_then(|value| value.parse().ok()),
+        ))
+    }
+}
+
+/// Whether the `Range` header `value` is in bytes, the only unit supported.
+/// A `Range` header in any other unit is ignored.
+fn is_byte_range(value: &str) -> bool {
+    value.trim_start().starts_with("bytes=")
+}
+
+/// Bytes `start..end` of a chunk of `size` bytes asked by the `Range` header
+/// `value`. Only a single range is supported.
+fn parse_range(value: &str, size: u64) -> Option<(u64, u64)> {
+    let (first, last) = value.trim_start().strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (first.trim(), last.trim()) {
+        ("", suffix) => (size.saturating_sub(suffix.parse().ok()?), size),
+        (first, "") => (first.parse().ok()?, size),
+        (first, last) => (
+            first.parse().ok()?,
+            last.parse::<u64>().ok()?.saturating_add(1).min(size),
+        ),
+    };
+    (start < end).then_some((start, end))
+}
+
+/// A body keeping the read lock of its chunk until it has been streamed, so
+/// that no write changes the bytes verified in the meantime
+struct LockedBody<R> {
+    body: R,
+    _guard: OwnedRwLockReadGuard<()>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for LockedBody<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().body).poll_read(cx, buf)
+    }
+}
+
+pub struct ChunkStream {
+    body: Box<dyn AsyncRead + Send + Unpin>,
+    /// `Content-Range` of a partial response
+    content_range: Option<String>,
+}
+
+impl<'r> Responder<'r, 'static> for ChunkStream {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let mut resp = Response::build();
+        resp.header(ContentType::Binary)
+            .raw_header("Accept-Ranges", "bytes");
+        if let Some(content_range) = self.content_range {
+            resp.raw_header("Content-Range", content_range);
+        }
+        resp.streamed_body(self.body).ok()
+    }
+}
+
+#[derive(Responder)]
+pub enum StreamReadResponse {
+    OkResp(ChunkStream),
+    ErrResp(Wire<ErrResponse>),
+}
+
+#[get("/storage_chunk/<chunk>?<offset>&<length>")]
+pub async fn read_chunk(
+    chunk: ChunkId,
+    offset: Option<u64>,
+    length: Option<u64>,
+    range: RangeHeader,
+) -> (Status, StreamReadResponse) {
+    let err_ret = |err: TinyDfsError| {
+        let (status, etype, einfo) = err.exception();
+        (
+            status,
+            StreamReadResponse::ErrResp(
+                ErrResponse {
+                    exception_type: etype.to_string(),
+                    exception_info: einfo.to_string(),
+                }
+                .into(),
+            ),
+        )
+    };
+    let local_path = path::chunk_to_local(chunk);
+
+    log::info!("read_chunk: local path {:?}", local_path);
+
+    let guard = lock::read(chunk).await;
+    let Ok(mut file) = fs::File::open(local_path) else {
+        return err_ret(TinyDfsError::FileNotFound);
+    };
+    let Ok(size) = file.metadata().map(|metadata| metadata.len()) else {
+        return err_ret(TinyDfsError::FileNotFound);
+    };
+    let range = range.0.filter(|value| is_byte_range(value));
+    let (start, end) = match &range {
+        Some(value) => match parse_range(value, size) {
+            Some(window) => window,
+            None => {
+                let (_, resp) = err_ret(TinyDfsError::IndexOutOfBound);
+                return (Status::RangeNotSatisfiable, resp);
+            }
+        },
+        None => {
+            let start = offset.unwrap_or(0);
+            let end = length.map_or(size.max(start), |length| start.saturating_add(length));
+            if end > capacity::readable(chunk, size) {
+                return err_ret(TinyDfsError::IndexOutOfBound);
+            }
+            (start, end)
+        }
+    };
+    // Only the bytes on the disk are read, a hole past them reads as zeros
+    let stored = end.min(size).saturating_sub(start);
+
+    let (file, verified) = rocket::tokio::task::spawn_blocking(move || {
+        let verified = checksum::verify(chunk, &mut file, start, stored);
+        (file, verified)
+    })
+    .await
+    .unwrap();
+    if let Err(err) = verified {
+        return err_ret(err);
+    }
+    let mut file = File::from_std(file);
+    if file.seek(SeekFrom::Start(start)).await.is_err() {
+        return err_ret(TinyDfsError::IOInterrupted);
+    }
+    let (status, content_range) = if range.is_some() {
+        let content_range = format!("bytes {}-{}/{}", start, end - 1, size);
+        (Status::PartialContent, Some(content_range))
+    } else {
+        (Status::Ok, None)
+    };
+    (
+        status,
+        StreamReadResponse::OkResp(ChunkStream {
+            body: Box::new(LockedBody {
+                body: file
+                    .take(stored)
+                    .chain(io::repeat(0).take(end - start - stored)),
+                _guard: guard,
+            }),
+            content_range,
+        }),
+    )
+}
+
+/// Write the body at `offset`. Writes past the capacity of the chunk are
+/// rejected, up front if the body has a `Content-Length`.
+#[put("/storage_chunk/<chunk>?<offset>", data = "<data>")]
+pub async fn write_chunk(
+    chunk: ChunkId,
+    offset: Option<u64>,
+    length: ContentLength,
+    data: Data<'_>,
+) -> (Status, StreamWriteResponse) {
+    let err_ret = |err: TinyDfsError| {
+        let (status, etype, einfo) = err.exception();
+        (
+            status,
+            StreamWriteResponse::ErrResp(
+                ErrResponse {
+                    exception_type: etype.to_string(),
+                    exception_info: einfo.to_string(),
+                }
+                .into(),
+            ),
+        )
+    };
+    let local_path = path::chunk_to_local(chunk);
+    let offset = offset.unwrap_or(0);
+
+    log::info!(
+        "write_chunk: local path {:?}, offset {}",
+        local_path,
+        offset
+    );
+
+    let limit = match capacity::load(chunk) {
+        Some(capacity) if offset > capacity => None,
+        Some(capacity) => Some(ByteUnit::from(capacity - offset)),
+        None => Some(WRITE_LIMIT),
+    };
+    let Some(limit) = limit.filter(|limit| length.0.is_none_or(|length| length <= *limit)) else {
+        log::warn!("write_chunk:{}: past the end of the chunk", line!());
+        return err_ret(TinyDfsError::IndexOutOfBound);
+    };
+
+    // Kept until the write is noted, so that a truncation drops the note
+    let _guard = lock::write(chunk).await;
+    let Ok(file) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(local_path)
+    else {
+        log::warn!("write_chunk:{}: file not found", line!());
+        return err_ret(TinyDfsError::FileNotFound);
+    };
+    let (file, verified) = rocket::tokio::task::spawn_blocking(move || {
+        let mut file = file;
+        let verified = checksum::verify_kept(chunk, &mut file, offset, length.0);
+        (file, verified)
+    })
+    .await
+    .unwrap();
+    if let Err(err) = verified {
+        log::warn!("write_chunk:{}: checksum err {:?}", line!(), err);
+        return err_ret(err);
+    }
+    let mut file = File::from_std(file);
+    if file.seek(SeekFrom::Start(offset)).await.is_err() {
+        log::warn!("write_chunk:{}: seek failed", line!());
+        return err_ret(TinyDfsError::IndexOutOfBound);
+    }
+    let old_bytes = file.metadata().await.map_or(0, |meta| meta.len());
+    let written = data.open(limit).stream_to(&mut file).await;
+    let mut file = file.into_std().await;
+    load::chunk_resized(
+        old_bytes,
+        file.metadata().map_or(old_bytes, |meta| meta.len()),
+    );
+    // Whatever made it to the disk needs checksums, even on failure
+    let bytes = match &written {
+        Ok(n) => n.written,
+        Err(_) => file
+            .metadata()
+            .map_or(0, |metadata| metadata.len().saturating_sub(offset)),
+    };
+    let updated = rocket::tokio::task::spawn_blocking(move || {
+        checksum::update(chunk, &mut file, offset, bytes)
+    })
+    .await
+    .unwrap();
+    match (written, updated) {
+        (Ok(n), Ok(_)) if n.complete => {
+            note_write(chunk);
+            (
+                Status::Ok,
+                StreamWriteResponse::OkResp(StreamWriteOkResponse { bytes }.into()),
+            )
+        }
+        (Ok(_), Ok(_)) => {
+            log::warn!("write_chunk:{}: body beyond {}", line!(), limit);
+            err_ret(TinyDfsError::IndexOutOfBound)
+        }
+        (Err(err), _) => {
+            log::warn!("write_chunk:{}: write err, kind {:?}", line!(), err.kind());
+            err_ret(TinyDfsError::IOInterrupted)
+        }
+        (_, Err(err)) => {
+            log::warn!("write_chunk:{}: checksum err {:?}", line!(), err);
+            err_ret(err)
+        }
+    }
+}
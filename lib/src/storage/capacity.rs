@@ -0,0 +1,39 @@
+//! Bytes each local chunk may hold, the chunk size of its file.
+//!
+//! A chunk is only as long as the bytes written into it, so the range of a
+//! file skipped by a write is missing from the disk. Reads of such a hole
+//! return zeros up to the capacity of the chunk, kept next to it in decimal.
+
+use std::{fs, io};
+
+use crate::common::storage::ChunkId;
+
+use super::path;
+
+/// Remember that the chunk `chunk` may hold `size` bytes. Nothing is kept
+/// for an unknown size.
+pub fn store(chunk: ChunkId, size: u64) -> io::Result<()> {
+    if size == 0 {
+        return Ok(());
+    }
+    fs::write(path::capacity_to_local(chunk), size.to_string())
+}
+
+/// Bytes the chunk `chunk` may hold, if known
+pub fn load(chunk: ChunkId) -> Option<u64> {
+    fs::read_to_string(path::capacity_to_local(chunk))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Bytes of the chunk `chunk` readable by the clients, zeros past the
+/// `size` bytes on the disk included
+pub fn readable(chunk: ChunkId, size: u64) -> u64 {
+    load(chunk).map_or(size, |capacity| capacity.max(size))
+}
+
+pub fn remove(chunk: ChunkId) {
+    let _ = fs::remove_file(path::capacity_to_local(chunk));
+}
@@ -0,0 +1,152 @@
+//! CRC32C checksums of the local chunks.
+//!
+//! A chunk is checked in blocks of `BLOCK_SIZE` bytes. The checksums of its
+//! blocks are kept in order next to it, 4 bytes each, so that a read only has
+//! to check the blocks it touches.
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use crate::common::{error::TinyDfsError, storage::ChunkId};
+
+use super::path;
+
+/// Bytes covered by a single checksum
+const BLOCK_SIZE: u64 = 64 << 10;
+
+/// Bytes of a single checksum
+const SUM_SIZE: u64 = 4;
+
+/// Checksums of the blocks `first..last` of `file`, stopping at its end
+fn block_sums(file: &mut fs::File, first: u64, last: u64) -> io::Result<Vec<u32>> {
+    file.seek(SeekFrom::Start(first * BLOCK_SIZE))?;
+    let mut sums = Vec::new();
+    let mut buf = Vec::with_capacity(BLOCK_SIZE as usize);
+    for _ in first..last {
+        buf.clear();
+        Read::by_ref(file).take(BLOCK_SIZE).read_to_end(&mut buf)?;
+        if buf.is_empty() {
+            break;
+        }
+        sums.push(crc32c::crc32c(&buf));
+    }
+    Ok(sums)
+}
+
+/// Refresh the checksums after `length` bytes have been written into the
+/// chunk `chunk` at `offset`
+pub fn update(
+    chunk: ChunkId,
+    file: &mut fs::File,
+    offset: u64,
+    length: u64,
+) -> Result<(), TinyDfsError> {
+    let mut sum_file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path::checksum_to_local(chunk))
+        .or(Err(TinyDfsError::FileNotFound))?;
+    let stored = sum_file
+        .metadata()
+        .or(Err(TinyDfsError::FileNotFound))?
+        .len()
+        / SUM_SIZE;
+    // Writing past the end fills the gap with zeros, which needs checksums as
+    // well, starting with the last block stored
+    let first = (offset / BLOCK_SIZE).min(stored.saturating_sub(1));
+    let last = (offset + length).div_ceil(BLOCK_SIZE);
+    let sums = block_sums(file, first, last).or(Err(TinyDfsError::IOInterrupted))?;
+    let bytes: Vec<u8> = sums.iter().flat_map(|sum| sum.to_le_bytes()).collect();
+    sum_file
+        .seek(SeekFrom::Start(first * SUM_SIZE))
+        .and_then(|_| sum_file.write_all(&bytes))
+        .or(Err(TinyDfsError::IOInterrupted))
+}
+
+/// Check the blocks of the chunk `chunk` which a write of `length` bytes at
+/// `offset` only partly covers, before their checksums are computed again
+/// over the bytes left. Without a length, every block from `offset` on is
+/// checked.
+pub fn verify_kept(
+    chunk: ChunkId,
+    file: &mut fs::File,
+    offset: u64,
+    length: Option<u64>,
+) -> Result<(), TinyDfsError> {
+    let size = file.metadata().or(Err(TinyDfsError::FileNotFound))?.len();
+    // Past the end, the last block gets the zeros in between
+    let start = offset.min(size);
+    let mut blocks = Vec::new();
+    if !start.is_multiple_of(BLOCK_SIZE) {
+        blocks.push(start / BLOCK_SIZE);
+    }
+    match length {
+        Some(length) => {
+            let end = offset + length;
+            if end < size && !end.is_multiple_of(BLOCK_SIZE) {
+                blocks.push(end / BLOCK_SIZE);
+            }
+        }
+        None => blocks.extend(start / BLOCK_SIZE..size.div_ceil(BLOCK_SIZE)),
+    }
+    blocks.dedup();
+    for block in blocks {
+        let at = block * BLOCK_SIZE;
+        verify(chunk, file, at, BLOCK_SIZE.min(size - at))?;
+    }
+    Ok(())
+}
+
+/// Refresh the checksums after the chunk `chunk` has been cut or extended
+/// from `old_length` bytes
+pub fn resize(chunk: ChunkId, file: &mut fs::File, old_length: u64) -> Result<(), TinyDfsError> {
+    let length = file.metadata().or(Err(TinyDfsError::FileNotFound))?.len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(path::checksum_to_local(chunk))
+        .and_then(|sum_file| sum_file.set_len(length.div_ceil(BLOCK_SIZE) * SUM_SIZE))
+        .or(Err(TinyDfsError::IOInterrupted))?;
+    // Only the block the new end falls in, or the ones past the old end
+    let offset = old_length.min(length);
+    update(chunk, file, offset, length - offset)
+}
+
+/// Compute all checksums of the chunk `chunk` from scratch
+pub fn rebuild(chunk: ChunkId, file: &mut fs::File) -> Result<(), TinyDfsError> {
+    let length = file.metadata().or(Err(TinyDfsError::FileNotFound))?.len();
+    fs::File::create(path::checksum_to_local(chunk)).or(Err(TinyDfsError::PathInvalid))?;
+    update(chunk, file, 0, length)
+}
+
+/// Check the blocks of the chunk `chunk` holding the `length` bytes at `offset`
+pub fn verify(
+    chunk: ChunkId,
+    file: &mut fs::File,
+    offset: u64,
+    length: u64,
+) -> Result<(), TinyDfsError> {
+    if length == 0 {
+        return Ok(());
+    }
+    let first = offset / BLOCK_SIZE;
+    let last = (offset + length).div_ceil(BLOCK_SIZE);
+    let mut sum_file =
+        fs::File::open(path::checksum_to_local(chunk)).or(Err(TinyDfsError::ChecksumMismatch))?;
+    let mut bytes = vec![0; ((last - first) * SUM_SIZE) as usize];
+    sum_file
+        .seek(SeekFrom::Start(first * SUM_SIZE))
+        .and_then(|_| sum_file.read_exact(&mut bytes))
+        .or(Err(TinyDfsError::ChecksumMismatch))?;
+    let stored = bytes
+        .chunks(SUM_SIZE as usize)
+        .map(|sum| u32::from_le_bytes(sum.try_into().unwrap()));
+    let sums = block_sums(file, first, last).or(Err(TinyDfsError::IOInterrupted))?;
+    if sums.len() as u64 != last - first || !stored.eq(sums) {
+        log::warn!("verify: chunk {} is corrupted", chunk);
+        return Err(TinyDfsError::ChecksumMismatch);
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! Load of this storage server, reported in the heartbeats
+
+use std::{
+    fs, io,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Data, Request, Response,
+};
+
+use crate::common::storage::ChunkId;
+
+use super::path;
+
+/// Client requests being served
+static OUTSTANDING_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+pub fn outstanding_requests() -> u64 {
+    OUTSTANDING_REQUESTS.load(Ordering::Relaxed)
+}
+
+/// Count the requests from their arrival to their response
+pub struct RequestCounter;
+
+#[rocket::async_trait]
+impl Fairing for RequestCounter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Outstanding request counter",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, _req: &mut Request<'_>, _data: &mut Data<'_>) {
+        OUTSTANDING_REQUESTS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    async fn on_response<'r>(&self, _req: &'r Request<'_>, _res: &mut Response<'r>) {
+        OUTSTANDING_REQUESTS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Bytes of the chunks kept here, counted once at startup and then kept up to
+/// date by whatever changes a chunk, so that a heartbeat doesn't walk the disk
+static USED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+pub fn used_bytes() -> u64 {
+    USED_BYTES.load(Ordering::Relaxed)
+}
+
+/// Count the bytes of the chunks kept right under `dir`
+pub fn count_used_bytes(dir: &Path) -> io::Result<()> {
+    let mut bytes = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_file() && path::local_to_chunk(&entry.path()).is_some() {
+            bytes += meta.len();
+        }
+    }
+    USED_BYTES.store(bytes, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Bytes of the chunk `chunk`, 0 if it isn't kept here
+pub fn chunk_bytes(chunk: ChunkId) -> u64 {
+    fs::metadata(path::chunk_to_local(chunk)).map_or(0, |meta| meta.len())
+}
+
+/// Account for a chunk going from `old` to `new` bytes
+pub fn chunk_resized(old: u64, new: u64) {
+    if new >= old {
+        USED_BYTES.fetch_add(new - old, Ordering::Relaxed);
+    } else {
+        let _ = USED_BYTES.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            Some(used.saturating_sub(old - new))
+        });
+    }
+}
+
+/// Total and available bytes of the disk holding `dir`
+pub fn disk_space(dir: &Path) -> io::Result<(u64, u64)> {
+    Ok((fs2::total_space(dir)?, fs2::available_space(dir)?))
+}
@@ -0,0 +1,45 @@
+//! Per-chunk locks of the storage server.
+//!
+//! The data of a chunk and its checksums are two files, so a write updates
+//! them under the write lock of the chunk. Reads check the data against the
+//! checksums under the read lock, never in between.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+};
+
+use once_cell::sync::Lazy;
+use rocket::tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+use crate::common::storage::ChunkId;
+
+/// Locks in use, dropped with their last guard
+static LOCKS: Lazy<Mutex<HashMap<ChunkId, Weak<RwLock<()>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Locks kept in the table before the unused ones are swept out
+const SWEEP_THRESHOLD: usize = 1024;
+
+fn chunk_lock(chunk: ChunkId) -> Arc<RwLock<()>> {
+    let mut locks = LOCKS.lock().unwrap();
+    if let Some(lock) = locks.get(&chunk).and_then(Weak::upgrade) {
+        return lock;
+    }
+    if locks.len() >= SWEEP_THRESHOLD {
+        locks.retain(|_, lock| lock.strong_count() > 0);
+    }
+    let lock = Arc::new(RwLock::new(()));
+    locks.insert(chunk, Arc::downgrade(&lock));
+    lock
+}
+
+/// Hold off the writes of the chunk `chunk`
+pub async fn read(chunk: ChunkId) -> OwnedRwLockReadGuard<()> {
+    chunk_lock(chunk).read_owned().await
+}
+
+/// Hold off every other access to the chunk `chunk`
+pub async fn write(chunk: ChunkId) -> OwnedRwLockWriteGuard<()> {
+    chunk_lock(chunk).write_owned().await
+}
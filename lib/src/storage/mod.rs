@@ -1,65 +1,109 @@
 //! Code of storage server
 
 mod api;
+mod capacity;
+mod checksum;
+mod load;
+mod lock;
 mod path;
+mod scrub;
 
 use std::{
+    collections::{HashMap, HashSet},
     fs, io,
     path::Path,
-    sync::atomic::{AtomicU16, Ordering},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Mutex,
+    },
+    time::Duration,
 };
 
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
+use rocket::{
+    http::Status,
+    serde::{de::DeserializeOwned, Serialize},
+};
 
 use crate::common::{
+    addr::{Host, Ip},
     error::TinyDfsError,
-    registration::{RegisterArg, RegisterOkResponse},
+    registration::{ChunkWritten, HeartbeatArg, RegisterArg, RegisterOkResponse},
+    storage::ChunkId,
+    unix_millis,
+    wire::Wire,
+    ErrResponse,
 };
+use crate::config::StorageConfig;
 use api::{
-    command::{create_file, delete_file},
-    storage::{get_size, read_file, write_file},
+    command::{copy_chunk, create_chunk, delete_chunk, scrub_chunks, truncate_chunk},
+    storage::{append_file, get_size, read_file, truncate_file, write_file},
+    stream::{read_chunk, write_chunk},
 };
 
 static CLIENT_PORT: Lazy<AtomicU16> = Lazy::new(|| AtomicU16::new(0));
 static COMMAND_PORT: Lazy<AtomicU16> = Lazy::new(|| AtomicU16::new(0));
 
-const SERVER_IP: &str = "localhost";
-const NAMING_SERVER_IP: &str = "localhost";
+static ADVERTISED_HOST: OnceCell<String> = OnceCell::new();
+static NAMING_ADDR: OnceCell<String> = OnceCell::new();
+
+/// Chunks written since the last heartbeat, which reports them
+static WRITTEN: Lazy<Mutex<HashSet<ChunkId>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Chunk => truncations the naming server has told us of, missing if none
+static EPOCHS: Lazy<Mutex<HashMap<ChunkId, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Host the others reach this server at
+fn advertised_host() -> &'static str {
+    ADVERTISED_HOST
+        .get()
+        .map(String::as_str)
+        .unwrap_or_default()
+}
+
+/// host:port of the registration api of the naming server
+fn naming_addr() -> &'static str {
+    NAMING_ADDR.get().map(String::as_str).unwrap_or_default()
+}
 
-fn traverse_dir(dir: &Path, files: &mut Vec<String>) -> io::Result<()> {
+/// Collect the chunks kept in `dir`
+fn traverse_dir(dir: &Path, chunks: &mut Vec<ChunkId>) -> io::Result<()> {
     for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            traverse_dir(&path, files)?;
-        } else {
-            let global_path = path::local_to_global(path.to_str().unwrap());
-            log::debug!("{}: send path {:?}", line!(), path.as_os_str());
-            log::debug!("{}: send global path {:?}", line!(), global_path);
-            files.push(global_path.to_string());
+        let path = entry?.path();
+        match path::local_to_chunk(&path) {
+            Some(chunk) if path.is_file() => chunks.push(chunk),
+            _ if path::is_checksum(&path) || path::is_capacity(&path) => {}
+            _ if path::is_copying(&path) => {
+                // Left behind by a copy cut short by a crash
+                log::info!("traverse_dir: remove unfinished copy {:?}", path);
+                if let Err(err) = fs::remove_file(&path) {
+                    log::warn!("traverse_dir: remove {:?} failed, err {:?}", path, err);
+                }
+            }
+            _ if path == Path::new(&path::quarantine_dir()) => {}
+            _ => log::warn!("traverse_dir: {:?} is not a chunk", path),
         }
     }
     Ok(())
 }
 
-async fn regsiter_myself(registration_port: u16) -> Result<(), TinyDfsError> {
-    // Collect all local files
-    let mut files: Vec<String> = Vec::new();
-
-    let local_dir = path::global_to_local("/");
-    let local_dir = Path::new(&local_dir);
+async fn regsiter_myself() -> Result<(), TinyDfsError> {
+    // Collect all local chunks
+    let mut chunks: Vec<ChunkId> = Vec::new();
 
-    traverse_dir(local_dir, &mut files).or(Err(TinyDfsError::DirReadErr))?;
+    let local_dir = Path::new(path::local_dir());
+    fs::create_dir_all(local_dir).or(Err(TinyDfsError::DirReadErr))?;
+    traverse_dir(local_dir, &mut chunks).or(Err(TinyDfsError::DirReadErr))?;
 
     // Send registration request
     let arg = RegisterArg {
-        storage_ip: SERVER_IP.to_string(),
+        storage_ip: advertised_host().to_string(),
         client_port: CLIENT_PORT.load(Ordering::Relaxed),
         command_port: COMMAND_PORT.load(Ordering::Relaxed),
-        files,
+        chunks,
     };
     let client = reqwest::Client::new();
-    let addr = format!("http://{}:{}/register", NAMING_SERVER_IP, registration_port);
+    let addr = format!("http://{}/register", naming_addr());
     log::debug!("addr {:?}", addr);
     let resp = client
         .post(addr)
@@ -72,56 +116,229 @@ async fn regsiter_myself(registration_port: u16) -> Result<(), TinyDfsError> {
         log::warn!("{}: status {:?}", line!(), resp.status());
         return Err(TinyDfsError::RegisterFailed);
     }
-    let resp: RegisterOkResponse = resp.json().await.unwrap();
-
-    // Remove duplicated files
-    let duplicated_files = resp.files;
-    for file in duplicated_files {
-        log::info!("{}: remove relative path {}", line!(), file);
-        // let path = local_dir.to_string() + &file;
-        let path = path::global_to_local(&file);
-        log::info!("{}: remove path {}", line!(), path);
-        fs::remove_file(file).unwrap();
+    let resp: RegisterOkResponse = resp.json().await.or(Err(TinyDfsError::RegisterFailed))?;
+
+    // Remove the chunks the naming server doesn't know us to own
+    for chunk in resp.chunks {
+        let path = path::chunk_to_local(chunk);
+        log::info!("{}: remove stale chunk {}", line!(), path);
+        let bytes = load::chunk_bytes(chunk);
+        if let Err(err) = fs::remove_file(&path) {
+            // Left for the next registration to try again
+            log::warn!("{}: remove {} failed, err {:?}", line!(), path, err);
+            continue;
+        }
+        load::chunk_resized(bytes, 0);
+        let _ = fs::remove_file(path::checksum_to_local(chunk));
+        capacity::remove(chunk);
+        forget_chunk(chunk);
+    }
+    for (chunk, epoch) in resp.epochs {
+        set_epoch(chunk, epoch);
     }
     Ok(())
 }
 
-/// args: Command line args;
-/// args[2]: client port;
-/// args[3]: command port;
-/// args[4]: regsitration port (in naming server);
-/// args[5]: local dir
-pub async fn start_storage_server(args: &Vec<String>) {
-    log::info!("start a new storage server...");
+/// Have the size and the time of `chunk` reported by the next heartbeat
+fn note_write(chunk: ChunkId) {
+    WRITTEN.lock().unwrap().insert(chunk);
+}
+
+/// Drop the writes to `chunk` not reported yet, as it has been truncated
+fn forget_write(chunk: ChunkId) {
+    WRITTEN.lock().unwrap().remove(&chunk);
+}
+
+/// Truncation epoch of `chunk`
+fn epoch(chunk: ChunkId) -> u64 {
+    EPOCHS.lock().unwrap().get(&chunk).copied().unwrap_or(0)
+}
+
+/// Move `chunk` on to `epoch`, never back
+fn set_epoch(chunk: ChunkId, epoch: u64) {
+    if epoch > 0 {
+        let mut epochs = EPOCHS.lock().unwrap();
+        let known = epochs.entry(chunk).or_default();
+        *known = (*known).max(epoch);
+    }
+}
 
-    let client_port = args[2].parse::<u16>().unwrap();
-    let command_port = args[3].parse::<u16>().unwrap();
-    let registration_port = args[4].parse::<u16>().unwrap();
-    let local_dir = args[5].clone();
+/// Forget what is kept in memory about the deleted `chunk`
+fn forget_chunk(chunk: ChunkId) {
+    forget_write(chunk);
+    EPOCHS.lock().unwrap().remove(&chunk);
+}
 
-    CLIENT_PORT.store(client_port, Ordering::Relaxed);
-    COMMAND_PORT.store(command_port, Ordering::Relaxed);
-    path::set_local_dir(local_dir);
-    // *path::local_dir().write().await = local_dir;
+/// Sizes and times of the chunks written since the last heartbeat. Each is
+/// read along with the epoch under the lock of the chunk, so that a size from
+/// before a truncation goes with the epoch before it.
+async fn take_written() -> Vec<ChunkWritten> {
+    let chunks: Vec<ChunkId> = WRITTEN.lock().unwrap().drain().collect();
+    let mut written = Vec::with_capacity(chunks.len());
+    for chunk in chunks {
+        let _guard = lock::read(chunk).await;
+        match rocket::tokio::fs::metadata(path::chunk_to_local(chunk)).await {
+            Ok(metadata) => written.push(ChunkWritten {
+                chunk,
+                size: metadata.len(),
+                modified: metadata.modified().map_or(0, unix_millis),
+                epoch: epoch(chunk),
+            }),
+            // Deleted in the meantime
+            Err(err) => log::debug!("take_written: chunk {}, err {:?}", chunk, err),
+        }
+    }
+    written
+}
 
-    if let Some(err) = regsiter_myself(registration_port).await.err() {
+fn err_resp(err: TinyDfsError) -> (Status, Wire<ErrResponse>) {
+    let (status, etype, einfo) = err.exception();
+    (
+        status,
+        ErrResponse {
+            exception_type: etype.to_string(),
+            exception_info: einfo.to_string(),
+        }
+        .into(),
+    )
+}
+
+/// Post `arg` to `route` of the registration api of the naming server on
+/// behalf of a client, passing the errors of the naming server on as they are
+async fn ask_naming<A: Serialize, R: DeserializeOwned>(
+    route: &str,
+    arg: &A,
+) -> Result<R, (Status, Wire<ErrResponse>)> {
+    let addr = format!("http://{}/{}", naming_addr(), route);
+    let resp = reqwest::Client::new()
+        .post(addr)
+        .json(arg)
+        .send()
+        .await
+        .map_err(|err| {
+            log::warn!("ask_naming: {}, err {:?}", route, err);
+            err_resp(TinyDfsError::IOInterrupted)
+        })?;
+    let status = Status::from_code(resp.status().as_u16()).unwrap_or(Status::InternalServerError);
+    if status.class().is_success() {
+        resp.json()
+            .await
+            .map_err(|_| err_resp(TinyDfsError::IOInterrupted))
+    } else {
+        match resp.json::<ErrResponse>().await {
+            Ok(err) => Err((status, err.into())),
+            Err(_) => Err(err_resp(TinyDfsError::IOInterrupted)),
+        }
+    }
+}
+
+/// Interval between two heartbeats sent to the naming server
+const HEARTBEAT_PERIOD: Duration = Duration::from_secs(1);
+
+async fn send_heartbeats() {
+    let client = reqwest::Client::new();
+    let addr = format!("http://{}/heartbeat", naming_addr());
+    let mut interval = rocket::tokio::time::interval(HEARTBEAT_PERIOD);
+    loop {
+        interval.tick().await;
+        let local_dir = path::local_dir();
+        let (capacity_bytes, available_bytes) = rocket::tokio::task::spawn_blocking(move || {
+            load::disk_space(Path::new(local_dir)).unwrap_or_else(|err| {
+                log::warn!("send_heartbeats: disk space, err {:?}", err);
+                (0, 0)
+            })
+        })
+        .await
+        .unwrap();
+        let arg = HeartbeatArg {
+            storage_ip: advertised_host().to_string(),
+            client_port: CLIENT_PORT.load(Ordering::Relaxed),
+            command_port: COMMAND_PORT.load(Ordering::Relaxed),
+            used_bytes: load::used_bytes(),
+            capacity_bytes,
+            available_bytes,
+            outstanding_requests: load::outstanding_requests(),
+            written: take_written().await,
+        };
+        let sent = client.post(&addr).json(&arg).send().await;
+        if !sent.as_ref().is_ok_and(|resp| resp.status().is_success()) {
+            // Reported again by the next heartbeat
+            for write in &arg.written {
+                note_write(write.chunk);
+            }
+        }
+        match sent {
+            Ok(resp) if resp.status() == reqwest::StatusCode::NOT_FOUND => {
+                // The naming server has forgotten us
+                log::warn!("send_heartbeats: not registered, register again");
+                if let Some(err) = regsiter_myself().await.err() {
+                    log::warn!("send_heartbeats: register failed, err {:?}", err);
+                }
+            }
+            Ok(resp) if !resp.status().is_success() => {
+                log::warn!("send_heartbeats: status {:?}", resp.status())
+            }
+            Ok(_) => {}
+            Err(err) => log::warn!("send_heartbeats: err {:?}", err),
+        }
+    }
+}
+
+pub async fn start_storage_server(config: &StorageConfig) {
+    log::info!("start a new storage server...");
+
+    CLIENT_PORT.store(config.client_port, Ordering::Relaxed);
+    COMMAND_PORT.store(config.command_port, Ordering::Relaxed);
+    // Validated by the config already, only put in the canonical form
+    let advertised_host = Host::parse(&config.advertised_host).expect("advertised host is valid");
+    let naming_addr = Ip::parse(&config.naming_address).expect("naming address is valid");
+    ADVERTISED_HOST
+        .set(advertised_host.to_string())
+        .expect("advertised host has been set");
+    NAMING_ADDR
+        .set(naming_addr.to_string())
+        .expect("naming address has been set");
+    let local_dir = config.data_dir.to_string_lossy();
+    path::set_local_dir(local_dir.trim_end_matches('/').to_string());
+    scrub::set_scrub_period(config.scrub_period);
+
+    if let Some(err) = regsiter_myself().await.err() {
         log::error!("register failed, err {:?}", err);
         panic!();
     }
+    if let Err(err) = load::count_used_bytes(Path::new(path::local_dir())) {
+        log::warn!("count used bytes failed, err {:?}", err);
+    }
+    rocket::tokio::spawn(send_heartbeats());
+    scrub::start_scrub_task();
 
     let client_config = rocket::Config {
-        port: client_port,
+        address: config.bind_address,
+        port: config.client_port,
         ..rocket::Config::debug_default()
     };
     let command_config = rocket::Config {
-        port: command_port,
+        address: config.bind_address,
+        port: config.command_port,
         ..rocket::Config::debug_default()
     };
 
     let client_task = rocket::tokio::spawn(async move {
         rocket::build()
             .configure(client_config)
-            .mount("/", routes![get_size, read_file, write_file])
+            .attach(load::RequestCounter)
+            .mount(
+                "/",
+                routes![
+                    get_size,
+                    read_file,
+                    write_file,
+                    read_chunk,
+                    write_chunk,
+                    truncate_file,
+                    append_file
+                ],
+            )
             .launch()
             .await
             .unwrap();
@@ -130,7 +347,16 @@ pub async fn start_storage_server(args: &Vec<String>) {
     let command_task = rocket::tokio::spawn(async move {
         rocket::build()
             .configure(command_config)
-            .mount("/", routes![delete_file, create_file])
+            .mount(
+                "/",
+                routes![
+                    delete_chunk,
+                    create_chunk,
+                    copy_chunk,
+                    truncate_chunk,
+                    scrub_chunks
+                ],
+            )
             .launch()
             .await
             .unwrap();
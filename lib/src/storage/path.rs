@@ -1,25 +1,77 @@
-use once_cell::sync::Lazy;
+use std::path::Path;
+
+use once_cell::sync::OnceCell;
+
+use crate::common::storage::ChunkId;
 
 /// Note that local dir must NOT have '/' at the end
-static mut LOCAL_DIR: Lazy<String> = Lazy::new(|| String::new());
+static LOCAL_DIR: OnceCell<String> = OnceCell::new();
 
-// fn local_dir() -> &'static str {
-//     unsafe { &LOCAL_DIR }
-// }
+pub fn local_dir() -> &'static str {
+    LOCAL_DIR.get().map(String::as_str).unwrap_or_default()
+}
 
 pub fn set_local_dir(dir: String) {
-    unsafe { *LOCAL_DIR = dir }
+    LOCAL_DIR.set(dir).expect("local dir has been set");
+}
+
+/// Chunks are kept right under the local dir, named after their ids
+pub fn chunk_to_local(chunk: ChunkId) -> String {
+    format!("{}/{}", local_dir(), chunk)
+}
+
+/// Checksums of a chunk are kept next to it
+pub fn checksum_to_local(chunk: ChunkId) -> String {
+    format!("{}/{}.{}", local_dir(), chunk, CHECKSUM_EXTENSION)
+}
+
+/// Bytes a chunk may hold are kept next to it
+pub fn capacity_to_local(chunk: ChunkId) -> String {
+    format!("{}/{}.{}", local_dir(), chunk, CAPACITY_EXTENSION)
 }
 
-pub fn global_to_local(global_path: &str) -> String {
-    unsafe { LOCAL_DIR.to_owned() + global_path }
-    // format!("{}{}", unsafe { LOCAL_DIR }, global_path)
+/// Corrupted chunks are moved here by the scrubber
+pub fn quarantine_dir() -> String {
+    format!("{}/quarantine", local_dir())
 }
 
-pub fn local_to_global<'a>(local_path: &'a str) -> &'a str {
-    &local_path[unsafe { LOCAL_DIR.len() }..]
+const CHECKSUM_EXTENSION: &str = "crc";
+
+pub fn is_checksum(local_path: &Path) -> bool {
+    local_path
+        .extension()
+        .is_some_and(|ext| ext == CHECKSUM_EXTENSION)
+}
+
+const CAPACITY_EXTENSION: &str = "cap";
+
+pub fn is_capacity(local_path: &Path) -> bool {
+    local_path
+        .extension()
+        .is_some_and(|ext| ext == CAPACITY_EXTENSION)
+}
+
+/// A copy of a chunk being pulled is written next to it under a name of its
+/// own, so that concurrent copies don't share a temp file
+pub fn copying_to_local(chunk: ChunkId) -> String {
+    format!(
+        "{}/{}.{:016x}.{}",
+        local_dir(),
+        chunk,
+        rand::random::<u64>(),
+        COPYING_EXTENSION
+    )
+}
+
+const COPYING_EXTENSION: &str = "copying";
+
+pub fn is_copying(local_path: &Path) -> bool {
+    local_path
+        .extension()
+        .is_some_and(|ext| ext == COPYING_EXTENSION)
 }
 
-pub fn path_is_invalid(path: &str) -> bool {
-    path.is_empty() || path.chars().nth(0).unwrap() != '/'
+/// The chunk kept at `local_path`, if any
+pub fn local_to_chunk(local_path: &Path) -> Option<ChunkId> {
+    local_path.file_name()?.to_str()?.parse().ok()
 }
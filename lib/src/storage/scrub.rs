@@ -0,0 +1,198 @@
+//! Background scrubber of the local chunks.
+//!
+//! Chunks that are rarely read could rot unnoticed, so all of them are
+//! verified against their checksums from time to time. A corrupted chunk is
+//! reported to the naming server, which drops the replica and copies a
+//! healthy one elsewhere. Dropped replicas are moved into the quarantine dir,
+//! and pruned from there after a while. The last replica of a chunk is kept
+//! in place until a healthy copy has been made from whatever is left of it.
+
+use std::{
+    fs,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use once_cell::sync::Lazy;
+use rocket::tokio::sync::Mutex;
+
+use crate::common::{
+    error::TinyDfsError,
+    registration::{ReportCorruptArg, ReportCorruptOkResponse},
+    storage::ChunkId,
+};
+
+use super::{
+    advertised_host, capacity, checksum, load, lock, naming_addr, path, CLIENT_PORT, COMMAND_PORT,
+};
+
+/// Seconds between two passes over the local chunks
+static SCRUB_PERIOD: AtomicU64 = AtomicU64::new(24 * 3600);
+
+pub fn set_scrub_period(secs: u64) {
+    SCRUB_PERIOD.store(secs.max(1), Ordering::Relaxed);
+}
+
+/// Bytes verified per second at most, leaving the disk to the clients
+const SCRUB_RATE: u64 = 32 << 20;
+
+/// Quarantined chunks are kept this long for a post-mortem
+const QUARANTINE_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// A single pass at a time, periodic or on request
+static SCRUBBING: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Verify the whole chunk `chunk`. Return its size.
+fn verify_chunk(chunk: ChunkId) -> Result<u64, TinyDfsError> {
+    let mut file =
+        fs::File::open(path::chunk_to_local(chunk)).or(Err(TinyDfsError::FileNotFound))?;
+    let size = file.metadata().or(Err(TinyDfsError::FileNotFound))?.len();
+    checksum::verify(chunk, &mut file, 0, size)?;
+    Ok(size)
+}
+
+/// Move the chunk `chunk` and its checksums out of the way
+fn quarantine(chunk: ChunkId) -> std::io::Result<()> {
+    let dir = path::quarantine_dir();
+    fs::create_dir_all(&dir)?;
+    let bytes = load::chunk_bytes(chunk);
+    for local_path in [path::chunk_to_local(chunk), path::checksum_to_local(chunk)] {
+        let local_path = Path::new(&local_path);
+        if let Some(name) = local_path.file_name() {
+            let quarantined = Path::new(&dir).join(name);
+            fs::rename(local_path, &quarantined)?;
+            // Aged from now on, not from the last write
+            fs::File::options()
+                .write(true)
+                .open(&quarantined)?
+                .set_modified(SystemTime::now())?;
+        }
+    }
+    load::chunk_resized(bytes, 0);
+    capacity::remove(chunk);
+    Ok(())
+}
+
+/// Remove the chunks quarantined for longer than `QUARANTINE_TTL`
+fn prune_quarantine() {
+    let Ok(entries) = fs::read_dir(path::quarantine_dir()) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > QUARANTINE_TTL);
+        if expired {
+            log::info!("prune_quarantine: remove {:?}", entry.path());
+            if let Err(err) = fs::remove_file(entry.path()) {
+                log::warn!("prune_quarantine: remove {:?}, err {:?}", entry.path(), err);
+            }
+        }
+    }
+}
+
+/// Verify the chunk `chunk` again, with its write lock held by the caller.
+/// Return whether it is still corrupted, as a write or a copy may have
+/// replaced it since.
+async fn still_corrupted(chunk: ChunkId) -> bool {
+    let verified = rocket::tokio::task::spawn_blocking(move || verify_chunk(chunk))
+        .await
+        .unwrap();
+    verified == Err(TinyDfsError::ChecksumMismatch)
+}
+
+async fn verify_in_background(chunk: ChunkId) -> Result<u64, TinyDfsError> {
+    let _guard = lock::read(chunk).await;
+    rocket::tokio::task::spawn_blocking(move || verify_chunk(chunk))
+        .await
+        .unwrap()
+}
+
+/// Tell the naming server to drop our replicas of `chunks`. Return the ones
+/// to keep.
+async fn report(chunks: Vec<ChunkId>) -> Result<Vec<ChunkId>, TinyDfsError> {
+    let arg = ReportCorruptArg {
+        storage_ip: advertised_host().to_string(),
+        client_port: CLIENT_PORT.load(Ordering::Relaxed),
+        command_port: COMMAND_PORT.load(Ordering::Relaxed),
+        chunks,
+    };
+    let addr = format!("http://{}/report_corrupt", naming_addr());
+    let resp = reqwest::Client::new()
+        .post(addr)
+        .json(&arg)
+        .send()
+        .await
+        .or(Err(TinyDfsError::IOInterrupted))?;
+    if !resp.status().is_success() {
+        log::warn!("report:{}: status {:?}", line!(), resp.status());
+        return Err(TinyDfsError::ServerNotRegistered);
+    }
+    let resp: ReportCorruptOkResponse = resp.json().await.or(Err(TinyDfsError::IOInterrupted))?;
+    Ok(resp.kept)
+}
+
+/// Verify all local chunks once. Return the corrupted ones.
+pub async fn scrub() -> Vec<ChunkId> {
+    let _guard = SCRUBBING.lock().await;
+    prune_quarantine();
+    let mut chunks = Vec::new();
+    if let Err(err) = super::traverse_dir(Path::new(path::local_dir()), &mut chunks) {
+        log::warn!("scrub: traverse local dir, err {:?}", err);
+        return Vec::new();
+    }
+    let mut corrupted = Vec::new();
+    for chunk in chunks {
+        let size = match verify_in_background(chunk).await {
+            Ok(size) => size,
+            Err(TinyDfsError::ChecksumMismatch) => {
+                let _guard = lock::write(chunk).await;
+                if still_corrupted(chunk).await {
+                    log::warn!("scrub: chunk {} is corrupted", chunk);
+                    corrupted.push(chunk);
+                }
+                continue;
+            }
+            // Deleted in the meantime
+            Err(_) => continue,
+        };
+        rocket::tokio::time::sleep(Duration::from_secs_f64(size as f64 / SCRUB_RATE as f64)).await;
+    }
+    if corrupted.is_empty() {
+        return corrupted;
+    }
+    let kept = match report(corrupted.clone()).await {
+        Ok(kept) => kept,
+        Err(err) => {
+            // Kept in place and reported again by the next pass
+            log::warn!("scrub: report failed, err {:?}", err);
+            return corrupted;
+        }
+    };
+    for &chunk in corrupted.iter().filter(|chunk| !kept.contains(chunk)) {
+        let _guard = lock::write(chunk).await;
+        if !still_corrupted(chunk).await {
+            continue;
+        }
+        log::warn!("scrub: quarantine chunk {}", chunk);
+        if let Err(err) = quarantine(chunk) {
+            log::warn!("scrub: quarantine chunk {}, err {:?}", chunk, err);
+        }
+    }
+    corrupted
+}
+
+pub fn start_scrub_task() {
+    rocket::tokio::spawn(async {
+        loop {
+            let period = SCRUB_PERIOD.load(Ordering::Relaxed);
+            rocket::tokio::time::sleep(Duration::from_secs(period)).await;
+            log::info!("start to scrub local chunks...");
+            scrub().await;
+        }
+    });
+}
@@ -0,0 +1,56 @@
+use tiny_dfs::common::addr::{Host, Ip};
+
+#[test]
+fn test_addr() {
+    for (host, canonical, with_port) in [
+        ("10.0.0.1", "10.0.0.1", "10.0.0.1:1"),
+        ("::1", "::1", "[::1]:1"),
+        ("[0:0:0:0:0:0:0:1]", "::1", "[::1]:1"),
+        (
+            "Storage-1.Example.com",
+            "storage-1.example.com",
+            "storage-1.example.com:1",
+        ),
+        ("localhost", "localhost", "localhost:1"),
+    ] {
+        let host = Host::parse(host).unwrap();
+        assert_eq!(host.as_str(), canonical);
+        assert_eq!(host.with_port(1), with_port);
+    }
+    for bad in ["", "-a", "a_b", "a..b", "10.0.0.256", "[10.0.0.1]", "::1]"] {
+        assert!(Host::parse(bad).is_err(), "{:?}", bad);
+    }
+
+    log::info!("start to parse host:port...");
+    let ip = Ip::parse("[::1]:22222").unwrap();
+    assert_eq!((ip.host().as_str(), ip.port()), ("::1", 22222));
+    assert_eq!(ip.to_string(), "[::1]:22222");
+    let ip = Ip::parse("LocalHost:22222").unwrap();
+    assert_eq!((ip.host().as_str(), ip.port()), ("localhost", 22222));
+    assert_eq!(
+        ip,
+        Ip::new(Host::parse("localhost").unwrap(), 22222).unwrap()
+    );
+    for bad in [
+        "localhost",
+        "::1:22222",
+        "localhost:abc",
+        ":22222",
+        "localhost:0",
+        "localhost:65536",
+    ] {
+        assert!(Ip::parse(bad).is_err(), "{:?}", bad);
+    }
+    assert!(Ip::new(Host::parse("localhost").unwrap(), 0).is_err());
+
+    // Same wire format as a plain string
+    let host: Host = rocket::serde::json::from_str("\"::1\"").unwrap();
+    assert_eq!(rocket::serde::json::to_string(&host).unwrap(), "\"::1\"");
+    assert!(rocket::serde::json::from_str::<Host>("\"a_b\"").is_err());
+    let ip: Ip = rocket::serde::json::from_str("\"[::1]:22222\"").unwrap();
+    assert_eq!(
+        rocket::serde::json::to_string(&ip).unwrap(),
+        "\"[::1]:22222\""
+    );
+    assert!(rocket::serde::json::from_str::<Ip>("\"::1\"").is_err());
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use tiny_dfs::client::{AdminClient, DfsClient, DfsError};
+use tokio::{io::AsyncWriteExt, time::sleep};
+
+mod common;
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_admin() {
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let admin = AdminClient::new("localhost:22222");
+    let client = DfsClient::new("localhost:11111");
+
+    log::warn!("test_admin: start...");
+    // Wait for a heartbeat with the load
+    sleep(Duration::from_millis(1500)).await;
+    let servers = admin.servers().await.unwrap();
+    assert_eq!(servers.len(), 1);
+    let srv = &servers[0];
+    assert_eq!((srv.client_port, srv.command_port), (33333, 44444));
+    assert!(srv.alive && !srv.decommissioned);
+    assert_eq!(srv.files, new_files.len() as u64);
+    assert!(srv.chunks >= srv.files);
+    assert!(srv.capacity_bytes > 0 && srv.available_bytes <= srv.capacity_bytes);
+
+    log::info!("start to show the replicas...");
+    let file = admin.file("/test111").await.unwrap();
+    assert_eq!(file.chunk_size, common::CHUNK_SIZE);
+    assert_eq!(file.replication, 1);
+    assert!(!file.chunks.is_empty());
+    for chunk in &file.chunks {
+        assert_eq!(chunk.replicas.len(), 1);
+        assert_eq!(chunk.replicas[0].client_port, 33333);
+    }
+    assert!(matches!(
+        admin.file("/nope").await,
+        Err(DfsError::NotFound(_))
+    ));
+    client.mkdir("/test_admin").await.unwrap();
+    assert!(matches!(
+        admin.file("/test_admin").await,
+        Err(DfsError::IllegalArgument(_))
+    ));
+
+    log::info!("start to replicate...");
+    // Every file is fully replicated already
+    assert_eq!(admin.replicate("/").await.unwrap(), 0);
+    assert!(matches!(
+        admin.replicate("/nope").await,
+        Err(DfsError::NotFound(_))
+    ));
+
+    log::info!("start to count the used bytes...");
+    // Within the first chunk of the file
+    let mut file = client.open("/test111").await.unwrap();
+    file.write_all(&[1; 10]).await.unwrap();
+    file.flush().await.unwrap();
+    sleep(Duration::from_millis(1500)).await;
+    let used = admin.servers().await.unwrap()[0].used_bytes;
+    assert_eq!(used, srv.used_bytes + 10);
+
+    log::info!("start to decommission...");
+    assert!(matches!(
+        admin.decommission("localhost", 1).await,
+        Err(DfsError::IllegalState(_))
+    ));
+    let left = admin.decommission("localhost", 33333).await.unwrap();
+    assert_eq!(left as u64, srv.chunks);
+    let servers = admin.servers().await.unwrap();
+    assert!(servers[0].decommissioned);
+    // Nowhere to move the replicas, so they stay, but nothing new goes there
+    sleep(Duration::from_millis(500)).await;
+    for chunk in admin.file("/test111").await.unwrap().chunks {
+        assert_eq!(chunk.replicas.len(), 1);
+    }
+    assert!(matches!(
+        client.create("/test_admin/file").await,
+        Err(DfsError::IllegalState(_))
+    ));
+}
@@ -0,0 +1,456 @@
+use std::{io::SeekFrom, time::Duration};
+
+use tiny_dfs::{
+    client::{DfsClient, DfsError},
+    common::{
+        registration::{ChunkWritten, HeartbeatArg},
+        service::{
+            FileType, FindArg, GetStorageArg, GetStorageOkResponse, LockArg, LockOkResponse,
+            UnlockArg,
+        },
+        storage::AppendArg,
+        wire::FileData,
+        ErrResponse,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    time::timeout,
+};
+
+mod common;
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_client() {
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let client = DfsClient::new("localhost:11111");
+
+    log::warn!("test_client: start...");
+    let create_dir = "/test_client";
+    let create_file = "/test_client/file";
+    client.mkdir(create_dir).await.unwrap();
+    client.create(create_file).await.unwrap();
+    assert!(matches!(
+        client.create(create_file).await,
+        Err(DfsError::IllegalState(_))
+    ));
+    assert_eq!(client.list(create_dir).await.unwrap(), vec!["file"]);
+
+    log::info!("start to write...");
+    // Spanning several chunks
+    let data: Vec<u8> = (0..common::CHUNK_SIZE as u8 * 3 + 5).collect();
+    let mut file = client.open(create_file).await.unwrap();
+    assert!(file.is_empty());
+    file.write_all(&data).await.unwrap();
+    file.flush().await.unwrap();
+    let stat = client.stat(create_file).await.unwrap();
+    assert!(!stat.is_dir);
+    assert_eq!(stat.size, data.len() as u64);
+    assert_eq!(stat.replicas, 1);
+    assert_eq!(stat.owner, std::env::var("USER").ok());
+    assert!(stat.created <= stat.modified && stat.modified <= stat.accessed);
+
+    log::info!("start to read...");
+    let mut file = client.open(create_file).await.unwrap();
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, data);
+
+    log::info!("start to seek...");
+    file.seek(SeekFrom::End(-5)).await.unwrap();
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).await.unwrap();
+    assert_eq!(tail, &data[data.len() - 5..]);
+    file.seek(SeekFrom::Start(10)).await.unwrap();
+    file.write_all(b"overwritten").await.unwrap();
+    file.seek(SeekFrom::Current(-11)).await.unwrap();
+    let mut middle = vec![0; 11];
+    file.read_exact(&mut middle).await.unwrap();
+    assert_eq!(middle, b"overwritten");
+    assert!(file.seek(SeekFrom::Current(-100)).await.is_err());
+
+    log::info!("start to rename...");
+    let renamed_file = "/test_client/renamed";
+    client.rename(create_file, renamed_file).await.unwrap();
+    assert_eq!(client.list(create_dir).await.unwrap(), vec!["renamed"]);
+    let mut file = client.open(renamed_file).await.unwrap();
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(&read[10..21], b"overwritten");
+    client.rename(renamed_file, create_file).await.unwrap();
+
+    log::info!("start to delete...");
+    assert!(client.stat(create_dir).await.unwrap().is_dir);
+    client.delete(create_dir).await.unwrap();
+    assert!(matches!(
+        client.stat(create_file).await,
+        Err(DfsError::NotFound(_))
+    ));
+    assert!(matches!(
+        client.open(create_file).await,
+        Err(DfsError::NotFound(_))
+    ));
+}
+
+/// Paths found by `arg`, in walk order
+async fn find(client: &DfsClient, arg: &FindArg) -> Result<Vec<String>, DfsError> {
+    let entries = client.find(arg).await?.collect().await?;
+    Ok(entries.into_iter().map(|entry| entry.path).collect())
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_find() {
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let client = DfsClient::new("localhost:11111");
+
+    log::warn!("test_find: start...");
+    client.mkdir("/test_find").await.unwrap();
+    client.mkdir("/test_find/sub").await.unwrap();
+    client.create("/test_find/a.txt").await.unwrap();
+    client.create("/test_find/sub/b.txt").await.unwrap();
+    client.create("/test_find/sub/c.log").await.unwrap();
+    let mut file = client.open("/test_find/sub/b.txt").await.unwrap();
+    file.write_all(&[7; 20]).await.unwrap();
+
+    log::info!("start to find everything...");
+    let arg = FindArg {
+        path: "/test_find".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(
+        find(&client, &arg).await.unwrap(),
+        vec![
+            "/test_find",
+            "/test_find/a.txt",
+            "/test_find/sub",
+            "/test_find/sub/b.txt",
+            "/test_find/sub/c.log",
+        ]
+    );
+
+    log::info!("start to find by pattern and type...");
+    let arg = FindArg {
+        path: "/test_find".to_string(),
+        pattern: Some("*.txt".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        find(&client, &arg).await.unwrap(),
+        vec!["/test_find/a.txt", "/test_find/sub/b.txt"]
+    );
+    // A pattern with a slash is matched against the whole path
+    let arg = FindArg {
+        path: "/test_find".to_string(),
+        pattern: Some("/test_find/*.txt".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(find(&client, &arg).await.unwrap(), vec!["/test_find/a.txt"]);
+    let arg = FindArg {
+        path: "/test_find".to_string(),
+        file_type: Some(FileType::Dir),
+        ..Default::default()
+    };
+    assert_eq!(
+        find(&client, &arg).await.unwrap(),
+        vec!["/test_find", "/test_find/sub"]
+    );
+
+    log::info!("start to find by size and mtime...");
+    let arg = FindArg {
+        path: "/test_find".to_string(),
+        file_type: Some(FileType::File),
+        min_size: Some(1),
+        ..Default::default()
+    };
+    assert_eq!(
+        find(&client, &arg).await.unwrap(),
+        vec!["/test_find/sub/b.txt"]
+    );
+    let written = client.stat("/test_find/sub/b.txt").await.unwrap().modified;
+    let arg = FindArg {
+        path: "/test_find/sub".to_string(),
+        file_type: Some(FileType::File),
+        modified_before: Some(written - 1),
+        ..Default::default()
+    };
+    let before = find(&client, &arg).await.unwrap();
+    assert!(!before.contains(&"/test_find/sub/b.txt".to_string()));
+    let arg = FindArg {
+        modified_after: Some(written),
+        modified_before: None,
+        ..arg
+    };
+    assert!(find(&client, &arg)
+        .await
+        .unwrap()
+        .contains(&"/test_find/sub/b.txt".to_string()));
+
+    log::info!("start to find with bad args...");
+    let arg = FindArg {
+        path: "/test_find/nope".to_string(),
+        ..Default::default()
+    };
+    assert!(matches!(
+        find(&client, &arg).await,
+        Err(DfsError::NotFound(_))
+    ));
+    let arg = FindArg {
+        path: "/test_find".to_string(),
+        pattern: Some("[".to_string()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        find(&client, &arg).await,
+        Err(DfsError::IllegalArgument(_))
+    ));
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_truncate_append() {
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let client = DfsClient::new("localhost:11111");
+
+    let http = reqwest::Client::new();
+
+    log::warn!("test_truncate_append: start...");
+    let path = "/test_truncate_append";
+    client.create(path).await.unwrap();
+
+    log::info!("start to append...");
+    // Crossing chunk boundaries
+    let first: Vec<u8> = (0..10).collect();
+    let second: Vec<u8> = (10..40).collect();
+    assert_eq!(client.append(path, &first).await.unwrap(), 0);
+    assert_eq!(client.append(path, &second).await.unwrap(), 10);
+    assert_eq!(client.append(path, &[]).await.unwrap(), 40);
+    assert_eq!(client.stat(path).await.unwrap().size, 40);
+    let mut read = Vec::new();
+    let mut file = client.open(path).await.unwrap();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, (0..40).collect::<Vec<u8>>());
+
+    log::info!("start to truncate...");
+    let arg = GetStorageArg {
+        path: path.to_string(),
+        offset: 0,
+        length: None,
+        write: false,
+    };
+    let resp = http
+        .post("http://localhost:11111/getstorage")
+        .json(&arg)
+        .send()
+        .await
+        .unwrap();
+    let chunks = resp.json::<GetStorageOkResponse>().await.unwrap().chunks;
+    client.truncate(path, 20).await.unwrap();
+    assert_eq!(client.stat(path).await.unwrap().size, 20);
+    // A heartbeat reporting the appends late, from before the truncation
+    let arg = HeartbeatArg {
+        storage_ip: "localhost".to_string(),
+        client_port: 33333,
+        command_port: 44444,
+        used_bytes: 0,
+        capacity_bytes: 0,
+        available_bytes: 0,
+        outstanding_requests: 0,
+        written: vec![ChunkWritten {
+            chunk: chunks[1].chunk,
+            size: 16,
+            modified: 0,
+            epoch: 0,
+        }],
+    };
+    let resp = http
+        .post("http://localhost:22222/heartbeat")
+        .json(&arg)
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    assert_eq!(client.stat(path).await.unwrap().size, 20);
+    let mut read = Vec::new();
+    let mut file = client.open(path).await.unwrap();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, (0..20).collect::<Vec<u8>>());
+    assert!(matches!(
+        client.truncate(path, 21).await,
+        Err(DfsError::IndexOutOfBounds(_))
+    ));
+
+    log::info!("start to append after truncate...");
+    assert_eq!(client.append(path, &[99; 5]).await.unwrap(), 20);
+    client.truncate(path, 0).await.unwrap();
+    assert_eq!(client.stat(path).await.unwrap().size, 0);
+    assert_eq!(client.append(path, b"again").await.unwrap(), 0);
+    let mut read = Vec::new();
+    let mut file = client.open(path).await.unwrap();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, b"again");
+
+    log::info!("start to check errors...");
+    assert!(matches!(
+        client.truncate("/test_truncate_append_missing", 0).await,
+        Err(DfsError::NotFound(_))
+    ));
+    // Straight to the storage server, the client would fail to locate it
+    let arg = AppendArg {
+        path: "/test_truncate_append/../x".to_string(),
+        data: FileData(b"x".to_vec()),
+    };
+    let resp = http
+        .post("http://localhost:33333/storage_append")
+        .json(&arg)
+        .send()
+        .await
+        .unwrap();
+    assert!(!resp.status().is_success());
+    let resp: ErrResponse = resp.json().await.unwrap();
+    assert_eq!(resp.exception_type, "IllegalArgumentException");
+
+    log::info!("start to truncate while somebody holds the lock...");
+    let arg = LockArg {
+        path: path.to_string(),
+        exclusive: true,
+    };
+    let resp = http
+        .post("http://localhost:11111/lock")
+        .json(&arg)
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let token = resp.json::<LockOkResponse>().await.unwrap().token;
+    let truncate = timeout(Duration::from_millis(500), client.truncate(path, 1)).await;
+    assert!(truncate.is_err());
+    let arg = UnlockArg {
+        path: path.to_string(),
+        exclusive: true,
+        token,
+        written_end: None,
+    };
+    let resp = http
+        .post("http://localhost:11111/unlock")
+        .json(&arg)
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    client.truncate(path, 1).await.unwrap();
+    assert_eq!(client.stat(path).await.unwrap().size, 1);
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_sparse_write() {
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let client = DfsClient::new("localhost:11111");
+
+    log::warn!("test_sparse_write: start...");
+    let path = "/test_sparse_write";
+    client.create(path).await.unwrap();
+
+    log::info!("start to write past the end...");
+    // Skipping whole chunks, which are never written
+    let hole = common::CHUNK_SIZE * 2 + 3;
+    let mut file = client.open(path).await.unwrap();
+    file.seek(SeekFrom::Start(hole)).await.unwrap();
+    file.write_all(b"tail").await.unwrap();
+    assert_eq!(client.stat(path).await.unwrap().size, hole + 4);
+
+    log::info!("start to read the hole...");
+    let mut file = client.open(path).await.unwrap();
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).await.unwrap();
+    let mut expected = vec![0; hole as usize];
+    expected.extend_from_slice(b"tail");
+    assert_eq!(read, expected);
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_locked_write() {
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let client = DfsClient::new("localhost:11111");
+    let http = reqwest::Client::new();
+
+    log::warn!("test_locked_write: start...");
+    let path = "/test_locked_write";
+    client.create(path).await.unwrap();
+
+    log::info!("start to write while somebody holds the lock...");
+    let arg = LockArg {
+        path: path.to_string(),
+        exclusive: true,
+    };
+    let resp = http
+        .post("http://localhost:11111/lock")
+        .json(&arg)
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let token = resp.json::<LockOkResponse>().await.unwrap().token;
+    let mut file = client.open(path).await.unwrap();
+    let write = timeout(Duration::from_millis(500), file.write_all(b"blocked")).await;
+    assert!(write.is_err());
+
+    log::info!("start to release the lock...");
+    let arg = UnlockArg {
+        path: path.to_string(),
+        exclusive: true,
+        token,
+        written_end: None,
+    };
+    let resp = http
+        .post("http://localhost:11111/unlock")
+        .json(&arg)
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+
+    log::info!("start to seek with the write in flight...");
+    // Lands where it was started before the seek takes effect
+    file.seek(SeekFrom::Start(2)).await.unwrap();
+    file.write_all(b"X").await.unwrap();
+    let mut file = client.open(path).await.unwrap();
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, b"blXcked");
+
+    log::info!("start to unlock claiming more than written...");
+    let resp = http
+        .post("http://localhost:11111/lock")
+        .json(&LockArg {
+            path: path.to_string(),
+            exclusive: true,
+        })
+        .send()
+        .await
+        .unwrap();
+    let token = resp.json::<LockOkResponse>().await.unwrap().token;
+    let arg = UnlockArg {
+        path: path.to_string(),
+        exclusive: true,
+        token,
+        written_end: Some(12),
+    };
+    let resp = http
+        .post("http://localhost:11111/unlock")
+        .json(&arg)
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    // Only as far as the replicas have the bytes
+    assert_eq!(client.stat(path).await.unwrap().size, 7);
+}
@@ -1,10 +1,26 @@
-use std::{fs, time::Duration};
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    io::Read,
+    process::{self, Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
 
 use once_cell::sync::Lazy;
-use rocket::futures::lock::Mutex;
-use tiny_dfs::{start_naming_server, start_storage_server};
+use rocket::{futures::lock::Mutex, serde::json};
+use tiny_dfs::{
+    client::AdminClient,
+    common::service::CreateFileArg,
+    config::{NamingConfig, StorageConfig},
+    start_naming_server, start_storage_server,
+};
 use tokio::time::sleep;
 
+/// Bytes of every chunk but the last one, small enough to split the test files
+pub const CHUNK_SIZE: u64 = 16;
+
 static INIT_LOCK: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
 
 pub async fn init(new_files: &Vec<&str>) {
@@ -18,37 +34,209 @@ pub async fn init(new_files: &Vec<&str>) {
 
     env_logger::init();
 
-    let service_port = "11111";
-    let registration_port = "22222";
-
-    // Start a naming server
-    let _naming_server = rocket::tokio::spawn(async move {
-        let args = vec![
-            "".to_string(),
-            "".to_string(),
-            service_port.to_string(),
-            registration_port.to_string(),
-        ];
-        start_naming_server(&args).await;
-    });
+    let service_port = 11111;
+    let registration_port = 22222;
+
+    // Start from an empty namespace
+    let meta_dir = "/tmp/tiny-dfs-naming";
+    let _ = fs::remove_dir_all(meta_dir);
 
-    // Start a storage server
     let local_dir = "/tmp/tiny-dfs";
-    // let new_file = "/test111";
+    let _ = fs::remove_dir_all(local_dir);
+    fs::create_dir_all(local_dir).unwrap();
+
+    // Every test owns a runtime of its own, so the servers need one that
+    // outlives the test which happens to start them
+    thread::spawn(move || {
+        rocket::tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                // Start a naming server
+                let _naming_server = rocket::tokio::spawn(async move {
+                    let config = NamingConfig {
+                        service_port,
+                        registration_port,
+                        meta_dir: meta_dir.into(),
+                        replication_threshold: 20,
+                        replication_factor: 1,
+                        placement: "random".to_string(),
+                        chunk_size: CHUNK_SIZE,
+                        ..Default::default()
+                    };
+                    start_naming_server(&config).await;
+                });
+
+                sleep(Duration::from_millis(100)).await;
+
+                // Start a storage server
+                let config = StorageConfig {
+                    client_port: 33333,
+                    command_port: 44444,
+                    naming_address: format!("localhost:{}", registration_port),
+                    data_dir: local_dir.into(),
+                    ..Default::default()
+                };
+                start_storage_server(&config).await;
+            });
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let client = reqwest::Client::new();
     for new_file in new_files {
-        fs::File::create(local_dir.to_owned() + new_file).unwrap();
+        let arg = CreateFileArg {
+            path: new_file.to_string(),
+            replication: None,
+            owner: None,
+        };
+        let addr = format!("http://localhost:{}/create_file", service_port);
+        client.post(addr).json(&arg).send().await.unwrap();
     }
-    let _storage_server = rocket::tokio::spawn(async move {
-        let args = vec![
-            "".to_string(),
-            "".to_string(),
-            "33333".to_string(),
-            "44444".to_string(),
-            registration_port.to_string(),
-            local_dir.to_string(),
-        ];
-        start_storage_server(&args).await;
+}
+
+/// Which server a re-executed test binary runs, naming or storage
+const SERVER_ENV: &str = "TINY_DFS_TEST_SERVER";
+
+/// Config of that server, in JSON
+const CONFIG_ENV: &str = "TINY_DFS_TEST_CONFIG";
+
+/// A server running in a child process, killed when dropped
+///
+/// The servers keep their state in statics, so the tests needing more than
+/// one of a kind, or restarting one, run them out of process.
+pub struct ServerProcess(Child);
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn spawn_server(kind: &str, config: String) -> ServerProcess {
+    let child = Command::new(env::current_exe().unwrap())
+        .args([
+            "--ignored",
+            "--exact",
+            "common::server_process",
+            "--nocapture",
+        ])
+        .env(SERVER_ENV, kind)
+        .env(CONFIG_ENV, config)
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    ServerProcess(child)
+}
+
+pub fn spawn_naming_server(config: &NamingConfig) -> ServerProcess {
+    spawn_server("naming", json::to_string(config).unwrap())
+}
+
+pub fn spawn_storage_server(config: &StorageConfig) -> ServerProcess {
+    spawn_server("storage", json::to_string(config).unwrap())
+}
+
+/// Wait for a server to accept connections on `port`
+pub async fn wait_for_port(port: u16) {
+    for _ in 0..100 {
+        if rocket::tokio::net::TcpStream::connect(("localhost", port))
+            .await
+            .is_ok()
+        {
+            return;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    panic!("nothing listens on port {}", port);
+}
+
+/// Config of a naming server listening on the default ports plus
+/// `port_offset`, with an empty meta dir
+pub fn fresh_naming(port_offset: u16) -> NamingConfig {
+    let config = NamingConfig {
+        service_port: 11111 + port_offset,
+        registration_port: 22222 + port_offset,
+        meta_dir: format!("/tmp/tiny-dfs-naming-{}", port_offset).into(),
+        chunk_size: CHUNK_SIZE,
+        ..Default::default()
+    };
+    let _ = fs::remove_dir_all(&config.meta_dir);
+    config
+}
+
+pub async fn spawn_naming(config: &NamingConfig) -> ServerProcess {
+    let server = spawn_naming_server(config);
+    wait_for_port(config.service_port).await;
+    wait_for_port(config.registration_port).await;
+    server
+}
+
+/// Config of a storage server registering with the naming server of
+/// `port_offset`, with an empty data dir
+pub fn fresh_storage(port_offset: u16, client_port: u16) -> StorageConfig {
+    let config = StorageConfig {
+        client_port,
+        command_port: client_port + 11111,
+        naming_address: format!("localhost:{}", 22222 + port_offset),
+        data_dir: format!("/tmp/tiny-dfs-{}", client_port).into(),
+        ..Default::default()
+    };
+    let _ = fs::remove_dir_all(&config.data_dir);
+    fs::create_dir_all(&config.data_dir).unwrap();
+    config
+}
+
+pub async fn spawn_storage(config: &StorageConfig) -> ServerProcess {
+    let server = spawn_storage_server(config);
+    // Registered before listening
+    wait_for_port(config.client_port).await;
+    server
+}
+
+/// Client ports of the alive replicas of every chunk of `path`
+pub async fn alive_replicas(admin: &AdminClient, path: &str) -> Vec<Vec<u16>> {
+    let file = admin.file(path).await.unwrap();
+    file.chunks
+        .iter()
+        .map(|chunk| {
+            chunk
+                .replicas
+                .iter()
+                .filter(|replica| replica.alive)
+                .map(|replica| replica.client_port)
+                .collect()
+        })
+        .collect()
+}
+
+/// Entry point of the servers spawned by `spawn_server`, a no-op otherwise
+#[test]
+#[ignore]
+fn server_process() {
+    let Ok(kind) = env::var(SERVER_ENV) else {
+        return;
+    };
+    let config = env::var(CONFIG_ENV).unwrap();
+    let _ = env_logger::try_init();
+
+    // Exit along with the test process, which holds the other end of stdin
+    thread::spawn(|| {
+        let _ = std::io::stdin().read_to_end(&mut Vec::new());
+        process::exit(0);
     });
 
-    sleep(Duration::from_millis(300)).await;
+    rocket::tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async move {
+            match kind.as_str() {
+                "naming" => start_naming_server(&json::from_str(&config).unwrap()).await,
+                "storage" => start_storage_server(&json::from_str(&config).unwrap()).await,
+                _ => panic!("unknown server {:?}", kind),
+            }
+        });
 }
@@ -0,0 +1,85 @@
+use std::fs;
+
+use tiny_dfs::config::{NamingConfig, StorageConfig};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn test_config() {
+    let default = NamingConfig::load(&[]).unwrap();
+    assert_eq!(default.service_port, 11111);
+    assert_eq!(default.placement, "random");
+
+    log::info!("start to layer the sources...");
+    let file = "/tmp/tiny-dfs-config.toml";
+    fs::write(
+        file,
+        "service_port = 1\nregistration_port = 2\nmeta_dir = \"/tmp/meta\"\nchunk_size = 16\n",
+    )
+    .unwrap();
+    std::env::set_var("TINY_DFS_NAMING_SERVICE_PORT", "3");
+    std::env::set_var("TINY_DFS_NAMING_REGISTRATION_PORT", "4");
+    let config = NamingConfig::load(&args(&[
+        "--config",
+        file,
+        "--service-port=5",
+        "--placement",
+        "round-robin",
+    ]))
+    .unwrap();
+    // Flags over the environment over the file
+    assert_eq!(config.service_port, 5);
+    assert_eq!(config.registration_port, 4);
+    assert_eq!(config.meta_dir.to_str(), Some("/tmp/meta"));
+    assert_eq!(config.chunk_size, 16);
+    assert_eq!(config.placement, "round-robin");
+    std::env::remove_var("TINY_DFS_NAMING_SERVICE_PORT");
+    std::env::remove_var("TINY_DFS_NAMING_REGISTRATION_PORT");
+
+    log::info!("start to reject bad ones...");
+    for bad in [
+        &["--service-port", "abc"][..],
+        &["--no-such-setting", "1"],
+        &["--placement", "nope"],
+        &["--service-port", "22222"],
+        &["--config", "/tmp/no-such-config.toml"],
+        &["--chunk-size"],
+        &["stray"],
+    ] {
+        assert!(NamingConfig::load(&args(bad)).is_err(), "{:?}", bad);
+    }
+
+    let config = StorageConfig::load(&args(&[
+        "--advertised-host",
+        "10.0.0.1",
+        "--naming-address",
+        "10.0.0.2:22222",
+        "--data-dir",
+        "/tmp/data",
+    ]))
+    .unwrap();
+    assert_eq!(config.advertised_host, "10.0.0.1");
+    assert_eq!(config.naming_address, "10.0.0.2:22222");
+    assert_eq!(config.client_port, 33333);
+    let config = StorageConfig::load(&args(&[
+        "--advertised-host",
+        "::1",
+        "--naming-address",
+        "[::1]:22222",
+    ]))
+    .unwrap();
+    assert_eq!(config.advertised_host, "::1");
+    assert_eq!(config.naming_address, "[::1]:22222");
+    for bad in [
+        &["--naming-address", "localhost"][..],
+        &["--naming-address", "::1:22222"],
+        &["--naming-address", "localhost:0"],
+        &["--advertised-host", "-nope-"],
+        &["--advertised-host", "10.0.0.256"],
+    ] {
+        assert!(StorageConfig::load(&args(bad)).is_err(), "{:?}", bad);
+    }
+    assert!(StorageConfig::load(&args(&["--bind-address", "nope"])).is_err());
+}
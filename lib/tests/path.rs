@@ -0,0 +1,43 @@
+use tiny_dfs::common::path::{DfsPath, MAX_NAME_LEN};
+
+#[test]
+fn test_path() {
+    for (path, canonical) in [
+        ("/", "/"),
+        ("//", "/"),
+        ("/a", "/a"),
+        ("/a/b/", "/a/b"),
+        ("//a///b", "/a/b"),
+        ("/a.b/..c/...", "/a.b/..c/..."),
+    ] {
+        assert_eq!(DfsPath::parse(path).unwrap().as_str(), canonical);
+    }
+    let long_name = format!("/{}", "x".repeat(MAX_NAME_LEN + 1));
+    for bad in [
+        "",
+        "a/b",
+        "/a/../../etc/passwd",
+        "/a/./b",
+        "/..",
+        "/a\0b",
+        &long_name,
+    ] {
+        assert!(DfsPath::parse(bad).is_err(), "{:?}", bad);
+    }
+
+    log::info!("start to walk paths...");
+    let path = DfsPath::parse("/a/b").unwrap();
+    assert_eq!(path.names(), vec!["a", "b"]);
+    assert_eq!(path.name(), Some("b"));
+    assert!(DfsPath::root().names().is_empty());
+    assert_eq!(DfsPath::root().join("a").unwrap().as_str(), "/a");
+    assert_eq!(path.join("c").unwrap().as_str(), "/a/b/c");
+    for bad in ["", "..", "c/d"] {
+        assert!(path.join(bad).is_err(), "{:?}", bad);
+    }
+    assert!(path.starts_with(&DfsPath::parse("/a").unwrap()));
+    assert!(path.starts_with(&path));
+    assert!(!DfsPath::parse("/ab")
+        .unwrap()
+        .starts_with(&DfsPath::parse("/a").unwrap()));
+}
@@ -0,0 +1,147 @@
+use std::{collections::HashMap, time::Duration};
+
+use tiny_dfs::{
+    client::{AdminClient, DfsClient},
+    common::service::GetStorageArg,
+    config::NamingConfig,
+};
+use tokio::{io::AsyncWriteExt, time::sleep};
+
+mod common;
+
+/// A naming server with the placement `policy` and storage servers on
+/// `ports`, all killed when dropped
+async fn spawn_cluster(
+    port_offset: u16,
+    policy: &str,
+    ports: &[u16],
+) -> Vec<common::ServerProcess> {
+    let config = NamingConfig {
+        placement: policy.to_string(),
+        ..common::fresh_naming(port_offset)
+    };
+    let mut servers = vec![common::spawn_naming(&config).await];
+    for port in ports {
+        let config = common::fresh_storage(port_offset, *port);
+        servers.push(common::spawn_storage(&config).await);
+    }
+    servers
+}
+
+/// Client port of the server holding the single replica of every chunk of `path`
+async fn replica(admin: &AdminClient, path: &str) -> u16 {
+    let replicas = common::alive_replicas(admin, path).await;
+    assert_eq!(replicas.len(), 1);
+    assert_eq!(replicas[0].len(), 1);
+    replicas[0][0]
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_round_robin() {
+    log::warn!("test_round_robin: start...");
+    let offset = 30;
+    let _cluster = spawn_cluster(offset, "round-robin", &[33360, 33361, 33362]).await;
+    let client = DfsClient::new(&format!("localhost:{}", 11111 + offset));
+    let admin = AdminClient::new(&format!("localhost:{}", 22222 + offset));
+
+    log::info!("start to create files...");
+    let mut placed: HashMap<u16, usize> = HashMap::new();
+    for i in 0..6 {
+        let path = format!("/test_round_robin_{}", i);
+        client.create(&path).await.unwrap();
+        *placed.entry(replica(&admin, &path).await).or_default() += 1;
+    }
+    assert_eq!(placed, HashMap::from([(33360, 2), (33361, 2), (33362, 2)]));
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_consistent_hashing() {
+    log::warn!("test_consistent_hashing: start...");
+    let offset = 31;
+    let ports = [33363, 33364, 33365];
+    let paths: Vec<String> = (0..8)
+        .map(|i| format!("/test_consistent_hashing_{}", i))
+        .collect();
+    let client = DfsClient::new(&format!("localhost:{}", 11111 + offset));
+    let admin = AdminClient::new(&format!("localhost:{}", 22222 + offset));
+
+    // Another naming server, starting from scratch, places them alike
+    log::info!("start to place the same paths twice...");
+    let mut placements = Vec::new();
+    for _ in 0..2 {
+        let _cluster = spawn_cluster(offset, "consistent-hashing", &ports).await;
+        let mut placed = Vec::new();
+        for path in &paths {
+            client.create(path).await.unwrap();
+            placed.push(replica(&admin, path).await);
+        }
+        // Recreated on the same server
+        client.delete(&paths[0]).await.unwrap();
+        client.create(&paths[0]).await.unwrap();
+        assert_eq!(replica(&admin, &paths[0]).await, placed[0]);
+        placements.push(placed);
+    }
+    assert_eq!(placements[0], placements[1]);
+    // Spread over more than one server
+    assert!(placements[0].iter().any(|port| *port != placements[0][0]));
+
+    log::info!("start to place the chunks of a single file...");
+    let _cluster = spawn_cluster(offset, "consistent-hashing", &ports).await;
+    let path = "/test_consistent_hashing_chunks";
+    client.create(path).await.unwrap();
+    let mut file = client.open(path).await.unwrap();
+    file.write_all(&[7; common::CHUNK_SIZE as usize * 8])
+        .await
+        .unwrap();
+    file.flush().await.unwrap();
+    let replicas = common::alive_replicas(&admin, path).await;
+    assert_eq!(replicas.len(), 8);
+    assert!(replicas.iter().any(|ports| *ports != replicas[0]));
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_least_outstanding_requests() {
+    log::warn!("test_least_outstanding_requests: start...");
+    let offset = 32;
+    let _cluster = spawn_cluster(offset, "least-outstanding-requests", &[33366, 33367]).await;
+    let client = DfsClient::new(&format!("localhost:{}", 11111 + offset));
+    let admin = AdminClient::new(&format!("localhost:{}", 22222 + offset));
+
+    log::info!("start to place while idle...");
+    let path = "/test_least_outstanding_requests";
+    client.create(path).await.unwrap();
+    // Expected to be written, so the next one goes elsewhere
+    let first = replica(&admin, path).await;
+    client
+        .create("/test_least_outstanding_requests_2")
+        .await
+        .unwrap();
+    assert_ne!(
+        replica(&admin, "/test_least_outstanding_requests_2").await,
+        first
+    );
+
+    log::info!("start to locate for reads...");
+    // Let a heartbeat report the real load
+    sleep(Duration::from_millis(1500)).await;
+    let http = reqwest::Client::new();
+    let addr = format!("http://localhost:{}/getstorage", 11111 + offset);
+    for _ in 0..50 {
+        let arg = GetStorageArg {
+            path: path.to_string(),
+            offset: 0,
+            length: None,
+            write: false,
+        };
+        let resp = http.post(&addr).json(&arg).send().await.unwrap();
+        assert!(resp.status().is_success());
+    }
+    let outstanding: u64 = admin
+        .servers()
+        .await
+        .unwrap()
+        .iter()
+        .map(|srv| srv.outstanding_requests)
+        .sum();
+    assert!(outstanding < 50, "{} outstanding requests", outstanding);
+}
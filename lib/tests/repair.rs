@@ -0,0 +1,84 @@
+use std::{collections::HashMap, time::Duration};
+
+use tiny_dfs::{
+    client::{AdminClient, DfsClient},
+    common::service::{CreateFileArg, LockArg},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::sleep,
+};
+
+mod common;
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_repair() {
+    log::warn!("test_repair: start...");
+    let offset = 20;
+    let service_port = 11111 + offset;
+    let _naming = common::spawn_naming(&common::fresh_naming(offset)).await;
+    let mut storages = HashMap::new();
+    for port in [33350, 33351, 33352] {
+        let config = common::fresh_storage(offset, port);
+        storages.insert(port, common::spawn_storage(&config).await);
+    }
+    let client = DfsClient::new(&format!("localhost:{}", service_port));
+    let admin = AdminClient::new(&format!("localhost:{}", 22222 + offset));
+    let http = reqwest::Client::new();
+
+    log::info!("start to create files with two replicas...");
+    // Scanned before the other one
+    let locked = "/test_a_locked";
+    let path = "/test_repair";
+    let data = b"repair me";
+    for path in [locked, path] {
+        let arg = CreateFileArg {
+            path: path.to_string(),
+            replication: Some(2),
+            owner: None,
+        };
+        let addr = format!("http://localhost:{}/create_file", service_port);
+        let resp = http.post(addr).json(&arg).send().await.unwrap();
+        assert!(resp.status().is_success());
+        let mut file = client.open(path).await.unwrap();
+        file.write_all(data).await.unwrap();
+        file.flush().await.unwrap();
+    }
+    let replicas = common::alive_replicas(&admin, path).await;
+    assert_eq!(replicas.len(), 1);
+    assert_eq!(replicas[0].len(), 2);
+
+    log::info!("start to lock a file needing repair for good...");
+    let arg = LockArg {
+        path: locked.to_string(),
+        exclusive: true,
+    };
+    let addr = format!("http://localhost:{}/lock", service_port);
+    let resp = http.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+
+    log::info!("start to kill a replica of both files...");
+    let locked_replicas = common::alive_replicas(&admin, locked).await;
+    let victim = *replicas[0]
+        .iter()
+        .find(|port| locked_replicas[0].contains(port))
+        .unwrap_or(&replicas[0][0]);
+    drop(storages.remove(&victim));
+
+    // Dead after 5 s, noticed by a scan every 5 s
+    log::info!("start to wait for the repair...");
+    let mut repaired = Vec::new();
+    for _ in 0..60 {
+        repaired = common::alive_replicas(&admin, path).await.remove(0);
+        if repaired.len() == 2 && !repaired.contains(&victim) {
+            break;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+    assert_eq!(repaired.len(), 2);
+    assert!(!repaired.contains(&victim));
+    let mut read = Vec::new();
+    let mut file = client.open(path).await.unwrap();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, data);
+}
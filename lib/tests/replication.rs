@@ -0,0 +1,153 @@
+use std::{fs, time::Duration};
+
+use tiny_dfs::{
+    client::{AdminClient, DfsClient},
+    common::service::{CreateFileArg, LockArg, LockOkResponse, UnlockArg},
+    config::NamingConfig,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::sleep,
+};
+
+mod common;
+
+/// Lock `path` and unlock it right away
+async fn lock_unlock(service_port: u16, path: &str, exclusive: bool) {
+    let client = reqwest::Client::new();
+    let arg = LockArg {
+        path: path.to_string(),
+        exclusive,
+    };
+    let addr = format!("http://localhost:{}/lock", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let token = resp.json::<LockOkResponse>().await.unwrap().token;
+    let arg = UnlockArg {
+        path: path.to_string(),
+        exclusive,
+        token,
+        written_end: None,
+    };
+    let addr = format!("http://localhost:{}/unlock", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_hot_replication() {
+    log::warn!("test_hot_replication: start...");
+    let offset = 10;
+    let config = NamingConfig {
+        replication_threshold: 3,
+        ..common::fresh_naming(offset)
+    };
+    let _naming = common::spawn_naming(&config).await;
+    let storage_a = common::spawn_storage(&common::fresh_storage(offset, 33340)).await;
+    let client = DfsClient::new(&format!("localhost:{}", 11111 + offset));
+    let admin = AdminClient::new(&format!("localhost:{}", 22222 + offset));
+
+    log::info!("start to write while a single server is up...");
+    let path = "/test_hot_replication";
+    let data: Vec<u8> = (0..common::CHUNK_SIZE as u8 * 2 + 3).collect();
+    client.create(path).await.unwrap();
+    let mut file = client.open(path).await.unwrap();
+    file.write_all(&data).await.unwrap();
+    file.flush().await.unwrap();
+    let _storage_b = common::spawn_storage(&common::fresh_storage(offset, 33341)).await;
+
+    log::info!("start to read often enough...");
+    for _ in 0..3 {
+        // A client of its own, which has to locate the chunks again
+        let client = DfsClient::new(&format!("localhost:{}", 11111 + offset));
+        let mut read = Vec::new();
+        let mut file = client.open(path).await.unwrap();
+        file.read_to_end(&mut read).await.unwrap();
+    }
+    for _ in 0..50 {
+        if common::alive_replicas(&admin, path)
+            .await
+            .iter()
+            .all(|ports| ports.len() == 2)
+        {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(
+        common::alive_replicas(&admin, path).await,
+        vec![vec![33340, 33341]; 3]
+    );
+
+    log::info!("start to write once the first replica is gone...");
+    drop(storage_a);
+    loop {
+        let replicas = common::alive_replicas(&admin, path).await;
+        if replicas.iter().all(|ports| ports == &[33341]) {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    lock_unlock(11111 + offset, path, true).await;
+    assert_eq!(
+        common::alive_replicas(&admin, path).await,
+        vec![vec![33341]; 3]
+    );
+    let mut read = Vec::new();
+    let mut file = client.open(path).await.unwrap();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, data);
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_copy_leftovers() {
+    log::warn!("test_copy_leftovers: start...");
+    let offset = 11;
+    let _naming = common::spawn_naming(&common::fresh_naming(offset)).await;
+    let config = common::fresh_storage(offset, 33342);
+
+    log::info!("start to restart after a crash during copies...");
+    let leftover = config.data_dir.join("7.0123456789abcdef.copying");
+    fs::write(&leftover, b"partial").unwrap();
+    let _storage = common::spawn_storage(&config).await;
+    assert!(!leftover.exists());
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_exclusive_replication() {
+    log::warn!("test_exclusive_replication: start...");
+    let offset = 12;
+    let service_port = 11111 + offset;
+    let _naming = common::spawn_naming(&common::fresh_naming(offset)).await;
+    let _storage_a = common::spawn_storage(&common::fresh_storage(offset, 33344)).await;
+    let _storage_b = common::spawn_storage(&common::fresh_storage(offset, 33345)).await;
+    let client = DfsClient::new(&format!("localhost:{}", service_port));
+    let admin = AdminClient::new(&format!("localhost:{}", 22222 + offset));
+
+    log::info!("start to write a file with two replicas...");
+    let path = "/test_exclusive_replication";
+    let arg = CreateFileArg {
+        path: path.to_string(),
+        replication: Some(2),
+        owner: None,
+    };
+    let addr = format!("http://localhost:{}/create_file", service_port);
+    let resp = reqwest::Client::new()
+        .post(addr)
+        .json(&arg)
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let mut file = client.open(path).await.unwrap();
+    file.write_all(&[7; common::CHUNK_SIZE as usize + 1])
+        .await
+        .unwrap();
+    file.flush().await.unwrap();
+
+    log::info!("start to lock exclusively...");
+    lock_unlock(service_port, path, true).await;
+    for replicas in common::alive_replicas(&admin, path).await {
+        assert_eq!(replicas.len(), 2);
+    }
+}
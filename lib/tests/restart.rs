@@ -0,0 +1,99 @@
+use std::fs;
+
+use tiny_dfs::{
+    client::{AdminClient, DfsClient, DfsError},
+    config::{NamingConfig, StorageConfig},
+};
+
+mod common;
+
+/// Run a naming server of its own until `body` is done
+async fn with_naming_server(config: &NamingConfig, body: impl std::future::Future<Output = ()>) {
+    let _server = common::spawn_naming_server(config);
+    common::wait_for_port(config.service_port).await;
+    common::wait_for_port(config.registration_port).await;
+    body.await;
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_restart() {
+    log::warn!("test_restart: start...");
+    let config = NamingConfig {
+        service_port: 11112,
+        registration_port: 22223,
+        meta_dir: "/tmp/tiny-dfs-naming-restart".into(),
+        ..Default::default()
+    };
+    let _ = fs::remove_dir_all(&config.meta_dir);
+    let client = DfsClient::new("localhost:11112");
+
+    log::info!("start to create before the first restart...");
+    with_naming_server(&config, async {
+        client.mkdir("/test_restart_a").await.unwrap();
+        client.mkdir("/test_restart_a/sub").await.unwrap();
+    })
+    .await;
+
+    // Recovery checkpoints the replayed journal, the records written after
+    // it have to land at the start of the emptied journal
+    log::info!("start to create after the first restart...");
+    with_naming_server(&config, async {
+        assert!(client.stat("/test_restart_a/sub").await.unwrap().is_dir);
+        client.mkdir("/test_restart_b").await.unwrap();
+        client.delete("/test_restart_a/sub").await.unwrap();
+    })
+    .await;
+
+    log::info!("start to check after the second restart...");
+    with_naming_server(&config, async {
+        assert!(client.stat("/test_restart_a").await.unwrap().is_dir);
+        assert!(client.stat("/test_restart_b").await.unwrap().is_dir);
+        assert!(matches!(
+            client.stat("/test_restart_a/sub").await,
+            Err(DfsError::NotFound(_))
+        ));
+    })
+    .await;
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_restart_servers() {
+    log::warn!("test_restart_servers: start...");
+    let config = NamingConfig {
+        service_port: 11113,
+        registration_port: 22224,
+        meta_dir: "/tmp/tiny-dfs-naming-restart-servers".into(),
+        ..Default::default()
+    };
+    let _ = fs::remove_dir_all(&config.meta_dir);
+    let storage_config = StorageConfig {
+        client_port: 33343,
+        command_port: 44454,
+        naming_address: "localhost:22224".to_string(),
+        data_dir: "/tmp/tiny-dfs-restart-servers".into(),
+        ..Default::default()
+    };
+    let _ = fs::remove_dir_all(&storage_config.data_dir);
+    let client = DfsClient::new("localhost:11113");
+    let admin = AdminClient::new("localhost:22224");
+
+    log::info!("start to register before the restart...");
+    with_naming_server(&config, async {
+        let _storage = common::spawn_storage_server(&storage_config);
+        common::wait_for_port(storage_config.client_port).await;
+        client.create("/test_restart_servers").await.unwrap();
+    })
+    .await;
+
+    // The storage server is gone along with the first naming server
+    log::info!("start to check the restored server...");
+    with_naming_server(&config, async {
+        let servers = admin.servers().await.unwrap();
+        assert_eq!(servers.len(), 1);
+        assert!(!servers[0].alive);
+        assert_eq!(servers[0].silence_ms, None);
+        let stat = client.stat("/test_restart_servers").await.unwrap();
+        assert_eq!(stat.replicas, 0);
+    })
+    .await;
+}
@@ -0,0 +1,147 @@
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use tiny_dfs::{
+    client::{AdminClient, DfsClient},
+    common::{
+        service::CreateFileArg,
+        storage::{ChunkId, ScrubOkResponse},
+        ErrResponse,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::sleep,
+};
+
+mod common;
+
+/// Chunk and client port of the replicas of the only chunk of `path`
+async fn only_chunk(admin: &AdminClient, path: &str) -> (ChunkId, Vec<u16>) {
+    let file = admin.file(path).await.unwrap();
+    assert_eq!(file.chunks.len(), 1);
+    let chunk = &file.chunks[0];
+    let ports = chunk.replicas.iter().map(|r| r.client_port).collect();
+    (chunk.chunk, ports)
+}
+
+/// Overwrite a replica behind the back of its storage server
+fn corrupt(client_port: u16, chunk: ChunkId) {
+    let local_path = format!("/tmp/tiny-dfs-{}/{}", client_port, chunk);
+    let len = fs::metadata(&local_path).unwrap().len() as usize;
+    fs::write(&local_path, vec![b'X'; len]).unwrap();
+}
+
+async fn scrub(http: &reqwest::Client, client_port: u16) -> Vec<ChunkId> {
+    let addr = format!("http://localhost:{}/storage_scrub", client_port + 11111);
+    let resp = http.post(addr).send().await.unwrap();
+    assert!(resp.status().is_success());
+    resp.json::<ScrubOkResponse>().await.unwrap().corrupted
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_scrub() {
+    log::warn!("test_scrub: start...");
+    let offset = 40;
+    let service_port = 11111 + offset;
+    let ports = [33370, 33371];
+    let _naming = common::spawn_naming(&common::fresh_naming(offset)).await;
+    let mut storages = Vec::new();
+    for port in ports {
+        let config = common::fresh_storage(offset, port);
+        storages.push(common::spawn_storage(&config).await);
+    }
+    let client = DfsClient::new(&format!("localhost:{}", service_port));
+    let admin = AdminClient::new(&format!("localhost:{}", 22222 + offset));
+    let http = reqwest::Client::new();
+
+    log::info!("start to create files...");
+    let two = "/test_scrub_two";
+    let last = "/test_scrub_last";
+    let data = b"scrub me";
+    for (path, replication) in [(two, 2), (last, 1)] {
+        let arg = CreateFileArg {
+            path: path.to_string(),
+            replication: Some(replication),
+            owner: None,
+        };
+        let addr = format!("http://localhost:{}/create_file", service_port);
+        let resp = http.post(addr).json(&arg).send().await.unwrap();
+        assert!(resp.status().is_success());
+        let mut file = client.open(path).await.unwrap();
+        file.write_all(data).await.unwrap();
+    }
+    let (two_chunk, two_ports) = only_chunk(&admin, two).await;
+    assert_eq!(two_ports.len(), 2);
+    let (last_chunk, last_ports) = only_chunk(&admin, last).await;
+    assert_eq!(last_ports.len(), 1);
+
+    log::info!("start to leave old and new chunks in quarantine...");
+    let quarantine = format!("/tmp/tiny-dfs-{}/quarantine", ports[0]);
+    fs::create_dir_all(&quarantine).unwrap();
+    let old = Path::new(&quarantine).join("1000");
+    let new = Path::new(&quarantine).join("1001");
+    fs::write(&old, "old").unwrap();
+    fs::write(&new, "new").unwrap();
+    let long_ago = SystemTime::now() - Duration::from_secs(30 * 24 * 3600);
+    fs::File::options()
+        .write(true)
+        .open(&old)
+        .unwrap()
+        .set_modified(long_ago)
+        .unwrap();
+
+    log::info!("start to scrub a replica with a healthy one left...");
+    let victim = two_ports[0];
+    corrupt(victim, two_chunk);
+    assert_eq!(scrub(&http, victim).await, vec![two_chunk]);
+    let quarantined = format!("/tmp/tiny-dfs-{}/quarantine/{}", victim, two_chunk);
+    assert!(Path::new(&quarantined).exists());
+    // Copied back from the healthy replica, the only other server
+    let local_path = format!("/tmp/tiny-dfs-{}/{}", victim, two_chunk);
+    let mut repaired = false;
+    for _ in 0..20 {
+        let replicas = common::alive_replicas(&admin, two).await.remove(0);
+        if replicas.len() == 2 && fs::read(&local_path).is_ok_and(|read| read == data) {
+            repaired = true;
+            break;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+    assert!(repaired);
+    let mut read = Vec::new();
+    let mut file = client.open(two).await.unwrap();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, data);
+
+    log::info!("start to check the quarantine has been pruned...");
+    if victim == ports[0] {
+        assert!(!old.exists());
+    } else {
+        assert!(scrub(&http, ports[0]).await.is_empty());
+        assert!(!old.exists());
+    }
+    assert!(new.exists());
+
+    log::info!("start to scrub the last replica...");
+    let victim = last_ports[0];
+    corrupt(victim, last_chunk);
+    assert_eq!(scrub(&http, victim).await, vec![last_chunk]);
+    // Kept in place as the best copy there is
+    let local_path = format!("/tmp/tiny-dfs-{}/{}", victim, last_chunk);
+    let quarantined = format!("/tmp/tiny-dfs-{}/quarantine/{}", victim, last_chunk);
+    assert!(Path::new(&local_path).exists());
+    assert!(!Path::new(&quarantined).exists());
+    let file = admin.file(last).await.unwrap();
+    let replicas = &file.chunks[0].replicas;
+    assert_eq!(replicas.len(), 1);
+    assert!(replicas[0].suspect);
+    let addr = format!("http://localhost:{}/storage_chunk/{}", victim, last_chunk);
+    let resp = http.get(addr).send().await.unwrap();
+    assert!(!resp.status().is_success());
+    let resp: ErrResponse = resp.json().await.unwrap();
+    assert_eq!(resp.exception_type, "ChecksumException");
+}
@@ -1,15 +1,41 @@
-use std::vec;
+use std::{time::Duration, vec};
 
 use tiny_dfs::common::{
+    admin::AdminServersOkResponse,
     service::{
-        CreateDirectoryArg, CreateFileArg, DeleteArg, IsDirectoryArg, IsValidPathArg,
-        IsValidPathResponse, ListArg, ListOkResponse,
+        CreateDirectoryArg, CreateFileArg, DeleteArg, FileType, GetStorageArg,
+        GetStorageOkResponse, IsDirectoryArg, IsValidPathArg, IsValidPathResponse, ListArg,
+        ListOkResponse, LockArg, LockOkResponse, RenameArg, SetReplicationArg, StatArg,
+        StatOkResponse, UnlockArg,
     },
+    storage::{ChunkId, WriteArg},
+    wire::FileData,
     ErrResponse, OkResponse,
 };
+use tokio::time::sleep;
 
 mod common;
 
+/// Ids of the chunks of the file at `path`
+async fn chunk_ids(client: &reqwest::Client, path: &str) -> Vec<ChunkId> {
+    let arg = GetStorageArg {
+        path: path.to_string(),
+        offset: 0,
+        length: None,
+        write: false,
+    };
+    let addr = format!("http://localhost:{}/getstorage", 11111);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let resp: GetStorageOkResponse = resp.json().await.unwrap();
+    resp.chunks.iter().map(|chunk| chunk.chunk).collect()
+}
+
+/// Whether the storage server keeps the chunk `id`
+fn chunk_stored(id: ChunkId) -> bool {
+    std::path::Path::new(&format!("/tmp/tiny-dfs/{}", id)).exists()
+}
+
 #[rocket::tokio::test(flavor = "multi_thread")]
 async fn test_valid_path() {
     let new_files = vec!["/test111", "/test222"];
@@ -29,6 +55,27 @@ async fn test_valid_path() {
     assert!(resp.status().is_success());
     let resp: IsValidPathResponse = resp.json().await.unwrap();
     assert!(resp.success);
+
+    log::info!("start to verify odd paths...");
+    for (path, valid) in [("//test111/", true), ("/test111/..", false), ("/.", false)] {
+        let arg = IsValidPathArg {
+            path: path.to_string(),
+        };
+        let addr = format!("http://localhost:{}/is_valid_path", 11111);
+        let resp = client.post(addr).json(&arg).send().await.unwrap();
+        let resp: IsValidPathResponse = resp.json().await.unwrap();
+        assert_eq!(resp.success, valid, "{:?}", path);
+    }
+    let arg = CreateFileArg {
+        path: "/test_valid_path/../escaped".to_string(),
+        replication: None,
+        owner: None,
+    };
+    let addr = format!("http://localhost:{}/create_file", 11111);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(!resp.status().is_success());
+    let resp: ErrResponse = resp.json().await.unwrap();
+    assert_eq!(resp.exception_type, "IllegalArgumentException");
 }
 
 #[rocket::tokio::test(flavor = "multi_thread")]
@@ -104,6 +151,8 @@ async fn test_create_file() {
     log::info!("start to create dir...");
     let arg = CreateDirectoryArg {
         path: create_dir.to_string(),
+        replication: None,
+        owner: None,
     };
     let client = reqwest::Client::new();
     let addr = format!("http://localhost:{}/create_directory", service_port);
@@ -112,6 +161,8 @@ async fn test_create_file() {
     log::info!("start to create file...");
     let arg = CreateFileArg {
         path: create_file.to_string(),
+        replication: None,
+        owner: None,
     };
     let client = reqwest::Client::new();
     let addr = format!("http://localhost:{}/create_file", service_port);
@@ -163,6 +214,8 @@ async fn test_list_dir() {
     log::info!("start to create dir...");
     let arg = CreateDirectoryArg {
         path: create_dir.to_string(),
+        replication: None,
+        owner: None,
     };
     let addr = format!("http://localhost:{}/create_directory", service_port);
     let _resp = client.post(addr).json(&arg).send().await.unwrap();
@@ -170,6 +223,8 @@ async fn test_list_dir() {
     log::info!("start to create file...");
     let arg = CreateFileArg {
         path: create_file.to_string(),
+        replication: None,
+        owner: None,
     };
     let addr = format!("http://localhost:{}/create_file", service_port);
     let resp = client.post(addr).json(&arg).send().await.unwrap();
@@ -186,6 +241,8 @@ async fn test_list_dir() {
     log::info!("start to create file2...");
     let arg = CreateFileArg {
         path: create_file2.to_string(),
+        replication: None,
+        owner: None,
     };
     let addr = format!("http://localhost:{}/create_file", service_port);
     let resp = client.post(addr).json(&arg).send().await.unwrap();
@@ -206,10 +263,573 @@ async fn test_list_dir() {
     log::info!("start to test list dir...");
     let arg = ListArg {
         path: create_dir.to_string(),
+        ..Default::default()
     };
     let addr = format!("http://localhost:{}/list", service_port);
-    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
     assert!(resp.status().is_success());
     let resp: ListOkResponse = resp.json().await.unwrap();
     log::info!("list dir: {:?}", resp.files);
+    assert_eq!(resp.files, vec!["test777", "test888"]);
+    assert!(resp.entries.is_none() && resp.continuation_token.is_none());
+
+    log::info!("start to list a page at a time...");
+    let arg = ListArg {
+        path: create_dir.to_string(),
+        limit: Some(1),
+        detailed: true,
+        ..Default::default()
+    };
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    let resp: ListOkResponse = resp.json().await.unwrap();
+    assert_eq!(resp.files, vec!["test777"]);
+    let entries = resp.entries.unwrap();
+    assert_eq!(entries[0].name, "test777");
+    assert_eq!(entries[0].file_type, FileType::File);
+    assert_eq!(entries[0].size, 0);
+    assert!(entries[0].modified > 0);
+    assert_eq!(resp.continuation_token.as_deref(), Some("test777"));
+    let arg = ListArg {
+        path: create_dir.to_string(),
+        start_after: resp.continuation_token,
+        limit: Some(1),
+        ..Default::default()
+    };
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    let resp: ListOkResponse = resp.json().await.unwrap();
+    assert_eq!(resp.files, vec!["test888"]);
+    assert!(resp.continuation_token.is_none());
+
+    let arg = ListArg {
+        path: create_file.to_string(),
+        ..Default::default()
+    };
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_journal() {
+    let service_port = 11111;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+
+    log::warn!("test_journal: start...");
+    let create_dir = "/test999";
+
+    log::info!("start to create dir...");
+    let arg = CreateDirectoryArg {
+        path: create_dir.to_string(),
+        replication: None,
+        owner: None,
+    };
+    let client = reqwest::Client::new();
+    let addr = format!("http://localhost:{}/create_directory", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+
+    log::info!("start to verify journal...");
+    // The mutation must have been persisted before the response
+    let journal = std::fs::read_to_string("/tmp/tiny-dfs-naming/journal").unwrap();
+    assert!(journal.contains(&format!("\"path\":\"{}\"", create_dir)));
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_delete_dir() {
+    let service_port = 11111;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+
+    log::warn!("test_delete_dir: start...");
+    let delete_dir = "/test555";
+    let create_files = vec!["/test555/test666", "/test555/sub/test777"];
+    let client = reqwest::Client::new();
+
+    log::info!("start to create dirs...");
+    for create_dir in [delete_dir, "/test555/sub"] {
+        let arg = CreateDirectoryArg {
+            path: create_dir.to_string(),
+            replication: None,
+            owner: None,
+        };
+        let addr = format!("http://localhost:{}/create_directory", service_port);
+        let _resp = client.post(addr).json(&arg).send().await.unwrap();
+    }
+
+    log::info!("start to create files...");
+    for create_file in &create_files {
+        let arg = CreateFileArg {
+            path: create_file.to_string(),
+            replication: None,
+            owner: None,
+        };
+        let addr = format!("http://localhost:{}/create_file", service_port);
+        let resp = client.post(addr).json(&arg).send().await.unwrap();
+        assert!(resp.status().is_success());
+    }
+
+    let mut chunks = vec![];
+    for create_file in &create_files {
+        chunks.extend(chunk_ids(&client, create_file).await);
+    }
+    assert!(chunks.iter().all(|id| chunk_stored(*id)));
+
+    log::info!("start to delete dir while a file in it is locked...");
+    let arg = LockArg {
+        path: create_files[1].to_string(),
+        exclusive: false,
+    };
+    let addr = format!("http://localhost:{}/lock", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let token = resp.json::<LockOkResponse>().await.unwrap().token;
+    let arg = DeleteArg {
+        path: delete_dir.to_string(),
+    };
+    let addr = format!("http://localhost:{}/delete", service_port);
+    let delete = rocket::tokio::spawn(client.post(addr).json(&arg).send());
+    sleep(Duration::from_millis(500)).await;
+    assert!(!delete.is_finished());
+
+    log::info!("start to delete dir once unlocked...");
+    let arg = UnlockArg {
+        path: create_files[1].to_string(),
+        exclusive: false,
+        token,
+        written_end: None,
+    };
+    let addr = format!("http://localhost:{}/unlock", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let resp = delete.await.unwrap().unwrap();
+    assert!(resp.status().is_success());
+    let resp: OkResponse = resp.json().await.unwrap();
+    assert!(resp.success);
+
+    log::info!("start to verify path...");
+    for path in create_files.iter().chain([&delete_dir]) {
+        let arg = IsValidPathArg {
+            path: path.to_string(),
+        };
+        let addr = format!("http://localhost:{}/is_valid_path", service_port);
+        let resp = client.post(addr).json(&arg).send().await.unwrap();
+        assert!(resp.status().is_success());
+        let resp: IsValidPathResponse = resp.json().await.unwrap();
+        assert!(!resp.success);
+    }
+    // The storage server has removed the chunks of the files
+    assert!(chunks.iter().all(|id| !chunk_stored(*id)));
+
+    log::info!("start to delete an invalid path...");
+    let arg = DeleteArg {
+        path: "/test555/../x".to_string(),
+    };
+    let addr = format!("http://localhost:{}/delete", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(!resp.status().is_success());
+    let resp: ErrResponse = resp.json().await.unwrap();
+    assert_eq!(resp.exception_type, "IllegalArgumentException");
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_rename() {
+    let service_port = 11111;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+
+    log::warn!("test_rename: start...");
+    let src_dir = "/test333";
+    let dst_dir = "/test444";
+    let create_file = "/test333/test888";
+    let renamed_file = "/test444/test888";
+    let client = reqwest::Client::new();
+
+    log::info!("start to create dir...");
+    let arg = CreateDirectoryArg {
+        path: src_dir.to_string(),
+        replication: None,
+        owner: None,
+    };
+    let addr = format!("http://localhost:{}/create_directory", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+
+    log::info!("start to create file...");
+    let arg = CreateFileArg {
+        path: create_file.to_string(),
+        replication: None,
+        owner: None,
+    };
+    let addr = format!("http://localhost:{}/create_file", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+
+    let chunks = chunk_ids(&client, create_file).await;
+
+    log::info!("start to rename dir while a file in it is locked...");
+    let arg = LockArg {
+        path: create_file.to_string(),
+        exclusive: false,
+    };
+    let addr = format!("http://localhost:{}/lock", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let token = resp.json::<LockOkResponse>().await.unwrap().token;
+    let arg = RenameArg {
+        src_path: src_dir.to_string(),
+        dst_path: dst_dir.to_string(),
+    };
+    let addr = format!("http://localhost:{}/rename", service_port);
+    let rename = rocket::tokio::spawn(client.post(addr).json(&arg).send());
+    sleep(Duration::from_millis(500)).await;
+    assert!(!rename.is_finished());
+
+    log::info!("start to rename dir once unlocked...");
+    let arg = UnlockArg {
+        path: create_file.to_string(),
+        exclusive: false,
+        token,
+        written_end: None,
+    };
+    let addr = format!("http://localhost:{}/unlock", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let resp = rename.await.unwrap().unwrap();
+    assert!(resp.status().is_success());
+    let resp: OkResponse = resp.json().await.unwrap();
+    assert!(resp.success);
+
+    log::info!("start to rename dir into itself...");
+    let arg = RenameArg {
+        src_path: dst_dir.to_string(),
+        dst_path: renamed_file.to_string() + "/test999",
+    };
+    let addr = format!("http://localhost:{}/rename", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(!resp.status().is_success());
+
+    log::info!("start to verify path...");
+    for (path, valid) in [(create_file, false), (renamed_file, true)] {
+        let arg = IsValidPathArg {
+            path: path.to_string(),
+        };
+        let addr = format!("http://localhost:{}/is_valid_path", service_port);
+        let resp = client.post(addr).json(&arg).send().await.unwrap();
+        assert!(resp.status().is_success());
+        let resp: IsValidPathResponse = resp.json().await.unwrap();
+        assert_eq!(resp.success, valid);
+    }
+    // Renaming keeps the chunks where they are
+    assert_eq!(chunk_ids(&client, renamed_file).await, chunks);
+    assert!(chunks.iter().all(|id| chunk_stored(*id)));
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_lock() {
+    let service_port = 11111;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+
+    log::warn!("test_lock: start...");
+    let client = reqwest::Client::new();
+
+    log::info!("start to lock exclusively...");
+    let arg = LockArg {
+        path: new_files[0].to_string(),
+        exclusive: true,
+    };
+    let addr = format!("http://localhost:{}/lock", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let token = resp.json::<LockOkResponse>().await.unwrap().token;
+
+    log::info!("start to lock shared while locked exclusively...");
+    let waiter = rocket::tokio::spawn(async move {
+        let arg = LockArg {
+            path: "/test111".to_string(),
+            exclusive: false,
+        };
+        let addr = format!("http://localhost:{}/lock", service_port);
+        let resp = reqwest::Client::new()
+            .post(addr)
+            .json(&arg)
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        resp.json::<LockOkResponse>().await.unwrap().token
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert!(!waiter.is_finished());
+
+    log::info!("start to unlock without holding the lock...");
+    let addr = format!("http://localhost:{}/unlock", service_port);
+    for (exclusive, token) in [(false, token), (true, token + 1000)] {
+        let arg = UnlockArg {
+            path: new_files[0].to_string(),
+            exclusive,
+            token,
+            written_end: None,
+        };
+        let resp = client.post(&addr).json(&arg).send().await.unwrap();
+        assert!(!resp.status().is_success());
+    }
+    assert!(!waiter.is_finished());
+
+    log::info!("start to unlock...");
+    let arg = UnlockArg {
+        path: new_files[0].to_string(),
+        exclusive: true,
+        token,
+        written_end: None,
+    };
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let token = waiter.await.unwrap();
+
+    log::info!("start to unlock twice...");
+    let arg = UnlockArg {
+        path: new_files[0].to_string(),
+        exclusive: false,
+        token,
+        written_end: None,
+    };
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    assert!(!resp.status().is_success());
+    let resp: ErrResponse = resp.json().await.unwrap();
+    assert_eq!(resp.exception_type, "IllegalArgumentException");
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_heartbeat() {
+    let registration_port = 22222;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+
+    log::warn!("test_heartbeat: start...");
+    // Outlive the server timeout, heartbeats keep the server alive
+    tokio::time::sleep(std::time::Duration::from_secs(6)).await;
+
+    log::info!("start to list servers...");
+    let client = reqwest::Client::new();
+    let addr = format!("http://localhost:{}/admin/servers", registration_port);
+    let resp = client.get(addr).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let resp: AdminServersOkResponse = resp.json().await.unwrap();
+    assert_eq!(resp.servers.len(), 1);
+    assert_eq!(resp.servers[0].client_port, 33333);
+    assert!(resp.servers[0].alive);
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_replication() {
+    let service_port = 11111;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+
+    log::warn!("test_replication: start...");
+    let create_dir = "/test666";
+    let create_file = "/test666/test777";
+    let client = reqwest::Client::new();
+
+    log::info!("start to create dir with replication...");
+    let arg = CreateDirectoryArg {
+        path: create_dir.to_string(),
+        replication: Some(3),
+        owner: None,
+    };
+    let addr = format!("http://localhost:{}/create_directory", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+
+    log::info!("start to create file with less servers than its replication...");
+    let arg = CreateFileArg {
+        path: create_file.to_string(),
+        replication: None,
+        owner: None,
+    };
+    let addr = format!("http://localhost:{}/create_file", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let chunks = chunk_ids(&client, create_file).await;
+    assert!(chunks.iter().all(|id| chunk_stored(*id)));
+
+    log::info!("start to set replication...");
+    let addr = format!("http://localhost:{}/set_replication", service_port);
+    for (path, replication, ok) in [
+        (create_dir, 1, true),
+        (create_file, 2, true),
+        (create_file, 0, false),
+        ("/test666/test888", 1, false),
+    ] {
+        let arg = SetReplicationArg {
+            path: path.to_string(),
+            replication,
+        };
+        let resp = client.post(&addr).json(&arg).send().await.unwrap();
+        assert_eq!(resp.status().is_success(), ok);
+    }
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_locate_out_of_range() {
+    let service_port = 11111;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+
+    log::warn!("test_locate_out_of_range: start...");
+    let create_file = "/test_locate_out_of_range";
+    let client = reqwest::Client::new();
+    let arg = CreateFileArg {
+        path: create_file.to_string(),
+        replication: None,
+        owner: None,
+    };
+    let addr = format!("http://localhost:{}/create_file", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    assert_eq!(chunk_ids(&client, create_file).await.len(), 1);
+
+    log::info!("start to write past the end of the file...");
+    let addr = format!("http://localhost:{}/getstorage", service_port);
+    for (offset, length) in [(u64::MAX - 1, 10), (1 << 40, 1)] {
+        let arg = GetStorageArg {
+            path: create_file.to_string(),
+            offset,
+            length: Some(length),
+            write: true,
+        };
+        let resp = client.post(&addr).json(&arg).send().await.unwrap();
+        assert!(!resp.status().is_success());
+        let resp: ErrResponse = resp.json().await.unwrap();
+        assert_eq!(resp.exception_type, "IndexOutOfBoundsException");
+    }
+    assert_eq!(chunk_ids(&client, create_file).await.len(), 1);
+
+    log::info!("start to write a few chunks past the end...");
+    let arg = GetStorageArg {
+        path: create_file.to_string(),
+        offset: common::CHUNK_SIZE * 3,
+        length: Some(1),
+        write: true,
+    };
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    assert_eq!(chunk_ids(&client, create_file).await.len(), 4);
+}
+
+/// Stat of the file at `path`
+async fn stat(client: &reqwest::Client, path: &str) -> Result<StatOkResponse, ErrResponse> {
+    let arg = StatArg {
+        path: path.to_string(),
+    };
+    let addr = format!("http://localhost:{}/stat", 11111);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    if resp.status().is_success() {
+        Ok(resp.json().await.unwrap())
+    } else {
+        Err(resp.json().await.unwrap())
+    }
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_stat() {
+    let service_port = 11111;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+
+    log::warn!("test_stat: start...");
+    let create_dir = "/test_stat";
+    let create_file = "/test_stat/file";
+    let client = reqwest::Client::new();
+
+    log::info!("start to create...");
+    let arg = CreateDirectoryArg {
+        path: create_dir.to_string(),
+        replication: None,
+        owner: Some("alice".to_string()),
+    };
+    let addr = format!("http://localhost:{}/create_directory", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let arg = CreateFileArg {
+        path: create_file.to_string(),
+        replication: None,
+        owner: Some("alice".to_string()),
+    };
+    let addr = format!("http://localhost:{}/create_file", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+
+    let created = stat(&client, create_file).await.unwrap();
+    assert_eq!(created.file_type, FileType::File);
+    assert_eq!(created.size, 0);
+    assert_eq!(created.replicas, 1);
+    assert_eq!(created.owner.as_deref(), Some("alice"));
+    assert!(created.created > 0);
+    assert_eq!(created.modified, created.created);
+    let dir = stat(&client, create_dir).await.unwrap();
+    assert_eq!(dir.file_type, FileType::Dir);
+    assert_eq!((dir.size, dir.replicas), (0, 0));
+    // Creating the file changed the dir
+    assert_eq!(dir.modified, created.created);
+
+    log::info!("start to write...");
+    let arg = GetStorageArg {
+        path: create_file.to_string(),
+        offset: 0,
+        length: Some(common::CHUNK_SIZE + 3),
+        write: true,
+    };
+    let addr = format!("http://localhost:{}/getstorage", service_port);
+    let resp: GetStorageOkResponse = client
+        .post(addr)
+        .json(&arg)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    // Only the second chunk, the first one is a hole
+    let chunk = &resp.chunks[1];
+    let arg = WriteArg {
+        chunk: chunk.chunk,
+        offset: 0,
+        data: FileData(b"abc".to_vec()),
+    };
+    let addr = format!(
+        "http://localhost:{}/storage_write",
+        chunk.servers[0].server_port
+    );
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+
+    // Reported by the next heartbeat of the storage server
+    let mut written = stat(&client, create_file).await.unwrap();
+    for _ in 0..30 {
+        if written.size > 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        written = stat(&client, create_file).await.unwrap();
+    }
+    assert_eq!(written.size, common::CHUNK_SIZE + 3);
+    assert_eq!(written.created, created.created);
+    assert!(written.modified >= created.modified);
+    assert!(written.accessed >= written.modified);
+
+    log::info!("start to stat the missing one...");
+    let resp = stat(&client, "/test_stat/nope").await.unwrap_err();
+    assert_eq!(resp.exception_type, "FileNotFoundException");
 }
@@ -1,14 +1,28 @@
+use rocket::serde::msgpack;
 use tiny_dfs::common::{
     service::{
         CreateDirectoryArg, CreateFileArg, DeleteArg, GetStorageArg, GetStorageOkResponse,
         IsValidPathArg, IsValidPathResponse,
     },
-    storage::{base64_decode, base64_encode, ReadArg, ReadOkResponse, WriteArg},
+    storage::{
+        ChunkId, CopyArg, CopyOkResponse, CreateChunkArg, ReadArg, ReadOkResponse,
+        StreamWriteOkResponse, WriteArg,
+    },
+    wire::FileData,
     ErrResponse, OkResponse,
 };
 
 mod common;
 
+/// Create the chunk `chunk` of `chunk_size` bytes right on the storage
+/// server, unknown to the naming server
+async fn create_chunk(client: &reqwest::Client, chunk: ChunkId, chunk_size: u64) {
+    let arg = CreateChunkArg { chunk, chunk_size };
+    let addr = "http://localhost:44444/storage_create";
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+}
+
 #[rocket::tokio::test(flavor = "multi_thread")]
 async fn test_read_write() {
     let service_port = 11111;
@@ -33,6 +47,8 @@ async fn test_read_write() {
     log::info!("start to create dir...");
     let arg = CreateDirectoryArg {
         path: create_dir.to_string(),
+        replication: None,
+        owner: None,
     };
     let addr = format!("http://localhost:{}/create_directory", service_port);
     let _resp = client.post(addr).json(&arg).send().await.unwrap();
@@ -40,6 +56,8 @@ async fn test_read_write() {
     log::info!("start to create file...");
     let arg = CreateFileArg {
         path: create_file.to_string(),
+        replication: None,
+        owner: None,
     };
     let addr = format!("http://localhost:{}/create_file", service_port);
     let resp = client.post(addr).json(&arg).send().await.unwrap();
@@ -65,51 +83,491 @@ async fn test_read_write() {
     assert!(resp.success);
 
     log::info!("start to get storage...");
+    // Long enough to span several chunks
+    let data = "hello world!!! hello chunks!!! hello again!!!";
     let arg = GetStorageArg {
         path: create_file.to_string(),
+        offset: 0,
+        length: Some(data.len() as u64),
+        write: true,
     };
     let addr = format!("http://localhost:{}/getstorage", service_port);
     let resp = client.post(addr).json(&arg).send().await.unwrap();
     assert!(resp.status().is_success());
     let resp: GetStorageOkResponse = resp.json().await.unwrap();
-    assert!(resp.server_port == client_port);
-    let storage_port = resp.server_port;
+    assert_eq!(resp.chunk_size, common::CHUNK_SIZE);
+    let chunk_size = resp.chunk_size as usize;
+    assert_eq!(resp.chunks.len(), data.len().div_ceil(chunk_size));
+    for (idx, chunk) in resp.chunks.iter().enumerate() {
+        assert_eq!(chunk.offset, (idx * chunk_size) as u64);
+        assert_eq!(chunk.servers[0].server_port, client_port);
+    }
 
     log::info!("start to write file...");
-    let data = "hello world!!!";
-    let encoded = base64_encode(data);
-    let arg = WriteArg {
+    for (chunk, piece) in resp.chunks.iter().zip(data.as_bytes().chunks(chunk_size)) {
+        let arg = WriteArg {
+            chunk: chunk.chunk,
+            offset: 0,
+            data: FileData(piece.to_vec()),
+        };
+        let addr = format!(
+            "http://localhost:{}/storage_write",
+            chunk.servers[0].server_port
+        );
+        let resp = client.post(addr).json(&arg).send().await.unwrap();
+        if !resp.status().is_success() {
+            let resp: ErrResponse = resp.json().await.unwrap();
+            log::error!("resp err, exception info: {}", resp.exception_info);
+            panic!();
+        };
+        let resp: OkResponse = resp.json().await.unwrap();
+        assert!(resp.success);
+    }
+
+    log::info!("start to read file...");
+    // The chunks are already there, reading must not allocate new ones
+    let arg = GetStorageArg {
         path: create_file.to_string(),
         offset: 0,
-        data: encoded.clone(),
+        length: None,
+        write: false,
     };
-    let addr = format!("http://localhost:{}/storage_write", storage_port);
+    let addr = format!("http://localhost:{}/getstorage", service_port);
     let resp = client.post(addr).json(&arg).send().await.unwrap();
-    if !resp.status().is_success() {
+    assert!(resp.status().is_success());
+    let resp: GetStorageOkResponse = resp.json().await.unwrap();
+    assert_eq!(resp.chunks.len(), data.len().div_ceil(chunk_size));
+    let mut read = vec![];
+    for chunk in &resp.chunks {
+        let arg = ReadArg {
+            chunk: chunk.chunk,
+            offset: 0,
+            length: (data.len() - read.len()).min(chunk_size) as i32,
+        };
+        let addr = format!(
+            "http://localhost:{}/storage_read",
+            chunk.servers[0].server_port
+        );
+        let resp = client.post(addr).json(&arg).send().await.unwrap();
+        if !resp.status().is_success() {
+            let resp: ErrResponse = resp.json().await.unwrap();
+            log::error!("resp err, exception info: {}", resp.exception_info);
+            panic!();
+        };
+        let resp: ReadOkResponse = resp.json().await.unwrap();
+        read.extend(resp.data.0);
+    }
+    assert_eq!(String::from_utf8(read).unwrap(), data);
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_copy() {
+    let service_port = 11111;
+    let client_port = 33333;
+    let command_port = 44444;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let client = reqwest::Client::new();
+
+    log::warn!("test_copy: start...");
+    let data = "copy me!!!";
+    let arg = GetStorageArg {
+        path: new_files[0].to_string(),
+        offset: 0,
+        length: None,
+        write: false,
+    };
+    let addr = format!("http://localhost:{}/getstorage", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let resp: GetStorageOkResponse = resp.json().await.unwrap();
+    let chunk = resp.chunks[0].chunk;
+    let local_path = format!("/tmp/tiny-dfs/{}", chunk);
+    let arg = WriteArg {
+        chunk,
+        offset: 0,
+        data: FileData(data.into()),
+    };
+    let addr = format!("http://localhost:{}/storage_write", client_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+
+    log::info!("start to copy chunk...");
+    // Pull the chunk from the server itself
+    let arg = CopyArg {
+        chunk,
+        source_ip: "localhost".to_string(),
+        source_port: client_port,
+    };
+    let addr = format!("http://localhost:{}/storage_copy", command_port);
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let resp: CopyOkResponse = resp.json().await.unwrap();
+    assert_eq!(resp.bytes, data.len() as u64);
+    assert_eq!(std::fs::read_to_string(&local_path).unwrap(), data);
+
+    log::info!("start to copy a missing chunk...");
+    let arg = CopyArg {
+        chunk: u64::MAX,
+        source_ip: "localhost".to_string(),
+        source_port: client_port,
+    };
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    assert!(!resp.status().is_success());
+    let resp: ErrResponse = resp.json().await.unwrap();
+    assert_eq!(resp.exception_type, "FileNotFoundException");
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_checksum() {
+    let service_port = 11111;
+    let client_port = 33333;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let client = reqwest::Client::new();
+
+    log::warn!("test_checksum: start...");
+    let data = "check me!!!";
+    let arg = GetStorageArg {
+        path: new_files[1].to_string(),
+        offset: 0,
+        length: None,
+        write: false,
+    };
+    let addr = format!("http://localhost:{}/getstorage", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let resp: GetStorageOkResponse = resp.json().await.unwrap();
+    let chunk = resp.chunks[0].chunk;
+
+    log::info!("start to write chunk...");
+    let arg = WriteArg {
+        chunk,
+        offset: 0,
+        data: FileData(data.into()),
+    };
+    let addr = format!("http://localhost:{}/storage_write", client_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+
+    log::info!("start to read the intact chunk...");
+    let arg = ReadArg {
+        chunk,
+        offset: 2,
+        length: 5,
+    };
+    let addr = format!("http://localhost:{}/storage_read", client_port);
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let resp: ReadOkResponse = resp.json().await.unwrap();
+    assert_eq!(resp.data.0, &data.as_bytes()[2..7]);
+
+    log::info!("start to read the corrupted chunk...");
+    // Flip the data behind the back of the storage server
+    std::fs::write(format!("/tmp/tiny-dfs/{}", chunk), "CHECK ME!!!").unwrap();
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    assert!(!resp.status().is_success());
+    let resp: ErrResponse = resp.json().await.unwrap();
+    assert_eq!(resp.exception_type, "ChecksumException");
+
+    log::info!("start to write into the corrupted chunk...");
+    // The bytes left around the write would get checksums they don't match
+    let write = WriteArg {
+        chunk,
+        offset: 2,
+        data: FileData(b"eck".to_vec()),
+    };
+    let write_addr = format!("http://localhost:{}/storage_write", client_port);
+    let resp = client.post(&write_addr).json(&write).send().await.unwrap();
+    assert!(!resp.status().is_success());
+    let resp: ErrResponse = resp.json().await.unwrap();
+    assert_eq!(resp.exception_type, "ChecksumException");
+
+    log::info!("start to rewrite the corrupted chunk...");
+    // Fresh checksums for the others using the file
+    let write = WriteArg {
+        chunk,
+        offset: 0,
+        data: FileData(data.into()),
+    };
+    let resp = client.post(write_addr).json(&write).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let resp = client.post(&addr).json(&arg).send().await.unwrap();
+    assert!(resp.status().is_success());
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_stream() {
+    let client_port = 33333;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let client = reqwest::Client::new();
+
+    log::warn!("test_stream: start...");
+    // Larger than the chunks of the naming server
+    let chunk = u64::MAX - 1;
+    create_chunk(&client, chunk, 256).await;
+    let addr = format!("http://localhost:{}/storage_chunk/{}", client_port, chunk);
+
+    log::info!("start to write raw bytes...");
+    let data: Vec<u8> = (0..=255).collect();
+    let resp = client
+        .put(format!("{}?offset=0", addr))
+        .body(data[..200].to_vec())
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let resp: StreamWriteOkResponse = resp.json().await.unwrap();
+    assert_eq!(resp.bytes, 200);
+    let resp = client
+        .put(format!("{}?offset=200", addr))
+        .body(data[200..].to_vec())
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+
+    log::info!("start to read raw bytes...");
+    let resp = client.get(&addr).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+    assert_eq!(resp.bytes().await.unwrap(), data);
+    let resp = client
+        .get(format!("{}?offset=10&length=5", addr))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.bytes().await.unwrap(), data[10..15]);
+
+    log::info!("start to read ranges...");
+    for (range, expected, content_range) in [
+        ("bytes=2-5", &data[2..6], "bytes 2-5/256"),
+        ("bytes=250-", &data[250..], "bytes 250-255/256"),
+        ("bytes=-3", &data[253..], "bytes 253-255/256"),
+        ("bytes=100-999", &data[100..], "bytes 100-255/256"),
+    ] {
+        let resp = client
+            .get(&addr)
+            .header("Range", range)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers()["Content-Range"], content_range);
+        assert_eq!(resp.bytes().await.unwrap(), expected);
+    }
+    let resp = client
+        .get(&addr)
+        .header("Range", "bytes=300-")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::RANGE_NOT_SATISFIABLE);
+    // Other units are ignored
+    let resp = client
+        .get(&addr)
+        .header("Range", "items=2-5")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+    assert_eq!(resp.bytes().await.unwrap(), data);
+
+    log::info!("start to write past the end of the chunk...");
+    for (offset, length) in [(200, 57), (256, 1), (300, 0)] {
+        let resp = client
+            .put(format!("{}?offset={}", addr, offset))
+            .body(vec![0; length])
+            .send()
+            .await
+            .unwrap();
+        assert!(!resp.status().is_success());
         let resp: ErrResponse = resp.json().await.unwrap();
-        log::error!("resp err, exception info: {}", resp.exception_info);
-        panic!();
+        assert_eq!(resp.exception_type, "IndexOutOfBoundsException");
+    }
+    let arg = WriteArg {
+        chunk,
+        offset: 250,
+        data: FileData(vec![0; 7]),
+    };
+    let write_addr = format!("http://localhost:{}/storage_write", client_port);
+    let resp = client.post(write_addr).json(&arg).send().await.unwrap();
+    assert!(!resp.status().is_success());
+    let resp: ErrResponse = resp.json().await.unwrap();
+    assert_eq!(resp.exception_type, "IndexOutOfBoundsException");
+    // Left untouched
+    let resp = client.get(&addr).send().await.unwrap();
+    assert_eq!(resp.bytes().await.unwrap(), data);
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_msgpack() {
+    let service_port = 11111;
+    let client_port = 33333;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let client = reqwest::Client::new();
+
+    log::warn!("test_msgpack: start...");
+    let create_file = "/test_msgpack";
+    let arg = CreateFileArg {
+        path: create_file.to_string(),
+        replication: None,
+        owner: None,
     };
+    let addr = format!("http://localhost:{}/create_file", service_port);
+    let resp = client
+        .post(addr)
+        .header("Content-Type", "application/msgpack")
+        .body(msgpack::to_vec(&arg).unwrap())
+        .send()
+        .await
+        .unwrap();
     assert!(resp.status().is_success());
-    let resp: OkResponse = resp.json().await.unwrap();
+    assert_eq!(resp.headers()["Content-Type"], "application/msgpack");
+    let resp: OkResponse = msgpack::from_slice(&resp.bytes().await.unwrap()).unwrap();
     assert!(resp.success);
 
-    log::info!("start to read file...");
-    let arg = ReadArg {
+    log::info!("start to get storage...");
+    // JSON request, MessagePack response
+    let arg = GetStorageArg {
         path: create_file.to_string(),
         offset: 0,
+        length: None,
+        write: false,
+    };
+    let addr = format!("http://localhost:{}/getstorage", service_port);
+    let resp = client
+        .post(addr)
+        .header("Accept", "application/msgpack")
+        .json(&arg)
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let resp: GetStorageOkResponse = msgpack::from_slice(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(resp.chunks.len(), 1);
+
+    log::info!("start to write and read raw bytes...");
+    // Larger than the chunks of the naming server
+    let chunk = u64::MAX - 2;
+    create_chunk(&client, chunk, 256).await;
+    let data: Vec<u8> = (0..=255).collect();
+    let arg = WriteArg {
+        chunk,
+        offset: 0,
+        data: FileData(data.clone()),
+    };
+    let body = msgpack::to_vec(&arg).unwrap();
+    // Raw bytes, not base64
+    assert!(body.len() < data.len() + 64);
+    let addr = format!("http://localhost:{}/storage_write", client_port);
+    let resp = client
+        .post(addr)
+        .header("Content-Type", "application/msgpack")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+
+    let arg = ReadArg {
+        chunk,
+        offset: 0,
         length: data.len() as i32,
     };
-    let addr = format!("http://localhost:{}/storage_read", storage_port);
+    let addr = format!("http://localhost:{}/storage_read", client_port);
+    let resp = client
+        .post(&addr)
+        .header("Content-Type", "application/msgpack")
+        .body(msgpack::to_vec(&arg).unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let resp: ReadOkResponse = msgpack::from_slice(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(resp.data.0, data);
+
+    log::info!("start to read past the end...");
+    let arg = ReadArg {
+        chunk,
+        offset: 1000,
+        length: 1,
+    };
+    let resp = client
+        .post(&addr)
+        .header("Content-Type", "application/msgpack")
+        .body(msgpack::to_vec(&arg).unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert!(!resp.status().is_success());
+    let resp: ErrResponse = msgpack::from_slice(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(resp.exception_type, "IndexOutOfBoundsException");
+}
+
+#[rocket::tokio::test(flavor = "multi_thread")]
+async fn test_concurrent_write() {
+    let service_port = 11111;
+    let new_files = vec!["/test111", "/test222"];
+
+    common::init(&new_files).await;
+    let client = reqwest::Client::new();
+
+    log::warn!("test_concurrent_write: start...");
+    let create_file = "/test_concurrent_write";
+    let arg = CreateFileArg {
+        path: create_file.to_string(),
+        replication: None,
+        owner: None,
+    };
+    let addr = format!("http://localhost:{}/create_file", service_port);
     let resp = client.post(addr).json(&arg).send().await.unwrap();
-    if !resp.status().is_success() {
-        let resp: ErrResponse = resp.json().await.unwrap();
-        log::error!("resp err, exception info: {}", resp.exception_info);
-        panic!();
+    assert!(resp.status().is_success());
+
+    let arg = GetStorageArg {
+        path: create_file.to_string(),
+        offset: 0,
+        length: None,
+        write: false,
     };
+    let addr = format!("http://localhost:{}/getstorage", service_port);
+    let resp = client.post(addr).json(&arg).send().await.unwrap();
+    let resp: GetStorageOkResponse = resp.json().await.unwrap();
+    let chunk = &resp.chunks[0];
+    let addr = format!(
+        "http://localhost:{}/storage_chunk/{}",
+        chunk.servers[0].server_port, chunk.chunk
+    );
+
+    log::info!("start to write and read at once...");
+    // Every read in between checks the data against the checksums
+    let length = common::CHUNK_SIZE as usize;
+    for round in 0..20u8 {
+        let mut tasks = Vec::new();
+        for i in 0..8u8 {
+            let write = client.put(&addr).body(vec![round * 8 + i; length]);
+            tasks.push(tokio::spawn(
+                async move { write.send().await.unwrap().status() },
+            ));
+            let read = client.get(&addr);
+            tasks.push(tokio::spawn(
+                async move { read.send().await.unwrap().status() },
+            ));
+        }
+        for task in tasks {
+            assert!(task.await.unwrap().is_success());
+        }
+    }
+
+    log::info!("start to read the last write...");
+    let resp = client.get(&addr).send().await.unwrap();
     assert!(resp.status().is_success());
-    let resp: ReadOkResponse = resp.json().await.unwrap();
-    assert!(resp.data.eq(&encoded));
-    let decoded = base64_decode(resp.data).unwrap();
-    log::info!("read data {:?}", String::from_utf8(decoded).unwrap());
+    let data = resp.bytes().await.unwrap();
+    assert_eq!(data.len(), length);
+    assert!(data.iter().all(|byte| *byte == data[0]));
 }
@@ -1,6 +1,5 @@
 #!/bin/bash
 
 # Run from root dir
-export RUST_LOG=debug
 cd core
-cargo run naming 11111 22222
\ No newline at end of file
+cargo run naming --service-port 11111 --registration-port 22222 --meta-dir /tmp/tiny-dfs-naming --log-level debug
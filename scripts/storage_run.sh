@@ -1,6 +1,5 @@
 #!/bin/bash
 
 # Run from root dir
-export RUST_LOG=debug
 cd core
-cargo run storage 33333 44444 22222 /tmp/tiny-dfs
\ No newline at end of file
+cargo run storage --client-port 33333 --command-port 44444 --naming-address localhost:22222 --data-dir /tmp/tiny-dfs --log-level debug